@@ -0,0 +1,26 @@
+// Stretches the compute texture over the whole surface, which may be larger when rendering at a
+// reduced resolution scale.
+@group(0) @binding(0)
+var color_buffer: texture_2d<f32>;
+@group(0) @binding(1)
+var color_sampler: sampler;
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen, drawn without any vertex buffer.
+@vertex
+fn main_vertex(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn main_fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(color_buffer, color_sampler, in.uv);
+}
@@ -0,0 +1,124 @@
+// Tileable blue noise made with void and cluster (Ulichney 1993): every pixel of the tile gets a
+// rank, and the pixels below any threshold of the ranks are spread evenly, across the edges too.
+
+// Width of the Gaussian the energy of a point spreads over, in pixels.
+const SIGMA: f32 = 1.5;
+
+#[derive(Clone)]
+struct Pattern {
+    size: usize,
+    points: Vec<bool>,
+    // Sum of the Gaussians of every point, seen from each pixel.
+    energy: Vec<f32>,
+}
+
+impl Pattern {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            points: vec![false; size * size],
+            energy: vec![0.0; size * size],
+        }
+    }
+
+    fn toggle(&mut self, pixel: usize, kernel: &[f32]) {
+        self.points[pixel] = !self.points[pixel];
+        let sign = if self.points[pixel] { 1.0 } else { -1.0 };
+        let size = self.size;
+        let (px, py) = (pixel % size, pixel / size);
+        for (y, row) in self.energy.chunks_exact_mut(size).enumerate() {
+            let dy = (y + size - py) % size;
+            let kernel_row = &kernel[dy * size..(dy + 1) * size];
+            // The kernel row starts at px and wraps around the tile.
+            let (before, after) = row.split_at_mut(px);
+            let (wrapped, start) = kernel_row.split_at(size - px);
+            for (energy, k) in after
+                .iter_mut()
+                .zip(wrapped)
+                .chain(before.iter_mut().zip(start))
+            {
+                *energy += sign * k;
+            }
+        }
+    }
+
+    // The point with the most neighbours.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    // The empty pixel furthest from every point.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, point: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (i, &energy) in self.energy.iter().enumerate() {
+            if self.points[i] == point && best.is_none_or(|b: usize| better(energy, self.energy[b]))
+            {
+                best = Some(i);
+            }
+        }
+        best.expect("the pattern is neither empty nor full")
+    }
+}
+
+// Values in (0, 1), row by row. The same size and seed give the same tile.
+pub(crate) fn generate(size: usize, seed: u64) -> Vec<f32> {
+    let count = size * size;
+    // Gaussian of the distance on the torus, so that the tile wraps around.
+    let kernel: Vec<f32> = (0..count)
+        .map(|i| {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let (dx, dy) = (wrap(i % size), wrap(i / size));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+
+    // Random points on a tenth of the pixels, then moved from the clusters to the voids until
+    // that changes nothing.
+    let mut state = seed;
+    let mut initial = Pattern::new(size);
+    let ones = (count / 10).max(1);
+    while initial.points.iter().filter(|&&point| point).count() < ones {
+        // SplitMix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        let pixel = ((z ^ (z >> 31)) % count as u64) as usize;
+        if !initial.points[pixel] {
+            initial.toggle(pixel, &kernel);
+        }
+    }
+    for _ in 0..count {
+        let cluster = initial.tightest_cluster();
+        initial.toggle(cluster, &kernel);
+        let void = initial.largest_void();
+        initial.toggle(void, &kernel);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; count];
+    // The initial points are ranked by removing them, tightest first.
+    let mut pattern = initial.clone();
+    for rank in (0..ones).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster, &kernel);
+        ranks[cluster] = rank;
+    }
+    // Then the other pixels by filling the largest void each time.
+    let mut pattern = initial;
+    for rank in ones..count {
+        let void = pattern.largest_void();
+        pattern.toggle(void, &kernel);
+        ranks[void] = rank;
+    }
+    ranks
+        .into_iter()
+        .map(|rank| (rank as f32 + 0.5) / count as f32)
+        .collect()
+}
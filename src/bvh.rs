@@ -0,0 +1,132 @@
+use crate::math::*;
+
+use bytemuck::Zeroable;
+
+// Leaves hold at most this many spheres.
+const MAX_LEAF_SIZE: usize = 4;
+
+// Flattened node as read by the shader.
+// A leaf (count > 0) covers the spheres first..first + count, an inner node (count == 0)
+// has its children at left and left + 1.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BvhNode {
+    min: [f32; 3],
+    left_or_first: u32,
+    max: [f32; 3],
+    count: u32,
+}
+
+struct Bounds {
+    min: [f32; 3],
+    max: [f32; 3],
+}
+
+impl Bounds {
+    fn empty() -> Self {
+        Self {
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+        }
+    }
+
+    fn grow(&mut self, min: [f32; 3], max: [f32; 3]) {
+        for axis in 0..3 {
+            self.min[axis] = self.min[axis].min(min[axis]);
+            self.max[axis] = self.max[axis].max(max[axis]);
+        }
+    }
+
+    fn longest_axis(&self) -> usize {
+        let extent = [0, 1, 2].map(|axis| self.max[axis] - self.min[axis]);
+        if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+// Bounds of the whole sweep of the sphere while the shutter is open.
+fn sphere_bounds(sphere: &Vec4, velocity: &Vec4) -> Bounds {
+    let radius = sphere[3].abs();
+    let mut bounds = Bounds::empty();
+    for center in [*sphere, add(*sphere, *velocity)] {
+        bounds.grow(
+            [center[0] - radius, center[1] - radius, center[2] - radius],
+            [center[0] + radius, center[1] + radius, center[2] + radius],
+        );
+    }
+    bounds
+}
+
+// Build the hierarchy over the spheres, reordering them so that each leaf covers a contiguous range.
+// Returns the permutation applied: the i-th sphere after the call was at order[i] before.
+pub fn build(spheres: &[Vec4], velocities: &[Vec4]) -> (Vec<BvhNode>, Vec<usize>) {
+    let mut order: Vec<usize> = (0..spheres.len()).collect();
+    let mut nodes = Vec::new();
+    if !spheres.is_empty() {
+        nodes.push(BvhNode::zeroed());
+        subdivide(spheres, velocities, &mut order, 0, 0, &mut nodes);
+    }
+    (nodes, order)
+}
+
+// Move a hierarchy built by build to start at nodes[node_offset] and to cover the spheres from
+// first on, so that several hierarchies share one buffer.
+pub fn offset(nodes: &mut [BvhNode], node_offset: u32, first: u32) {
+    for node in nodes {
+        node.left_or_first += if node.count > 0 { first } else { node_offset };
+    }
+}
+
+// Fill nodes[node] for the spheres in order, which starts at index first, splitting at the median
+// of the centroids along the longest axis.
+fn subdivide(
+    spheres: &[Vec4],
+    velocities: &[Vec4],
+    order: &mut [usize],
+    first: usize,
+    node: usize,
+    nodes: &mut Vec<BvhNode>,
+) {
+    let mut bounds = Bounds::empty();
+    let mut centroids = Bounds::empty();
+    for &i in order.iter() {
+        let sweep = sphere_bounds(&spheres[i], &velocities[i]);
+        bounds.grow(sweep.min, sweep.max);
+        let center = [spheres[i][0], spheres[i][1], spheres[i][2]];
+        centroids.grow(center, center);
+    }
+    nodes[node].min = bounds.min;
+    nodes[node].max = bounds.max;
+
+    if order.len() <= MAX_LEAF_SIZE {
+        nodes[node].left_or_first = first as u32;
+        nodes[node].count = order.len() as u32;
+        return;
+    }
+
+    let axis = centroids.longest_axis();
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| spheres[a][axis].total_cmp(&spheres[b][axis]));
+
+    let left = nodes.len();
+    nodes.push(BvhNode::zeroed());
+    nodes.push(BvhNode::zeroed());
+    nodes[node].left_or_first = left as u32;
+    nodes[node].count = 0;
+
+    let (left_order, right_order) = order.split_at_mut(mid);
+    subdivide(spheres, velocities, left_order, first, left, nodes);
+    subdivide(
+        spheres,
+        velocities,
+        right_order,
+        first + mid,
+        left + 1,
+        nodes,
+    );
+}
@@ -0,0 +1,250 @@
+use gpu_raytracing::state::AdapterChoice;
+use gpu_raytracing::world_data::OutputColorspace;
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "usage: gpu-raytracing [SCENE | --random SEED] \
+    [--seed N] [--gamma G | --colorspace srgb|rec709|linear] [--firefly-clamp L] [--gpu NAME|high|low] \
+    [--width W --height H] [--fullscreen] [--passes N] [--frames N] [--tile-size N] [--target-fps FPS | --headless WIDTHxHEIGHT --out FILE [--samples N] [--gbuffer] \
+    [--turntable FRAMES [--orbit-radius R]]]";
+
+pub struct Args {
+    pub scene: PathBuf,
+    // Generate the random sphere field instead of loading the scene.
+    pub random_seed: Option<u64>,
+    // Adapt the samples per pixel to keep this framerate while the camera moves.
+    pub target_fps: Option<u32>,
+    // Compute dispatches per presented frame while the camera is still.
+    pub passes: u32,
+    // Stop tracing after this many frames, the window stays open on the result.
+    pub frames: Option<u32>,
+    // Base of the shader's random numbers, renders with the same seed are identical.
+    pub seed: Option<u32>,
+    // Encode the output with this gamma instead of sRGB.
+    pub gamma: Option<f32>,
+    pub colorspace: OutputColorspace,
+    // Scale down samples brighter than this luminance.
+    pub firefly_clamp: Option<f32>,
+    // high and low select by power preference, anything else by adapter name.
+    pub adapter: AdapterChoice,
+    // Trace each frame in square tiles of this side, submitted separately.
+    pub tile_size: Option<u32>,
+    // Initial inner size of the window, left to the platform when None.
+    pub window_size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    // Render a single image without opening a window.
+    pub headless: Option<HeadlessArgs>,
+}
+
+pub struct HeadlessArgs {
+    pub width: u32,
+    pub height: u32,
+    // Rounded up to a whole number of frames.
+    pub samples: u32,
+    pub out: PathBuf,
+    // Also write the color, albedo and normal images as PFM files for a denoiser.
+    pub gbuffer: bool,
+    // Write a sequence of images into the out directory instead of a single one.
+    pub turntable: Option<TurntableArgs>,
+}
+
+pub struct TurntableArgs {
+    // Images in a full turn around lookat.
+    pub frames: u32,
+    // Distance from lookat, defaults to the one of the scene's camera.
+    pub radius: Option<f32>,
+}
+
+fn value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{flag} expects a value"))
+}
+
+fn parse_number<T: FromStr>(value: &str, flag: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{flag} expects a positive number, got {value:?}"))
+}
+
+fn parse_size(value: &str, flag: &str) -> Result<(u32, u32), String> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| format!("{flag} expects WIDTHxHEIGHT, got {value:?}"))?;
+    let size = (parse_number(width, flag)?, parse_number(height, flag)?);
+    if size.0 == 0 || size.1 == 0 {
+        return Err(format!("{flag} expects a non-zero size"));
+    }
+    Ok(size)
+}
+
+pub fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut scene = None;
+    let mut size = None;
+    let mut samples = None;
+    let mut out = None;
+    let mut random_seed = None;
+    let mut target_fps = None;
+    let mut passes = None;
+    let mut frames = None;
+    let mut tile_size = None;
+    let mut seed = None;
+    let mut gamma = None;
+    let mut colorspace = None;
+    let mut firefly_clamp = None;
+    let mut gbuffer = false;
+    let mut adapter = AdapterChoice::Default;
+    let mut width = None;
+    let mut height = None;
+    let mut fullscreen = false;
+    let mut turntable_frames = None;
+    let mut orbit_radius = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--headless" => size = Some(parse_size(&value(&mut args, &arg)?, &arg)?),
+            "--samples" => samples = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--out" => out = Some(PathBuf::from(value(&mut args, &arg)?)),
+            "--random" => random_seed = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--target-fps" => target_fps = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--passes" => passes = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--frames" => frames = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--tile-size" => tile_size = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--seed" => seed = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--gamma" => gamma = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--colorspace" => {
+                colorspace = Some(match value(&mut args, &arg)?.as_str() {
+                    "srgb" => OutputColorspace::Srgb,
+                    "rec709" => OutputColorspace::Rec709,
+                    "linear" => OutputColorspace::Linear,
+                    other => {
+                        return Err(format!(
+                            "--colorspace expects srgb, rec709 or linear, got {other:?}"
+                        ))
+                    }
+                })
+            }
+            "--firefly-clamp" => {
+                firefly_clamp = Some(parse_number(&value(&mut args, &arg)?, &arg)?)
+            }
+            "--gbuffer" => gbuffer = true,
+            "--width" => width = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--height" => height = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--fullscreen" => fullscreen = true,
+            "--turntable" => turntable_frames = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--orbit-radius" => orbit_radius = Some(parse_number(&value(&mut args, &arg)?, &arg)?),
+            "--gpu" => {
+                adapter = match value(&mut args, &arg)?.as_str() {
+                    "high" => AdapterChoice::HighPerformance,
+                    "low" => AdapterChoice::LowPower,
+                    name => AdapterChoice::Name(name.to_string()),
+                }
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            _ if scene.is_some() => return Err(format!("unexpected argument {arg}")),
+            _ => scene = Some(PathBuf::from(arg)),
+        }
+    }
+
+    if turntable_frames == Some(0) {
+        return Err("--turntable needs at least 1 frame".to_string());
+    }
+    if orbit_radius.is_some_and(|radius: f32| radius.is_nan() || radius <= 0.0) {
+        return Err("--orbit-radius must be positive".to_string());
+    }
+    let turntable = match (turntable_frames, orbit_radius) {
+        (Some(frames), radius) => Some(TurntableArgs { frames, radius }),
+        (None, Some(_)) => return Err("--orbit-radius only applies to --turntable".to_string()),
+        (None, None) => None,
+    };
+    if turntable.is_some() && gbuffer {
+        return Err("--gbuffer does not apply to --turntable".to_string());
+    }
+
+    let headless = match (size, out) {
+        (Some((width, height)), Some(out)) => Some(HeadlessArgs {
+            width,
+            height,
+            samples: samples.unwrap_or(100),
+            out,
+            gbuffer,
+            turntable,
+        }),
+        (Some(_), None) => return Err("--headless requires --out".to_string()),
+        (None, _) if samples.is_some() => {
+            return Err("--samples only applies to --headless".to_string())
+        }
+        (None, Some(_)) => return Err("--out only applies to --headless".to_string()),
+        (None, None) if gbuffer => return Err("--gbuffer only applies to --headless".to_string()),
+        (None, None) if turntable.is_some() => {
+            return Err("--turntable only applies to --headless".to_string())
+        }
+        (None, None) => None,
+    };
+
+    if gamma.is_some_and(|gamma: f32| gamma.is_nan() || gamma <= 0.0) {
+        return Err("--gamma must be positive".to_string());
+    }
+    // Both choose the encoding of the output.
+    if gamma.is_some() && colorspace.is_some() {
+        return Err("--gamma replaces the colorspace, pass only one of them".to_string());
+    }
+    if firefly_clamp.is_some_and(|luminance: f32| luminance.is_nan() || luminance <= 0.0) {
+        return Err("--firefly-clamp must be positive".to_string());
+    }
+    if target_fps == Some(0) {
+        return Err("--target-fps must be at least 1".to_string());
+    }
+    if headless.is_some() && target_fps.is_some() {
+        return Err("--target-fps does not apply to --headless".to_string());
+    }
+    if passes == Some(0) {
+        return Err("--passes must be at least 1".to_string());
+    }
+    // Headless renders are never presented until the end anyway.
+    if headless.is_some() && passes.is_some() {
+        return Err("--passes does not apply to --headless".to_string());
+    }
+    if frames == Some(0) {
+        return Err("--frames must be at least 1".to_string());
+    }
+    if headless.is_some() && frames.is_some() {
+        return Err("--frames does not apply to --headless, use --samples".to_string());
+    }
+
+    if tile_size == Some(0) {
+        return Err("--tile-size must be at least 1".to_string());
+    }
+
+    let window_size = match (width, height) {
+        (Some(0), _) | (_, Some(0)) => {
+            return Err("--width and --height must be at least 1".to_string())
+        }
+        (Some(width), Some(height)) => Some((width, height)),
+        (None, None) => None,
+        _ => return Err("--width and --height must be given together".to_string()),
+    };
+    if headless.is_some() && (window_size.is_some() || fullscreen) {
+        return Err("the window options do not apply to --headless".to_string());
+    }
+
+    if scene.is_some() && random_seed.is_some() {
+        return Err("--random replaces the scene, pass only one of them".to_string());
+    }
+
+    Ok(Args {
+        scene: scene.unwrap_or_else(|| PathBuf::from("scenes/default.ron")),
+        random_seed,
+        target_fps,
+        passes: passes.unwrap_or(1),
+        frames,
+        seed,
+        gamma,
+        colorspace: colorspace.unwrap_or_default(),
+        firefly_clamp,
+        adapter,
+        tile_size,
+        window_size,
+        fullscreen,
+        headless,
+    })
+}
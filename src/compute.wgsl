@@ -4,26 +4,134 @@
 @group(0) @binding(0)
 var color_buffer: texture_storage_2d<bgra8unorm, write>;
 @group(0) @binding(1)
-var<uniform> world_data: WorldData;
+var<uniform> world_data: WorldUniform;
+// A sphere is encoded as a vec4: first three components are center, last is radius.
+@group(0) @binding(2)
+var<storage, read> spheres: array<vec4<f32>>;
+@group(0) @binding(3)
+var<storage, read> materials: array<Material>;
+// Sums of every frame since the scene last changed: the color in xyz, the squared luminance in w.
+@group(0) @binding(4)
+var accumulation_buffer: texture_storage_2d<rgba32float, read_write>;
+@group(0) @binding(5)
+var<storage, read> triangles: array<Triangle>;
+@group(0) @binding(6)
+var<storage, read> boxes: array<AxisAlignedBox>;
+// Hierarchy over the spheres, the root is the first node.
+@group(0) @binding(7)
+var<storage, read> bvh_nodes: array<BvhNode>;
+// Equirectangular map seen when nothing is hit, a 1x1 placeholder when the scene has none.
+@group(0) @binding(8)
+var environment_map: texture_2d<f32>;
+@group(0) @binding(9)
+var environment_sampler: sampler;
+@group(0) @binding(10)
+var<storage, read> lights: array<PointLight>;
+// Indices of the emissive spheres, sampled directly at Lambertian hits.
+@group(0) @binding(11)
+var<storage, read> emissive_spheres: array<u32>;
+// Displacement of each sphere while the shutter is open, zero for static ones.
+@group(0) @binding(12)
+var<storage, read> sphere_velocities: array<vec4<f32>>;
+@group(0) @binding(13)
+var<storage, read> quads: array<Quad>;
+@group(0) @binding(14)
+var<storage, read> cylinders: array<Cylinder>;
+// Distance to the first hit through the center of the pixel, -1 for the background.
+@group(0) @binding(15)
+var depth_buffer: texture_storage_2d<r32float, write>;
+// Albedo and world space normal at the same hit, zero for the background. Inputs of a denoiser.
+@group(0) @binding(16)
+var albedo_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(17)
+var normal_buffer: texture_storage_2d<rgba32float, write>;
+@group(0) @binding(18)
+var<storage, read> planes: array<Plane>;
+@group(0) @binding(19)
+var<storage, read> transformed_spheres: array<TransformedSphere>;
+// Center and radius of each instanced sphere, grouped by material.
+@group(0) @binding(20)
+var<storage, read> instances: array<vec4<f32>>;
+@group(0) @binding(21)
+var<storage, read> instance_groups: array<InstanceGroup>;
+// Tileable, every value in (0, 1) appears once. See blue_noise.rs.
+@group(0) @binding(22)
+var blue_noise_tile: texture_2d<f32>;
+// Images of the textured materials, all resized to the same square. A 1x1 white layer when the
+// scene has none.
+@group(0) @binding(23)
+var image_textures: texture_2d_array<f32>;
+@group(0) @binding(24)
+var image_sampler: sampler;
+// Pixels traced this frame with adaptive sampling on, cleared before every dispatch.
+@group(0) @binding(25)
+var<storage, read_write> active_pixels: atomic<u32>;
+// Top left pixel of the tile traced by this dispatch, zero without tiles.
+@group(0) @binding(26)
+var<uniform> tile_origin: vec2<u32>;
+// Sum of the pixel filter weights behind the accumulation_buffer colors.
+@group(0) @binding(27)
+var weight_buffer: texture_storage_2d<r32float, read_write>;
+// One hierarchy per instance group, starting at InstanceGroup.root.
+@group(0) @binding(28)
+var<storage, read> instance_bvh_nodes: array<BvhNode>;
+
+// Moment in [0, shutter) the current sample is taken at, shared by every bounce of the path.
+var<private> shutter_time: f32;
+// Set by pixel_color for each sample when blue noise is on, random_range_f32 then reads the first
+// blue_noise_dimensions numbers from the tile.
+var<private> noise_pixel: vec2<u32>;
+var<private> noise_scramble: u32;
+var<private> noise_dimension: u32 = blue_noise_dimensions;
+// Set by pbr_reflect, whether the bounce followed the diffuse lobe, which the lights are sampled for.
+var<private> took_diffuse_lobe: bool;
+// Bounding box and sphere tests done by hit_spheres, shown by the BVH cost debug view.
+var<private> traversal_tests: u32;
 
 const pi = radians(180.0);
+// HitInfo.sphere_idx of anything that is not a sphere.
+const no_sphere = 0xffffffffu;
+// HitInfo.quad_idx of anything that is not a quad.
+const no_quad = 0xffffffffu;
+// Enough for the pixel offset, the shutter time, the lens and the first bounce.
+const blue_noise_dimensions = 8u;
+// Number of tests shown as pure red by the BVH cost debug view.
+const heatmap_max_tests = 128.0;
+// Enough for a median split BVH over far more spheres than fit in memory.
+const bvh_stack_size = 32u;
 
 struct Material {
     color: vec4<f32>,
-    _padding: vec2<f32>,
-    // 0 is Lambertian, 1 is Metallic
+    // second color of the checker pattern
+    checker_color: vec4<f32>,
+    // Light given off by any material on top of what it scatters, rgb and strength in w.
+    emission: vec4<f32>,
+    // specific to Dielectric, fraction of each channel lost per unit of distance inside, w unused
+    absorption: vec4<f32>,
+    // 0 is Lambertian, 1 is Metallic, 2 is Dielectric, 3 is Emissive, 4 is BrushedMetal, 5 is Pbr
     material_type: u32,
-    // specific to Metallic
+    // specific to Metallic, holds the intensity for Emissive and the metalness for Pbr
     fuzz: f32,
+    // specific to Dielectric
+    refraction_index: f32,
+    // 0 is Solid, 1 is Checker, 2 is Image
+    texture_type: u32,
+    // size of a checker cell in world units
+    checker_scale: f32,
+    // specific to BrushedMetal and Pbr, along the tangent and the bitangent
+    roughness_u: f32,
+    roughness_v: f32,
+    // layer of image_textures for the Image texture type
+    texture_index: u32,
 }
 
-struct WorldData {
+struct WorldUniform {
     window_width: u32,
     window_height: u32,
     sample_per_pixels: u32,
     max_depth: u32,
     frame: u32,
-    scene_changed: u32,
+    frames_since_change: u32,
     vfov: f32,
     sphere_count: u32,
     lookfrom: vec4<f32>,
@@ -34,9 +142,158 @@ struct WorldData {
     pix_delta_x: vec4<f32>,
     pix_delta_y: vec4<f32>,
     pixel_up_left: vec4<f32>,
-    // A sphere is encoded as a vec4: first three components are center, last is radius.
-    spheres: array<vec4<f32>, 128>,
-    materials: array<Material, 128>
+    // Lens basis scaled by the aperture radius, the primary rays start on this disk.
+    defocus_disk_u: vec4<f32>,
+    defocus_disk_v: vec4<f32>,
+    // Colors of the background gradient, picked from the ray direction when nothing is hit.
+    sky_top: vec4<f32>,
+    sky_bottom: vec4<f32>,
+    // Flat background of the solid color mode.
+    solid_color: vec4<f32>,
+    // Diameter of the lens, 0 means a perfect pinhole camera.
+    aperture: f32,
+    focus_dist: f32,
+    triangle_count: u32,
+    // Number of bounces before paths start being randomly terminated.
+    rr_min_bounces: u32,
+    // 0 is None, 1 is Reinhard, 2 is ACES
+    tone_map: u32,
+    box_count: u32,
+    // 0 when there is no BVH, every sphere is tested then.
+    bvh_node_count: u32,
+    // 0 shoots every sample through the center of the pixel.
+    antialiasing: u32,
+    // Seen when nothing is hit, 0 is Gradient, 1 is SolidColor, 2 is Environment
+    background_mode: u32,
+    light_count: u32,
+    emissive_sphere_count: u32,
+    // 0 is Perspective, 1 is Orthographic
+    camera_type: u32,
+    // Height of the viewport in world units for the orthographic camera.
+    ortho_scale: f32,
+    // Fraction of the frame interval during which the shutter is open.
+    shutter: f32,
+    quad_count: u32,
+    // Radiance mapped to pure white by Reinhard, anything brighter clips.
+    white_point: f32,
+    // Mixed into every random sequence, the same seed and frame give the same image.
+    seed: u32,
+    cylinder_count: u32,
+    // 0 is Off, 1 is Normals, 2 is Depth, 3 is AlbedoOnly, 4 is BvhCost, 5 is AmbientOcclusion
+    debug_mode: u32,
+    // Exponent of the encoding applied here, 0 leaves it to the sRGB surface view.
+    gamma: f32,
+    // 0 scatters Lambertian hits toward the normal plus a random unit vector instead.
+    cosine_sampling: u32,
+    plane_count: u32,
+    // Luminance above which a sample is scaled down, 0 keeps every sample as is.
+    firefly_clamp: f32,
+    // 0 is Box, 1 is Tent, 2 is Gaussian
+    pixel_filter: u32,
+    transformed_sphere_count: u32,
+    instance_group_count: u32,
+    exposure: f32,
+    blue_noise: u32,
+    // 1 stops tracing the pixels whose mean is known well enough, see pixel_converged.
+    adaptive_pixel_sampling: u32,
+    // Rays shot from the first hit and their length, for the ambient occlusion debug mode.
+    ao_rays: u32,
+    ao_radius: f32,
+    // Bounces off anything but a dielectric, glass only counts against max_depth.
+    max_diffuse_depth: u32,
+    // 0 is sRGB, left to the surface, 1 is Rec.709, 2 is Linear
+    output_colorspace: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+}
+
+struct Triangle {
+    // Only the first three components of each vertex are used.
+    v0: vec4<f32>,
+    v1: vec4<f32>,
+    v2: vec4<f32>,
+    material: Material,
+}
+
+struct AxisAlignedBox {
+    // Opposite corners, only the first three components are used.
+    min: vec4<f32>,
+    max: vec4<f32>,
+    material: Material,
+}
+
+struct Quad {
+    // Parallelogram spanning corner + a * u + b * v for a and b in [0, 1].
+    corner: vec4<f32>,
+    u: vec4<f32>,
+    v: vec4<f32>,
+    material: Material,
+}
+
+struct Cylinder {
+    // Center of the bottom cap.
+    base: vec4<f32>,
+    // Unit vector from the bottom cap to the top one.
+    axis: vec4<f32>,
+    radius: f32,
+    height: f32,
+    _padding0: f32,
+    _padding1: f32,
+    material: Material,
+}
+
+struct Plane {
+    point: vec4<f32>,
+    // Unit vector, the plane is visible from both sides.
+    normal: vec4<f32>,
+    // Disk of this radius around point, 0 for an infinite plane.
+    radius: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+    material: Material,
+}
+
+struct PointLight {
+    position: vec3<f32>,
+    intensity: f32,
+    color: vec4<f32>,
+    // Spotlights only, point lights have a zero direction and both cosines at -1.
+    direction: vec3<f32>,
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+}
+
+// A leaf (count > 0) covers the spheres left_or_first..left_or_first + count,
+// an inner node (count == 0) has its children at left_or_first and left_or_first + 1.
+struct BvhNode {
+    min: vec3<f32>,
+    left_or_first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
+// Sphere of this radius around the origin of its object space.
+struct TransformedSphere {
+    world_to_object: mat4x4<f32>,
+    radius: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+    material: Material,
+}
+
+// Range of instances sharing a material.
+struct InstanceGroup {
+    first: u32,
+    count: u32,
+    root: u32,
+    _padding: u32,
+    material: Material,
 }
 
 struct HitInfo {
@@ -46,6 +303,10 @@ struct HitInfo {
     normal: vec3<f32>,
     material: Material,
     front_face: bool,
+    sphere_idx: u32,
+    quad_idx: u32,
+    // Texture coordinates, (0, 0) is the top left corner of the image.
+    uv: vec2<f32>,
 }
 
 struct ReflectInfo {
@@ -54,92 +315,744 @@ struct ReflectInfo {
 }
 
 struct Ray {
-    dir: vec3<f32>,
     origin: vec3<f32>,
+    dir: vec3<f32>,
 }
 
 // Ideal workgroup size depends on the hardware, the workload, and other factors. However, it should
 // _generally_ be a multiple of 64. Common sizes are 64x1x1, 256x1x1; or 8x8x1, 16x16x1 for 2D workloads.
-@compute @workgroup_size(8, 8, 1)
+// Overridden at pipeline creation with WORKGROUP_SIZE from state.rs, which also drives the dispatch.
+override workgroup_size: u32 = 8u;
+
+@compute @workgroup_size(workgroup_size, workgroup_size, 1)
 fn main_compute(
     @builtin(global_invocation_id) global_invocation_id: vec3<u32>
 ) {
     let window_size: vec2<u32> = vec2(world_data.window_width, world_data.window_height);
-    let x = global_invocation_id.x;
-    let y = global_invocation_id.y;
-    var seed = initSeed(vec2(x, y)); 
-    let pix_color = pixel_color(x, y, &seed);
+    let x = tile_origin.x + global_invocation_id.x;
+    let y = tile_origin.y + global_invocation_id.y;
+    if x >= window_size.x || y >= window_size.y {
+        return;
+    }
+    let coords = vec2<i32>(i32(x), i32(y));
+    // frames_since_change is 1 on the first frame after a change: start again from scratch.
+    var accumulated = vec4(0.0);
+    var weight = 0.0;
+    if world_data.frames_since_change > 1u {
+        accumulated = textureLoad(accumulation_buffer, coords);
+        weight = textureLoad(weight_buffer, coords).x;
+    }
+    let previous_frames = f32(max(world_data.frames_since_change, 1u) - 1u);
+    if world_data.adaptive_pixel_sampling != 0u
+        && pixel_converged(accumulated, weight, previous_frames) {
+        // Adding the mean keeps the mean and the variance, so the pixel stays converged.
+        accumulated += accumulated / previous_frames;
+        weight += weight / previous_frames;
+    } else {
+        var samples = world_data.sample_per_pixels;
+        if world_data.adaptive_pixel_sampling != 0u {
+            atomicAdd(&active_pixels, 1u);
+            // The time saved on the converged pixels goes to the noisiest ones.
+            if previous_frames >= adaptive_min_frames
+                && pixel_error(accumulated, weight, previous_frames) > 4.0 * adaptive_max_error {
+                samples *= 2u;
+            }
+        }
+        // The weights are summed over every frame, not normalized per frame: with one sample per
+        // frame, the weight would cancel out and every filter would turn into a wide box.
+        let weighted = pixel_color(x, y, samples);
+        let luminance = dot(weighted.xyz / max(weighted.w, 1e-8), vec3(0.2126, 0.7152, 0.0722));
+        accumulated += vec4(weighted.xyz, luminance * luminance);
+        weight += weighted.w;
+    }
+    textureStore(accumulation_buffer, coords, accumulated);
+    textureStore(weight_buffer, coords, vec4(weight, 0.0, 0.0, 0.0));
+    store_color(coords, accumulated, weight);
+    store_first_hit(x, y, coords);
+}
 
-    textureStore(color_buffer, vec2<i32>(i32(x), i32(y)), pix_color);
+// Displays the accumulation again without tracing anything, after a change of the exposure, the
+// tone map or the output encoding, which all come after the accumulation.
+@compute @workgroup_size(workgroup_size, workgroup_size, 1)
+fn resolve(
+    @builtin(global_invocation_id) global_invocation_id: vec3<u32>
+) {
+    if global_invocation_id.x >= world_data.window_width
+        || global_invocation_id.y >= world_data.window_height {
+        return;
+    }
+    let coords = vec2<i32>(global_invocation_id.xy);
+    let accumulated = textureLoad(accumulation_buffer, coords);
+    let weight = textureLoad(weight_buffer, coords).x;
+    store_color(coords, accumulated, weight);
 }
 
-fn pixel_color(x: u32, y: u32, seed: ptr<function, u32>) -> vec4<f32>{
-    var mean_color: vec4<f32>;
+// Exposes, tone maps and encodes the accumulated radiance of a pixel for display.
+fn store_color(coords: vec2<i32>, accumulated: vec4<f32>, weight: f32) {
+    var color = resolved_radiance(accumulated, weight);
+    // The debug colors are already in [0, 1].
+    if world_data.debug_mode == 0u {
+        color = tone_map(color * exp2(world_data.exposure));
+    }
+    if world_data.gamma > 0.0 {
+        color = pow(max(color, vec3(0.0)), vec3(1.0 / world_data.gamma));
+    } else if world_data.output_colorspace == 1u {
+        color = rec709_oetf(color);
+    }
+    textureStore(color_buffer, coords, vec4(color, 1.0));
+}
+
+// Frames every pixel gets before its variance is trusted.
+const adaptive_min_frames = 16.0;
+// Standard error of the mean luminance, relative to that mean, below which a pixel is converged.
+const adaptive_max_error = 0.01;
+
+// Weighted mean of the samples behind a pixel of the accumulation.
+fn resolved_radiance(accumulated: vec4<f32>, weight: f32) -> vec3<f32> {
+    return accumulated.xyz / max(weight, 1e-8);
+}
+
+// Relative standard error of the mean luminance, from the sums of the accumulation texture. The w
+// channel sums the squared luminance of each frame.
+fn pixel_error(accumulated: vec4<f32>, weight: f32, frames: f32) -> f32 {
+    let mean = dot(resolved_radiance(accumulated, weight), vec3(0.2126, 0.7152, 0.0722));
+    let variance = max(accumulated.w / frames - mean * mean, 0.0);
+    // Black pixels with no variance are converged, the small bound keeps them from dividing by 0.
+    return sqrt(variance / frames) / max(mean, 1e-4);
+}
+
+fn pixel_converged(accumulated: vec4<f32>, weight: f32, frames: f32) -> bool {
+    return frames >= adaptive_min_frames
+        && pixel_error(accumulated, weight, frames) < adaptive_max_error;
+}
+
+fn store_first_hit(x: u32, y: u32, coords: vec2<i32>) {
+    // Past the indices of the samples, so that it does not repeat one of their sequences.
+    var seed = init_seed(vec2(x, y), world_data.sample_per_pixels);
+    shutter_time = 0.0;
+    let ray = get_ray(x, y, vec2(0.0, 0.0), &seed);
+    let hit = hit_world(ray, -1.0);
+    if !hit.hit {
+        textureStore(depth_buffer, coords, vec4(-1.0, 0.0, 0.0, 0.0));
+        textureStore(albedo_buffer, coords, vec4(0.0));
+        textureStore(normal_buffer, coords, vec4(0.0, 0.0, 0.0, -1.0));
+        return;
+    }
+    textureStore(depth_buffer, coords, vec4(hit.time * length(ray.dir), 0.0, 0.0, 0.0));
+    textureStore(albedo_buffer, coords, vec4(albedo(hit).xyz, 1.0));
+    // The spare w holds the index of the sphere for picking, -1 for anything else.
+    let sphere = select(f32(hit.sphere_idx), -1.0, hit.sphere_idx == no_sphere);
+    textureStore(normal_buffer, coords, vec4(hit.normal, sphere));
+}
+
+fn tone_map(radiance: vec3<f32>) -> vec3<f32> {
+    switch world_data.tone_map {
+        case 1u: {
+            // Extended Reinhard, plain Reinhard when the white point goes to infinity.
+            let white = world_data.white_point;
+            return radiance * (1.0 + radiance / (white * white)) / (1.0 + radiance);
+        }
+        case 2u: {
+            return aces(radiance);
+        }
+        default: {
+            return radiance;
+        }
+    }
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3(0.0), vec3(1.0));
+}
+
+// ITU-R BT.709 transfer function, from linear light to the encoded signal.
+fn rec709_oetf(linear: vec3<f32>) -> vec3<f32> {
+    let l = clamp(linear, vec3(0.0), vec3(1.0));
+    return select(1.099 * pow(l, vec3(0.45)) - 0.099, 4.5 * l, l < vec3(0.018));
+}
+
+// Sum of the samples weighted by the pixel filter, and the sum of their weights in w.
+fn pixel_color(x: u32, y: u32, samples: u32) -> vec4<f32>{
+    var weighted_sum = vec3(0.0);
+    var total_weight = 0.0;
+    // Stratified sampling: the pixel is split in a grid x grid array of cells, each jittered once.
+    // Samples that do not fit the grid (sample_per_pixels is not a perfect square) are uniform.
+    let grid = u32(floor(sqrt(f32(samples))));
     var i: u32;
-    for (i=0u; i < world_data.sample_per_pixels; i++) {
-        let ray = get_ray(x, y, seed);
+    for (i=0u; i < samples; i++) {
+        var sample_seed = init_seed(vec2(x, y), i);
+        let seed = &sample_seed;
+        if world_data.blue_noise != 0u {
+            noise_pixel = vec2(x, y);
+            noise_scramble = init_noise_scramble(i);
+            noise_dimension = 0u;
+        }
+        var offset = vec2(random_range_f32(-0.5, 0.5, seed), random_range_f32(-0.5, 0.5, seed));
+        if world_data.antialiasing == 0u {
+            offset = vec2(0.0, 0.0);
+        } else if i < grid * grid {
+            let cell = vec2(f32(i % grid), f32(i / grid));
+            offset = (cell + offset + 0.5) / f32(grid) - 0.5;
+        }
+        shutter_time = random_range_f32(0.0, 1.0, seed) * world_data.shutter;
+        offset *= pixel_filter_radius() * 2.0;
+        let ray = get_ray(x, y, offset, seed);
         let pix_color = ray_color(ray, seed);
-        mean_color = mean_color + pix_color;
+        let weight = pixel_filter_weight(offset);
+        weighted_sum += weight * clamp_firefly(pix_color).xyz;
+        total_weight += weight;
     }
-    mean_color = 1.0/f32(world_data.sample_per_pixels) * mean_color;
-    return mean_color;
+    noise_dimension = blue_noise_dimensions;
+    return vec4(weighted_sum, total_weight);
 }
 
-fn get_ray(x: u32, y: u32, seed: ptr<function, u32>) -> Ray {
+// Half the side of the square the samples are spread over, in pixels.
+fn pixel_filter_radius() -> f32 {
+    switch world_data.pixel_filter {
+        case 1u: {
+            return 1.0;
+        }
+        case 2u: {
+            return 1.5;
+        }
+        default: {
+            return 0.5;
+        }
+    }
+}
 
-    
-    let x_eps = random_range_f32(-0.5, 0.5, seed);
-    let y_eps = random_range_f32(-0.5, 0.5, seed);
+// Never 0 inside the radius, so the total weight of a pixel is positive.
+fn pixel_filter_weight(offset: vec2<f32>) -> f32 {
+    switch world_data.pixel_filter {
+        case 1u: {
+            let falloff = max(1.0 - abs(offset), vec2(1e-3));
+            return falloff.x * falloff.y;
+        }
+        case 2u: {
+            return exp(-2.0 * dot(offset, offset));
+        }
+        default: {
+            return 1.0;
+        }
+    }
+}
 
+// Scale the sample down to the clamp luminance, keeping its hue.
+fn clamp_firefly(color: vec4<f32>) -> vec4<f32> {
+    let luminance = dot(color.xyz, vec3(0.2126, 0.7152, 0.0722));
+    if world_data.firefly_clamp > 0.0 && luminance > world_data.firefly_clamp {
+        return vec4(color.xyz * (world_data.firefly_clamp / luminance), color.w);
+    }
+    return color;
+}
+
+// offset is the position of the sample inside the pixel, in [-0.5, 0.5]^2.
+fn get_ray(x: u32, y: u32, offset: vec2<f32>, seed: ptr<function, u32>) -> Ray {
     let pix = world_data.pixel_up_left 
-        + (f32(x) + x_eps) * world_data.pix_delta_x 
-        + (f32(y) + y_eps) * world_data.pix_delta_y;
+        + (f32(x) + offset.x) * world_data.pix_delta_x 
+        + (f32(y) + offset.y) * world_data.pix_delta_y;
     
+    var origin = world_data.lookfrom;
+    // Orthographic rays start on the plane through lookfrom, right behind their pixel.
+    if world_data.camera_type == 1u {
+        origin = pix + world_data.focus_dist * world_data.camera_frame_w;
+    }
+    if world_data.aperture > 0.0 {
+        let p = random_vec2_unit(seed);
+        origin += p.x * world_data.defocus_disk_u + p.y * world_data.defocus_disk_v;
+    }
+
     var ray: Ray;
-    ray.origin = world_data.lookfrom.xyz;
-    ray.dir = (pix - world_data.lookfrom).xyz;
+    ray.origin = origin.xyz;
+    ray.dir = (pix - origin).xyz;
     return ray;
 }
 
+// Color of the debug modes, only the first hit matters.
+fn debug_color(ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
+    // The background has a cost too.
+    if world_data.debug_mode == 4u {
+        traversal_tests = 0u;
+        _ = hit_spheres(ray, -1.0);
+        return vec4(heatmap(f32(traversal_tests) / heatmap_max_tests), 1.0);
+    }
+    let hit = hit_world(ray, -1.0);
+    if !hit.hit {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+    switch world_data.debug_mode {
+        case 1u: {
+            return vec4(0.5 * (hit.normal + 1.0), 1.0);
+        }
+        case 2u: {
+            // Linear in the distance, black from twice the focus distance on.
+            let distance = hit.time * length(ray.dir);
+            return vec4(vec3(1.0 - clamp(0.5 * distance / world_data.focus_dist, 0.0, 1.0)), 1.0);
+        }
+        case 5u: {
+            return vec4(vec3(ambient_occlusion(hit, seed)), 1.0);
+        }
+        default: {
+            return vec4(albedo(hit).xyz, 1.0);
+        }
+    }
+}
+
+// Fraction of cosine weighted rays from the hit that go ao_radius without hitting anything.
+fn ambient_occlusion(hit: HitInfo, seed: ptr<function, u32>) -> f32 {
+    var unoccluded = 0u;
+    for (var i = 0u; i < world_data.ao_rays; i++) {
+        let dir = cosine_hemisphere(hit.normal, seed);
+        if !hit_world(Ray(hit.point, dir), world_data.ao_radius).hit {
+            unoccluded++;
+        }
+    }
+    return f32(unoccluded) / f32(world_data.ao_rays);
+}
+
+// Blue at 0, then cyan, green, yellow and red at 1.
+fn heatmap(t: f32) -> vec3<f32> {
+    let x = 4.0 * clamp(t, 0.0, 1.0);
+    return clamp(vec3(x - 2.0, 2.0 - abs(x - 2.0), 2.0 - x), vec3(0.0), vec3(1.0));
+}
+
 fn ray_color(initial_ray: Ray, seed: ptr<function, u32>) -> vec4<f32> {
+    if world_data.debug_mode != 0u {
+        return debug_color(initial_ray, seed);
+    }
     var ray = initial_ray;
-    var color: vec3<f32>;
+    var radiance = vec3(0.0, 0.0, 0.0);
     var throughput = vec3(1.0, 1.0, 1.0);
+    // Set after a Lambertian bounce or a diffuse Pbr one, where the emissive spheres were also
+    // sampled directly.
+    var sampled_lights = false;
+    var last_point: vec3<f32>;
+    var last_bsdf_pdf: f32;
+    var diffuse_bounces = 0u;
     
     for (var i = 0u; i < world_data.max_depth; i++){
-        var closest_hit: HitInfo;
-        closest_hit.hit = false;
+        let closest_hit = hit_world(ray, -1.0);
 
+        if closest_hit.hit {
+            // Leaving a dielectric through its back face: the segment was inside, Beer–Lambert.
+            if closest_hit.material.material_type == 2u && !closest_hit.front_face {
+                let distance = closest_hit.time * length(ray.dir);
+                throughput *= exp(-closest_hit.material.absorption.xyz * distance);
+            }
+            // Light sources end the path, they do not scatter anything.
+            if closest_hit.material.material_type == 3u {
+                let emitted = closest_hit.material.color.xyz * closest_hit.material.fuzz;
+                // The light sample of the previous bounce could have found this sphere or quad too.
+                var weight = 1.0;
+                if sampled_lights && closest_hit.sphere_idx != no_sphere {
+                    let light_pdf = sphere_light_pdf(last_point, closest_hit.sphere_idx);
+                    weight = power_heuristic(last_bsdf_pdf, light_pdf);
+                }
+                if sampled_lights && closest_hit.quad_idx != no_quad {
+                    let light_pdf = quad_light_pdf(last_point, closest_hit);
+                    weight = power_heuristic(last_bsdf_pdf, light_pdf);
+                }
+                radiance += throughput * emitted * weight;
+                break;
+            }
+            let emission = closest_hit.material.emission;
+            radiance += throughput * emission.xyz * emission.w;
+            // Point lights cannot be hit by the scattered rays, they are only gathered here.
+            // Emissive spheres and quads are gathered both ways, the weights keep the sum unbiased.
+            // Only the diffuse lobe of Pbr surfaces is sampled this way, taken with lobes.w.
+            let material_type = closest_hit.material.material_type;
+            var lobes = vec4(0.0, 0.0, 0.0, 1.0);
+            if material_type == 0u {
+                lobes = vec4(albedo(closest_hit).xyz, 1.0);
+            } else if material_type == 5u {
+                lobes = pbr_lobes(ray, closest_hit);
+            }
+            if material_type == 0u || material_type == 5u {
+                radiance += throughput * direct_light(closest_hit, lobes.xyz);
+                radiance += throughput * sample_emissive_sphere(closest_hit, lobes, seed);
+                radiance += throughput * sample_area_lights(closest_hit, lobes, seed);
+            }
+            // Nested glass needs many bounces, they do not use up the budget of the other materials.
+            if closest_hit.material.material_type != 2u {
+                diffuse_bounces++;
+                if diffuse_bounces >= world_data.max_diffuse_depth {
+                    break;
+                }
+            }
+            let reflect = scatter(ray, closest_hit, seed);
+            // A specular Pbr bounce was not sampled against the lights, it keeps the whole weight.
+            sampled_lights = material_type == 0u || (material_type == 5u && took_diffuse_lobe);
+            if sampled_lights {
+                last_point = closest_hit.point;
+                last_bsdf_pdf = lobes.w * lambertian_pdf(closest_hit.normal, reflect.ray.dir);
+            }
+            ray = reflect.ray;
+            throughput *= reflect.color.xyz;
+            // Nothing more can be gathered along an absorbed path.
+            if all(throughput == vec3(0.0)) {
+                break;
+            }
+
+            // Russian roulette: dim paths are likely killed, the survivors are scaled up to stay unbiased.
+            if i >= world_data.rr_min_bounces {
+                let survival = min(max(throughput.x, max(throughput.y, throughput.z)), 1.0);
+                if random_range_f32(0.0, 1.0, seed) >= survival {
+                    break;
+                }
+                throughput /= survival;
+            }
+        } else {
+            radiance += throughput * sky_color(ray.dir);
+            break;
+        }
+    }
+
+    return vec4(radiance, 1.0);
+}
+
+// Closest hit among every primitive, max_t is -1 for no limit.
+fn hit_world(ray: Ray, max_t: f32) -> HitInfo {
+    var closest_hit = hit_spheres(ray, max_t);
+
+    for (var i = 0u; i < world_data.triangle_count; i++) {
+        let hit_info = hit_triangle(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    for (var i = 0u; i < world_data.box_count; i++) {
+        let hit_info = hit_box(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    for (var i = 0u; i < world_data.quad_count; i++) {
+        let hit_info = hit_quad(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    for (var i = 0u; i < world_data.cylinder_count; i++) {
+        let hit_info = hit_cylinder(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    for (var i = 0u; i < world_data.plane_count; i++) {
+        let hit_info = hit_plane(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    for (var i = 0u; i < world_data.transformed_sphere_count; i++) {
+        let hit_info = hit_transformed_sphere(ray, i, 0.01, max_t);
+        if hit_info.hit {
+            if closest_hit.time > hit_info.time || !closest_hit.hit {
+                closest_hit = hit_info;
+            }
+        }
+    }
+
+    closest_hit = hit_instances(ray, closest_hit, max_t);
+
+    return closest_hit;
+}
+
+// 1 inside the inner cone of a spotlight, 0 outside the outer one, smooth in between. The zero
+// direction of a point light gives a cosine of 0, always above its -1 bounds.
+fn cone_falloff(light: PointLight, dir: vec3<f32>) -> f32 {
+    let cos_angle = dot(-dir, light.direction);
+    let t = clamp((cos_angle - light.cos_outer) / max(light.cos_inner - light.cos_outer, 1e-4), 0.0, 1.0);
+    return t * t * (3.0 - 2.0 * t);
+}
+
+// Light reflected by a Lambertian surface of the given albedo from every point light and spotlight
+// it can see.
+fn direct_light(hit_info: HitInfo, diffuse: vec3<f32>) -> vec3<f32> {
+    var irradiance = vec3(0.0, 0.0, 0.0);
+    for (var i = 0u; i < world_data.light_count; i++) {
+        let light = lights[i];
+        let to_light = light.position - hit_info.point;
+        let dist = length(to_light);
+        let dir = to_light / dist;
+        let cos_theta = dot(hit_info.normal, dir);
+        let falloff = cone_falloff(light, dir);
+        if cos_theta <= 0.0 || falloff <= 0.0 {
+            continue;
+        }
+        // Shadow ray: anything between the surface and the light blocks it.
+        if hit_world(Ray(hit_info.point, dir), dist).hit {
+            continue;
+        }
+        irradiance += light.color.xyz * light.intensity * falloff * cos_theta / (dist * dist);
+    }
+    // Lambertian BRDF
+    return diffuse / pi * irradiance;
+}
+
+// Next event estimation: sample the cone of directions toward one random emissive sphere.
+// The result is weighted against the BSDF sample with multiple importance sampling. lobes holds the
+// albedo of the diffuse lobe and the probability with which the BSDF sample takes it.
+fn sample_emissive_sphere(hit_info: HitInfo, lobes: vec4<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let count = world_data.emissive_sphere_count;
+    if count == 0u {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let pick = min(u32(random_range_f32(0.0, 1.0, seed) * f32(count)), count - 1u);
+    let sphere_idx = emissive_spheres[pick];
+    let sphere = spheres[sphere_idx];
+
+    let to_center = sphere_center(sphere_idx) - hit_info.point;
+    let dist2 = dot(to_center, to_center);
+    let radius2 = sphere.w * sphere.w;
+    // Inside the light, the cone covers every direction.
+    if dist2 <= radius2 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let cos_theta_max = sqrt(1.0 - radius2 / dist2);
+    // Too far to resolve the cone, for instance from a distant point of an infinite plane.
+    if cos_theta_max >= 1.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let cos_theta = 1.0 + random_range_f32(0.0, 1.0, seed) * (cos_theta_max - 1.0);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    let phi = 2.0 * pi * random_range_f32(0.0, 1.0, seed);
+    let basis = orthonormal_basis(to_center / sqrt(dist2));
+    let dir = basis * vec3(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+
+    let cos_surface = dot(hit_info.normal, dir);
+    if cos_surface <= 0.0 {
+        return vec3(0.0, 0.0, 0.0);
+    }
+    let ray = Ray(hit_info.point, dir);
+    let light_hit = hit_sphere(ray, sphere_idx, 0.01, -1.0);
+    if !light_hit.hit || hit_world(ray, light_hit.time * 0.999).hit {
+        return vec3(0.0, 0.0, 0.0);
+    }
+
+    let material = materials[sphere_idx];
+    let emitted = material.color.xyz * material.fuzz;
+    let light_pdf = 1.0 / (2.0 * pi * (1.0 - cos_theta_max) * f32(count));
+    let bsdf_pdf = lobes.w * cos_surface / pi;
+    let weight = power_heuristic(light_pdf, bsdf_pdf);
+    // Lambertian BRDF
+    let brdf = lobes.xyz / pi;
+    return brdf * emitted * cos_surface / light_pdf * weight;
+}
+
+// Density with which sample_emissive_sphere picks a direction from point toward the sphere.
+fn sphere_light_pdf(point: vec3<f32>, sphere_idx: u32) -> f32 {
+    let sphere = spheres[sphere_idx];
+    let to_center = sphere_center(sphere_idx) - point;
+    let dist2 = dot(to_center, to_center);
+    let radius2 = sphere.w * sphere.w;
+    if dist2 <= radius2 {
+        return 0.0;
+    }
+    let cos_theta_max = sqrt(1.0 - radius2 / dist2);
+    // Never sampled then, see sample_emissive_sphere.
+    if cos_theta_max >= 1.0 {
+        return 0.0;
+    }
+    return 1.0 / (2.0 * pi * (1.0 - cos_theta_max) * f32(world_data.emissive_sphere_count));
+}
+
+// Next event estimation toward every emissive quad, with one point taken uniformly on each.
+// Quads emit from both sides. Weighted against the BSDF sample like sample_emissive_sphere.
+fn sample_area_lights(hit_info: HitInfo, lobes: vec4<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    var result = vec3(0.0, 0.0, 0.0);
+    for (var i = 0u; i < world_data.quad_count; i++) {
+        let quad = quads[i];
+        if quad.material.material_type != 3u {
+            continue;
+        }
+        let a = random_range_f32(0.0, 1.0, seed);
+        let b = random_range_f32(0.0, 1.0, seed);
+        let to_light = quad.corner.xyz + a * quad.u.xyz + b * quad.v.xyz - hit_info.point;
+        let dist2 = dot(to_light, to_light);
+        let dist = sqrt(dist2);
+        let dir = to_light / dist;
+        let cos_surface = dot(hit_info.normal, dir);
+        let n = cross(quad.u.xyz, quad.v.xyz);
+        let area = length(n);
+        let cos_light = abs(dot(n, dir)) / area;
+        if cos_surface <= 0.0 || cos_light <= 0.0 {
+            continue;
+        }
+        if hit_world(Ray(hit_info.point, dir), dist * 0.999).hit {
+            continue;
+        }
+
+        let emitted = quad.material.color.xyz * quad.material.fuzz;
+        // Uniform over the area, turned into a density over directions.
+        let light_pdf = dist2 / (cos_light * area);
+        let bsdf_pdf = lobes.w * cos_surface / pi;
+        let weight = power_heuristic(light_pdf, bsdf_pdf);
+        // Lambertian BRDF
+        let brdf = lobes.xyz / pi;
+        result += brdf * emitted * cos_surface / light_pdf * weight;
+    }
+    return result;
+}
+
+// Density with which sample_area_lights picks the direction from point to light_hit, a hit on a quad.
+fn quad_light_pdf(point: vec3<f32>, light_hit: HitInfo) -> f32 {
+    let quad = quads[light_hit.quad_idx];
+    let to_light = light_hit.point - point;
+    let dist2 = dot(to_light, to_light);
+    let n = cross(quad.u.xyz, quad.v.xyz);
+    let area = length(n);
+    let cos_light = abs(dot(n, to_light)) / (area * sqrt(dist2));
+    if cos_light <= 0.0 {
+        return 0.0;
+    }
+    return dist2 / (cos_light * area);
+}
+
+// Cosine weighted, see lambertian_reflect.
+fn lambertian_pdf(normal: vec3<f32>, dir: vec3<f32>) -> f32 {
+    return max(dot(normal, normalize(dir)), 0.0) / pi;
+}
+
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b == 0.0 {
+        return 0.0;
+    }
+    return a / (a + b);
+}
+
+// Rotation taking z to n (Duff et al., Building an Orthonormal Basis, Revisited).
+fn orthonormal_basis(n: vec3<f32>) -> mat3x3<f32> {
+    let s = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (s + n.z);
+    let b = n.x * n.y * a;
+    let t = vec3(1.0 + s * n.x * n.x * a, s * b, -s * n.x);
+    let u = vec3(b, s + n.y * n.y * a, -n.y);
+    return mat3x3(t, u, n);
+}
+
+fn sky_color(dir: vec3<f32>) -> vec3<f32> {
+    if world_data.background_mode == 1u {
+        return world_data.solid_color.xyz;
+    }
+    let u_dir = normalize(dir);
+    if world_data.background_mode == 2u {
+        // Longitude around y along u, angle from the zenith along v.
+        let phi = atan2(u_dir.z, u_dir.x);
+        let theta = acos(clamp(u_dir.y, -1.0, 1.0));
+        let uv = vec2(phi / (2.0 * pi) + 0.5, theta / pi);
+        return textureSampleLevel(environment_map, environment_sampler, uv, 0.0).xyz;
+    }
+    let a = 0.5 * (u_dir.y + 1.0);
+    return mix(world_data.sky_bottom.xyz, world_data.sky_top.xyz, a);
+}
+
+// Closest sphere along the ray, going through the BVH when there is one.
+fn hit_spheres(ray: Ray, max_t: f32) -> HitInfo {
+    var closest_hit: HitInfo;
+    closest_hit.hit = false;
+
+    if world_data.bvh_node_count == 0u {
         for (var i = 0u; i < world_data.sphere_count; i++) {
-            let hit_info = hit(ray, i, 0.01,  -1.0);
+            traversal_tests++;
+            let hit_info = hit_sphere(ray, i, 0.01, max_t);
             if hit_info.hit {
                 if closest_hit.time > hit_info.time || !closest_hit.hit {
                     closest_hit = hit_info;
                 }
             }
         }
+        return closest_hit;
+    }
 
-        if closest_hit.hit {
-            let reflect = lambertian_reflect(closest_hit, seed);
-            ray = reflect.ray;
-            throughput *= reflect.color.xyz;
-        } else {
-            let u_dir = normalize(ray.dir);
-            let a = 0.5 * (u_dir.y + 1.0);
-            color = (1.0 - a) * vec3(1.0, 1.0, 1.0) + a * vec3(0.5, 0.7, 1.0);
-            break;
+    let inv_dir = 1.0 / ray.dir;
+    var stack: array<u32, bvh_stack_size>;
+    stack[0] = 0u;
+    var stack_len = 1u;
+    // Nodes further than the closest hit so far cannot contain anything closer.
+    var limit = max_t;
+    while stack_len > 0u {
+        stack_len--;
+        let node = bvh_nodes[stack[stack_len]];
+        traversal_tests++;
+
+        if !hit_bounds(ray, inv_dir, node.min, node.max, 0.01, limit) {
+            continue;
+        }
+
+        if node.count > 0u {
+            for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+                traversal_tests++;
+                let hit_info = hit_sphere(ray, i, 0.01, limit);
+                if hit_info.hit {
+                    if closest_hit.time > hit_info.time || !closest_hit.hit {
+                        closest_hit = hit_info;
+                        limit = hit_info.time;
+                    }
+                }
+            }
+        } else if stack_len + 2u <= bvh_stack_size {
+            stack[stack_len] = node.left_or_first;
+            stack[stack_len + 1u] = node.left_or_first + 1u;
+            stack_len += 2u;
         }
     }
+    return closest_hit;
+}
 
-    return vec4(throughput * color, 1.0);
+// Slab test against a bounding box, only tells whether the ray goes through it within the range.
+fn hit_bounds(ray: Ray, inv_dir: vec3<f32>, box_min: vec3<f32>, box_max: vec3<f32>, min_t: f32, max_t: f32) -> bool {
+    let t0 = (box_min - ray.origin) * inv_dir;
+    let t1 = (box_max - ray.origin) * inv_dir;
+    let t_small = min(t0, t1);
+    let t_big = max(t0, t1);
+    let t_near = max(max(max(t_small.x, t_small.y), t_small.z), min_t);
+    var t_far = min(min(t_big.x, t_big.y), t_big.z);
+    if max_t != -1.0 {
+        t_far = min(t_far, max_t);
+    }
+    return t_near <= t_far;
 }
 
+// Position of the sphere at the shutter time of the current sample.
+fn sphere_center(sphere_idx: u32) -> vec3<f32> {
+    return spheres[sphere_idx].xyz + shutter_time * sphere_velocities[sphere_idx].xyz;
+}
 
-fn hit(ray: Ray, sphere_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
-    let sphere = world_data.spheres[sphere_idx];
-    let center = sphere.xyz;
-    let radius = sphere.w;
+fn hit_sphere(ray: Ray, sphere_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let center = sphere_center(sphere_idx);
+    var hit_info = hit_sphere_at(ray, center, spheres[sphere_idx].w, min_t, max_t);
+    hit_info.material = materials[sphere_idx];
+    hit_info.sphere_idx = sphere_idx;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
 
+// Geometry only, the caller fills in the material and the sphere index.
+fn hit_sphere_at(ray: Ray, center: vec3<f32>, radius: f32, min_t: f32, max_t: f32) -> HitInfo {
     let v: vec3<f32> = center - ray.origin;
     let a: f32 = dot(ray.dir, ray.dir);
     let h: f32 = dot(ray.dir, v);
@@ -168,57 +1081,652 @@ fn hit(ray: Ray, sphere_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
     hit_info.point = ray.origin + root * ray.dir;
     hit_info.normal = normalize(hit_info.point - center); 
     hit_info.front_face = dot(hit_info.normal, ray.dir) < 0.0;
+    hit_info.uv = sphere_uv(hit_info.normal);
 
     if !hit_info.front_face {
         hit_info.normal = -hit_info.normal;
     }
-    
-    hit_info.material = world_data.materials[sphere_idx];
-    return hit_info;    
+    return hit_info;
+}
+
+// Every instance of a group shares its material. Each group is traversed through its own hierarchy,
+// like hit_spheres.
+fn hit_instances(ray: Ray, closest: HitInfo, max_t: f32) -> HitInfo {
+    var closest_hit = closest;
+    let inv_dir = 1.0 / ray.dir;
+    var limit = max_t;
+    if closest_hit.hit {
+        limit = closest_hit.time;
+    }
+    for (var g = 0u; g < world_data.instance_group_count; g++) {
+        let group = instance_groups[g];
+        var stack: array<u32, bvh_stack_size>;
+        stack[0] = group.root;
+        var stack_len = 1u;
+        while stack_len > 0u {
+            stack_len--;
+            let node = instance_bvh_nodes[stack[stack_len]];
+            traversal_tests++;
+
+            if !hit_bounds(ray, inv_dir, node.min, node.max, 0.01, limit) {
+                continue;
+            }
+
+            if node.count > 0u {
+                for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+                    traversal_tests++;
+                    let instance = instances[i];
+                    var hit_info = hit_sphere_at(ray, instance.xyz, instance.w, 0.01, limit);
+                    if hit_info.hit {
+                        hit_info.material = group.material;
+                        hit_info.sphere_idx = no_sphere;
+                        hit_info.quad_idx = no_quad;
+                        closest_hit = hit_info;
+                        limit = hit_info.time;
+                    }
+                }
+            } else if stack_len + 2u <= bvh_stack_size {
+                stack[stack_len] = node.left_or_first;
+                stack[stack_len + 1u] = node.left_or_first + 1u;
+                stack_len += 2u;
+            }
+        }
+    }
+    return closest_hit;
+}
+
+// Möller–Trumbore intersection.
+fn hit_triangle(ray: Ray, triangle_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let triangle = triangles[triangle_idx];
+    let v0 = triangle.v0.xyz;
+    let edge1 = triangle.v1.xyz - v0;
+    let edge2 = triangle.v2.xyz - v0;
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    let p = cross(ray.dir, edge2);
+    let det = dot(edge1, p);
+    // The ray is parallel to the triangle.
+    if abs(det) < 1e-8 {
+        return hit_info;
+    }
+    let inv_det = 1.0 / det;
+
+    let s = ray.origin - v0;
+    let u = dot(s, p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return hit_info;
+    }
+
+    let q = cross(s, edge1);
+    let v = dot(ray.dir, q) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return hit_info;
+    }
+
+    let root = dot(edge2, q) * inv_det;
+    if (min_t != -1.0 && root < min_t ) || (max_t != -1.0 && root > max_t) {
+        return hit_info;
+    }
+
+    hit_info.hit = true;
+    hit_info.time = root;
+    hit_info.point = ray.origin + root * ray.dir;
+    hit_info.normal = normalize(cross(edge1, edge2));
+    hit_info.front_face = dot(hit_info.normal, ray.dir) < 0.0;
+
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = triangle.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
+
+// Intersect the plane of the quad, then express the point in the (u, v) basis to check it is inside.
+fn hit_quad(ray: Ray, quad_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let quad = quads[quad_idx];
+    let corner = quad.corner.xyz;
+    let u = quad.u.xyz;
+    let v = quad.v.xyz;
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    let n = cross(u, v);
+    let normal = normalize(n);
+    let denom = dot(normal, ray.dir);
+    // The ray is parallel to the quad.
+    if abs(denom) < 1e-8 {
+        return hit_info;
+    }
+
+    let root = dot(normal, corner - ray.origin) / denom;
+    if (min_t != -1.0 && root < min_t ) || (max_t != -1.0 && root > max_t) {
+        return hit_info;
+    }
+
+    let point = ray.origin + root * ray.dir;
+    let planar = point - corner;
+    let w = n / dot(n, n);
+    let alpha = dot(w, cross(planar, v));
+    let beta = dot(w, cross(u, planar));
+    if alpha < 0.0 || alpha > 1.0 || beta < 0.0 || beta > 1.0 {
+        return hit_info;
+    }
+
+    hit_info.hit = true;
+    hit_info.time = root;
+    hit_info.point = point;
+    hit_info.normal = normal;
+    hit_info.front_face = denom < 0.0;
+    // The image spans the quad, with its bottom left corner on corner and its bottom edge along u.
+    hit_info.uv = vec2(alpha, 1.0 - beta);
+
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = quad.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = quad_idx;
+    return hit_info;
+}
+
+// A disk is the part of its plane within radius of the center.
+fn hit_plane(ray: Ray, plane_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let plane = planes[plane_idx];
+    let normal = plane.normal.xyz;
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    let denom = dot(normal, ray.dir);
+    // The ray is parallel to the plane.
+    if abs(denom) < 1e-8 {
+        return hit_info;
+    }
+
+    let root = dot(normal, plane.point.xyz - ray.origin) / denom;
+    if !in_range(root, min_t, max_t) {
+        return hit_info;
+    }
+
+    let point = ray.origin + root * ray.dir;
+    let offset = point - plane.point.xyz;
+    if plane.radius > 0.0 && dot(offset, offset) > plane.radius * plane.radius {
+        return hit_info;
+    }
+
+    hit_info.hit = true;
+    hit_info.time = root;
+    hit_info.point = point;
+    hit_info.normal = normal;
+    hit_info.front_face = denom < 0.0;
+
+    // Shade the back face like the front one.
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = plane.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
+
+// The ray is moved to object space without normalizing its direction, so the roots found there
+// are the times along the world space ray.
+fn hit_transformed_sphere(ray: Ray, sphere_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let sphere = transformed_spheres[sphere_idx];
+    let origin = (sphere.world_to_object * vec4(ray.origin, 1.0)).xyz;
+    let dir = (sphere.world_to_object * vec4(ray.dir, 0.0)).xyz;
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    let a = dot(dir, dir);
+    let h = -dot(dir, origin);
+    let c = dot(origin, origin) - sphere.radius * sphere.radius;
+    let discriminant = h * h - a * c;
+    if discriminant < 0.0 {
+        return hit_info;
+    }
+
+    let sqrt_disc = sqrt(discriminant);
+    var root = (h - sqrt_disc) / a;
+    if !in_range(root, min_t, max_t) {
+        root = (h + sqrt_disc) / a;
+        if !in_range(root, min_t, max_t) {
+            return hit_info;
+        }
+    }
+
+    // Normals go back through the inverse transpose of the transform, which is the transpose of
+    // world_to_object. This keeps them perpendicular to the surface under a non-uniform scale.
+    let linear = mat3x3(
+        sphere.world_to_object[0].xyz,
+        sphere.world_to_object[1].xyz,
+        sphere.world_to_object[2].xyz,
+    );
+    let local_normal = origin + root * dir;
+
+    hit_info.hit = true;
+    // Before the transform, so that the image follows the rotation and the scale.
+    hit_info.uv = sphere_uv(local_normal / sphere.radius);
+    hit_info.time = root;
+    hit_info.point = ray.origin + root * ray.dir;
+    hit_info.normal = normalize(transpose(linear) * local_normal);
+    hit_info.front_face = dot(hit_info.normal, ray.dir) < 0.0;
+
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = sphere.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
+
+fn in_range(t: f32, min_t: f32, max_t: f32) -> bool {
+    return (min_t == -1.0 || t >= min_t) && (max_t == -1.0 || t <= max_t);
+}
+
+// Closest of the curved side, between the two caps, and of the two cap disks.
+fn hit_cylinder(ray: Ray, cylinder_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let cylinder = cylinders[cylinder_idx];
+    let axis = cylinder.axis.xyz;
+    let oc = ray.origin - cylinder.base.xyz;
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    // The side is a circle once the axis component is removed.
+    let dir_along = dot(ray.dir, axis);
+    let oc_along = dot(oc, axis);
+    let dir_perp = ray.dir - dir_along * axis;
+    let oc_perp = oc - oc_along * axis;
+    let a = dot(dir_perp, dir_perp);
+    let half_b = dot(dir_perp, oc_perp);
+    let c = dot(oc_perp, oc_perp) - cylinder.radius * cylinder.radius;
+    let discriminant = half_b * half_b - a * c;
+
+    var root = -1.0;
+    var normal: vec3<f32>;
+    if a > 1e-8 && discriminant >= 0.0 {
+        let sqrtd = sqrt(discriminant);
+        for (var k = 0u; k < 2u; k++) {
+            let t = select((-half_b + sqrtd) / a, (-half_b - sqrtd) / a, k == 0u);
+            let height = oc_along + t * dir_along;
+            if in_range(t, min_t, max_t) && height >= 0.0 && height <= cylinder.height {
+                root = t;
+                normal = (oc_perp + t * dir_perp) / cylinder.radius;
+                break;
+            }
+        }
+    }
+
+    if abs(dir_along) > 1e-8 {
+        for (var k = 0u; k < 2u; k++) {
+            let cap_height = select(cylinder.height, 0.0, k == 0u);
+            let t = (cap_height - oc_along) / dir_along;
+            let offset = oc_perp + t * dir_perp;
+            let closer = root < 0.0 || t < root;
+            if closer && in_range(t, min_t, max_t)
+                && dot(offset, offset) <= cylinder.radius * cylinder.radius {
+                root = t;
+                normal = select(axis, -axis, k == 0u);
+            }
+        }
+    }
+
+    if root < 0.0 {
+        return hit_info;
+    }
+
+    hit_info.hit = true;
+    hit_info.time = root;
+    hit_info.point = ray.origin + root * ray.dir;
+    hit_info.normal = normal;
+    hit_info.front_face = dot(normal, ray.dir) < 0.0;
+
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = cylinder.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
+
+// Slab test: intersect the ray with the three pairs of planes bounding the box.
+fn hit_box(ray: Ray, box_idx: u32, min_t: f32, max_t: f32) -> HitInfo {
+    let aabb = boxes[box_idx];
+
+    var hit_info: HitInfo;
+    hit_info.hit = false;
+
+    let inv_dir = 1.0 / ray.dir;
+    let t0 = (aabb.min.xyz - ray.origin) * inv_dir;
+    let t1 = (aabb.max.xyz - ray.origin) * inv_dir;
+    let t_small = min(t0, t1);
+    let t_big = max(t0, t1);
+    let t_near = max(max(t_small.x, t_small.y), t_small.z);
+    let t_far = min(min(t_big.x, t_big.y), t_big.z);
+
+    if t_near > t_far {
+        return hit_info;
+    }
+
+    // Same as spheres: take the far side when the near one is out of range, e.g. from inside the box.
+    var root = t_near;
+    if (min_t != -1.0 && root < min_t ) || (max_t != -1.0 && root > max_t) {
+        root = t_far;
+        if (min_t != -1.0 && root < min_t ) || (max_t != -1.0 && root > max_t) {
+            return hit_info;
+        }
+    }
+
+    hit_info.hit = true;
+    hit_info.time = root;
+    hit_info.point = ray.origin + root * ray.dir;
+
+    // The face that was hit is the one along which the point is the furthest from the center.
+    let center = 0.5 * (aabb.min.xyz + aabb.max.xyz);
+    let half_size = 0.5 * (aabb.max.xyz - aabb.min.xyz);
+    let local = (hit_info.point - center) / half_size;
+    let dist = abs(local);
+    if dist.x >= dist.y && dist.x >= dist.z {
+        hit_info.normal = vec3(sign(local.x), 0.0, 0.0);
+    } else if dist.y >= dist.z {
+        hit_info.normal = vec3(0.0, sign(local.y), 0.0);
+    } else {
+        hit_info.normal = vec3(0.0, 0.0, sign(local.z));
+    }
+    hit_info.front_face = dot(hit_info.normal, ray.dir) < 0.0;
+
+    if !hit_info.front_face {
+        hit_info.normal = -hit_info.normal;
+    }
+
+    hit_info.material = aabb.material;
+    hit_info.sphere_idx = no_sphere;
+    hit_info.quad_idx = no_quad;
+    return hit_info;
+}
+
+fn scatter(ray: Ray, hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
+    switch hit_info.material.material_type {
+        case 1u: {
+            return metallic_reflect(ray, hit_info, seed);
+        }
+        case 2u: {
+            return dielectric_refract(ray, hit_info, seed);
+        }
+        case 4u: {
+            return brushed_metal_reflect(ray, hit_info, seed);
+        }
+        case 5u: {
+            return pbr_reflect(ray, hit_info, seed);
+        }
+        default: {
+            return lambertian_reflect(hit_info, seed);
+        }
+    }
 }
 
 fn lambertian_reflect(hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
-    var new_dir = hit_info.normal + random_vec3_unit(seed);
-    
-    if dot(new_dir, new_dir) < 1e-16 {
-        new_dir = hit_info.normal;
+    var new_dir: vec3<f32>;
+    if world_data.cosine_sampling != 0u {
+        new_dir = cosine_hemisphere(hit_info.normal, seed);
+    } else {
+        // A point on the unit sphere around the normal also gives a cosine weighted direction.
+        new_dir = hit_info.normal + normalize(random_vec3_unit(seed));
+        if dot(new_dir, new_dir) < 1e-16 {
+            new_dir = hit_info.normal;
+        }
     }
     let ray = Ray(hit_info.point, new_dir);
-    let color = hit_info.material.color;
+    let color = albedo(hit_info);
 
     return ReflectInfo(color, ray);
 }
 
-fn initSeed(pixel: vec2<u32>) -> u32 {
-    // Got it from here https://nelari.us/post/weekend_raytracing_with_wgpu_1/
+// Malley's method: a uniform point on the unit disk lifted onto the hemisphere around the normal.
+fn cosine_hemisphere(normal: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let disk = random_vec2_unit(seed);
+    let z = sqrt(max(0.0, 1.0 - disk.x * disk.x - disk.y * disk.y));
+    return orthonormal_basis(normal) * vec3(disk.x, disk.y, z);
+}
+
+fn albedo(hit_info: HitInfo) -> vec4<f32> {
+    let material = hit_info.material;
+    if material.texture_type == 1u {
+        return checker(hit_info.point, material.color, material.checker_color, material.checker_scale);
+    }
+    if material.texture_type == 2u {
+        // An index past the loaded images reads the last one.
+        let layer = min(material.texture_index, textureNumLayers(image_textures) - 1u);
+        let texel = textureSampleLevel(image_textures, image_sampler, hit_info.uv, layer, 0.0);
+        return vec4(texel.xyz * material.color.xyz, 1.0);
+    }
+    return material.color;
+}
+
+// Longitude along u from -x around y, latitude along v from the top. Takes the outward unit normal.
+fn sphere_uv(normal: vec3<f32>) -> vec2<f32> {
+    let phi = atan2(-normal.z, normal.x) + pi;
+    let theta = acos(clamp(-normal.y, -1.0, 1.0));
+    return vec2(phi / (2.0 * pi), 1.0 - theta / pi);
+}
+
+// 3D checker: the space is cut in cubes of side scale, alternating between the two colors.
+fn checker(point: vec3<f32>, color_a: vec4<f32>, color_b: vec4<f32>, scale: f32) -> vec4<f32> {
+    let cell = floor(point / scale);
+    // 0 when the sum of the cell coordinates is even, 0.5 when odd, negative cells included.
+    let parity = fract(0.5 * (cell.x + cell.y + cell.z));
+    return select(color_a, color_b, parity > 0.25);
+}
+
+fn metallic_reflect(ray: Ray, hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
+    let reflected = reflect(normalize(ray.dir), hit_info.normal);
+    let new_dir = reflected + hit_info.material.fuzz * random_vec3_unit(seed);
+    let new_ray = Ray(hit_info.point, new_dir);
+    var color = hit_info.material.color;
+    // The fuzz can push the ray below the surface, the metal absorbs it then.
+    if dot(new_dir, hit_info.normal) <= 0.0 {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+    }
+
+    return ReflectInfo(color, new_ray);
+}
+
+// Anisotropic GGX: the half vector is drawn from the distribution of microfacet normals, the
+// Fresnel and shadowing terms are left in the weight. Everything is done in the tangent frame.
+fn brushed_metal_reflect(ray: Ray, hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
+    let material = hit_info.material;
+    let n = hit_info.normal;
+    // Brushed around the y axis, along x where the surface is horizontal.
+    var t = cross(vec3(0.0, 1.0, 0.0), n);
+    if dot(t, t) < 1e-6 {
+        t = vec3(1.0, 0.0, 0.0) - n * n.x;
+    }
+    t = normalize(t);
+    let frame = mat3x3(t, cross(n, t), n);
+    let roughness = vec2(material.roughness_u, material.roughness_v);
+    return ggx_reflect(ray, hit_info.point, frame, roughness, material.color.xyz, seed);
+}
+
+// frame takes the tangent space to world space, its last column is the normal. f0 is the
+// reflectance at normal incidence.
+fn ggx_reflect(
+    ray: Ray,
+    point: vec3<f32>,
+    frame: mat3x3<f32>,
+    roughness: vec2<f32>,
+    f0: vec3<f32>,
+    seed: ptr<function, u32>
+) -> ReflectInfo {
+    let alpha = max(roughness * roughness, vec2(1e-4));
+    let v = transpose(frame) * -normalize(ray.dir);
+    let h = sample_ggx_normal(alpha, seed);
+    let l = reflect(-v, h);
+    let new_ray = Ray(point, frame * l);
+
+    let v_dot_h = dot(v, h);
+    // The microfacet sends the ray below the surface, the metal absorbs it then.
+    if l.z <= 0.0 || v.z <= 0.0 || v_dot_h <= 0.0 {
+        return ReflectInfo(vec4(0.0, 0.0, 0.0, 1.0), new_ray);
+    }
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - v_dot_h, 5.0);
+    let shadowing = ggx_smith_g1(v, alpha) * ggx_smith_g1(l, alpha);
+    // BRDF * cos / pdf, where the pdf of l is D(h) cos(h) / (4 v.h).
+    let weight = fresnel * shadowing * v_dot_h / (v.z * h.z);
+    return ReflectInfo(vec4(weight, 1.0), new_ray);
+}
+
+// Metallic/roughness workflow: a GGX specular layer over a Lambertian base. Dielectrics reflect 4%
+// at normal incidence, metals tint the reflection with the albedo and have no base. Each bounce
+// follows one of the two lobes, picked in proportion to their estimated contribution.
+fn pbr_reflect(ray: Ray, hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
+    let material = hit_info.material;
+    let lobes = pbr_lobes(ray, hit_info);
+    took_diffuse_lobe = random_range_f32(0.0, 1.0, seed) >= 1.0 - lobes.w;
+    if !took_diffuse_lobe {
+        let f0 = mix(vec3(0.04), albedo(hit_info).xyz, material.fuzz);
+        let frame = orthonormal_basis(hit_info.normal);
+        let roughness = vec2(material.roughness_u, material.roughness_v);
+        var reflected = ggx_reflect(ray, hit_info.point, frame, roughness, f0, seed);
+        reflected.color = vec4(reflected.color.xyz / (1.0 - lobes.w), 1.0);
+        return reflected;
+    }
+    let new_ray = Ray(hit_info.point, cosine_hemisphere(hit_info.normal, seed));
+    return ReflectInfo(vec4(lobes.xyz / lobes.w, 1.0), new_ray);
+}
+
+// Albedo of the Lambertian base of a Pbr surface seen along the ray, and in w the probability with
+// which pbr_reflect follows it rather than the specular layer.
+fn pbr_lobes(ray: Ray, hit_info: HitInfo) -> vec4<f32> {
+    let base_color = albedo(hit_info).xyz;
+    let metallic = hit_info.material.fuzz;
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let cos_view = max(dot(-normalize(ray.dir), hit_info.normal), 0.0);
+    let fresnel = f0 + (1.0 - f0) * pow(1.0 - cos_view, 5.0);
+    let diffuse = base_color * (1.0 - metallic) * (1.0 - fresnel);
+
+    let luminance = vec3(0.2126, 0.7152, 0.0722);
+    let specular_weight = dot(fresnel, luminance);
+    let specular_probability = specular_weight / max(specular_weight + dot(diffuse, luminance), 1e-6);
+    return vec4(diffuse, 1.0 - specular_probability);
+}
 
-    let seed = dot(pixel, vec2<u32>(1u, world_data.window_width)) ^ jenkins_hash(world_data.frame);
-    return jenkins_hash(seed);
+// Microfacet normal in the tangent frame, with a density of D(h) cos(h).
+fn sample_ggx_normal(alpha: vec2<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    let u1 = random_range_f32(0.0, 1.0, seed);
+    let u2 = random_range_f32(0.0, 0.999, seed);
+    let phi = atan2(alpha.y * sin(2.0 * pi * u1), alpha.x * cos(2.0 * pi * u1));
+    let cos_phi = cos(phi);
+    let sin_phi = sin(phi);
+    let inv_alpha2 = cos_phi * cos_phi / (alpha.x * alpha.x) + sin_phi * sin_phi / (alpha.y * alpha.y);
+    let tan2_theta = u2 / ((1.0 - u2) * inv_alpha2);
+    let cos_theta = 1.0 / sqrt(1.0 + tan2_theta);
+    let sin_theta = sqrt(max(0.0, 1.0 - cos_theta * cos_theta));
+    return vec3(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
 }
 
-fn jenkins_hash(input: u32) -> u32 {
-    // got it from here too:
-    // https://nelari.us/post/weekend_raytracing_with_wgpu_1/
-    var x = input;
-    x += x << 10u;
-    x ^= x >> 6u;
-    x += x << 3u;
-    x ^= x >> 11u;
-    x += x << 15u;
-    return x;
+// Smith masking of one direction in the tangent frame.
+fn ggx_smith_g1(w: vec3<f32>, alpha: vec2<f32>) -> f32 {
+    let a2 = (alpha.x * alpha.x * w.x * w.x + alpha.y * alpha.y * w.y * w.y) / (w.z * w.z);
+    return 2.0 / (1.0 + sqrt(1.0 + a2));
+}
+
+fn dielectric_refract(ray: Ray, hit_info: HitInfo, seed: ptr<function, u32>) -> ReflectInfo {
+    // Going from the outside to the inside of the object, or the other way around.
+    var ri = hit_info.material.refraction_index;
+    if hit_info.front_face {
+        ri = 1.0 / ri;
+    }
+
+    let unit_dir = normalize(ray.dir);
+    let cos_theta = min(dot(-unit_dir, hit_info.normal), 1.0);
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+
+    // Snell's law has no solution past the critical angle: total internal reflection.
+    let cannot_refract = ri * sin_theta > 1.0;
+
+    var new_dir: vec3<f32>;
+    if cannot_refract || schlick_reflectance(cos_theta, ri) > random_range_f32(0.0, 1.0, seed) {
+        new_dir = reflect(unit_dir, hit_info.normal);
+    } else {
+        new_dir = refract(unit_dir, hit_info.normal, ri);
+    }
+    let new_ray = Ray(hit_info.point, new_dir);
+    let color = hit_info.material.color;
+
+    return ReflectInfo(color, new_ray);
+}
+
+fn schlick_reflectance(cosine: f32, ri: f32) -> f32 {
+    var r0 = (1.0 - ri) / (1.0 + ri);
+    r0 = r0 * r0;
+    return r0 + (1.0 - r0) * pow(1.0 - cosine, 5.0);
+}
+
+// Every sample of every pixel gets its own sequence, which only depends on the uniform.
+fn init_seed(pixel: vec2<u32>, sample_index: u32) -> u32 {
+    var seed = pcg_hash(world_data.seed);
+    seed = pcg_hash(seed + pixel.x);
+    seed = pcg_hash(seed + pixel.y);
+    seed = pcg_hash(seed + world_data.frame);
+    return pcg_hash(seed + sample_index);
+}
+
+// Shared by every pixel of a frame: a scramble that differs between neighbouring pixels would
+// read unrelated values of the tile and turn the blue noise back into white noise.
+fn init_noise_scramble(sample_index: u32) -> u32 {
+    let seed = pcg_hash(pcg_hash(world_data.seed) + world_data.frame);
+    return pcg_hash(seed + sample_index);
+}
+
+// PCG hash from "Hash Functions for GPU Rendering", Jarzynski and Olano 2020.
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
 }
 
 fn random_u32(seed: ptr<function, u32>) -> u32 {
-    let hash = jenkins_hash(*seed);
+    let hash = pcg_hash(*seed);
     *seed = hash;
     return hash;
 }
 
 fn random_range_f32(min: f32, max: f32, seed: ptr<function, u32>) -> f32 {
+    if noise_dimension < blue_noise_dimensions {
+        let unit = blue_noise(noise_dimension);
+        noise_dimension++;
+        return min + (max - min) * unit;
+    }
     let n = f32(random_u32(seed));
     return min + (max - min) * (n / f32(0xffffffffu));
 }
 
+// Each dimension of each sample reads the tile shifted and rotated by a random amount, so that the
+// dimensions and the frames stay independent. The shift and the rotation are the same for every
+// pixel, so neighbouring pixels still get spread out values.
+fn blue_noise(dimension: u32) -> f32 {
+    let hash = pcg_hash(noise_scramble ^ pcg_hash(dimension));
+    let size = textureDimensions(blue_noise_tile);
+    let coords = (noise_pixel + vec2(hash, hash >> 16u)) % size;
+    let value = textureLoad(blue_noise_tile, coords, 0).x;
+    return fract(value + f32(pcg_hash(hash) >> 8u) / 16777216.0);
+}
+
 
 // The reason we do not do rejection method is that it is highly incompatible
 // the way a gpu does multithreading: if a simple thread does 20 iterations to find a given vector, then *all* the concurrent threads (of the workgroup atleast) will get stuck waiting for it.
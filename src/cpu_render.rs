@@ -0,0 +1,248 @@
+use crate::material::Material;
+use crate::math::*;
+use crate::world_data::WorldData;
+
+// Slow reference path tracer, written apart from compute.wgsl so that the two can be compared.
+// It only knows the static spheres, the sky gradient and the Lambertian, metallic, dielectric and
+// emissive materials, anything else shades as Lambertian. It skips light sampling and Russian
+// roulette, which do not change the expected image, and leaves the tone mapping out.
+
+// Same as the shader, keeps the scattered rays from hitting the surface they start on.
+const MIN_T: f32 = 0.01;
+
+struct Hit {
+    time: f32,
+    point: Point4,
+    // Against the ray, like in the shader.
+    normal: Vec4,
+    front_face: bool,
+    material: Material,
+}
+
+// xorshift64*, the sequences have nothing to do with the shader's ones.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Splitmix the seed so that 0 and close seeds still give unrelated non-zero states.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    // Uniform in [0, 1).
+    fn next_f32(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn in_unit_disk(&mut self) -> [f32; 2] {
+        let r = self.next_f32().sqrt();
+        let theta = 2.0 * std::f32::consts::PI * self.next_f32();
+        [r * theta.cos(), r * theta.sin()]
+    }
+
+    fn unit_vector(&mut self) -> Vec4 {
+        let z = 1.0 - 2.0 * self.next_f32();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f32::consts::PI * self.next_f32();
+        [r * phi.cos(), r * phi.sin(), z, 0.0]
+    }
+
+    fn in_unit_ball(&mut self) -> Vec4 {
+        scale(self.next_f32().cbrt(), self.unit_vector())
+    }
+}
+
+fn mul(v: Vec4, w: Vec4) -> Vec4 {
+    [v[0] * w[0], v[1] * w[1], v[2] * w[2], v[3] * w[3]]
+}
+
+fn reflect(v: Vec4, n: Vec4) -> Vec4 {
+    sub(v, scale(2.0 * dot(v, n), n))
+}
+
+// v is a unit vector, ratio the refraction index before over the one after the surface.
+fn refract(v: Vec4, n: Vec4, ratio: f32) -> Vec4 {
+    let cos_theta = dot(scale(-1.0, v), n).min(1.0);
+    let perpendicular = scale(ratio, add(v, scale(cos_theta, n)));
+    let parallel = scale(-(1.0 - dot(perpendicular, perpendicular)).abs().sqrt(), n);
+    add(perpendicular, parallel)
+}
+
+fn schlick_reflectance(cosine: f32, ratio: f32) -> f32 {
+    let r0 = ((1.0 - ratio) / (1.0 + ratio)).powi(2);
+    r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
+}
+
+fn hit_spheres(world_data: &WorldData, origin: Point4, dir: Vec4) -> Option<Hit> {
+    let mut closest: Option<Hit> = None;
+    for (sphere, material) in world_data.iter_spheres() {
+        let v = sub(sphere, origin);
+        let a = dot(dir, dir);
+        let h = dot(dir, v);
+        let c = dot(v, v) - sphere[3] * sphere[3];
+        let discriminant = h * h - a * c;
+        if discriminant < 0.0 {
+            continue;
+        }
+        let sqrt_disc = discriminant.sqrt();
+        let limit = closest.as_ref().map_or(f32::INFINITY, |hit| hit.time);
+        let Some(time) = [(h - sqrt_disc) / a, (h + sqrt_disc) / a]
+            .into_iter()
+            .find(|t| *t >= MIN_T && *t < limit)
+        else {
+            continue;
+        };
+        let point = add(origin, scale(time, dir));
+        let outward = scale(1.0 / sphere[3], sub(point, sphere));
+        let front_face = dot(outward, dir) < 0.0;
+        closest = Some(Hit {
+            time,
+            point,
+            normal: if front_face {
+                outward
+            } else {
+                scale(-1.0, outward)
+            },
+            front_face,
+            material,
+        });
+    }
+    closest
+}
+
+fn albedo(hit: &Hit) -> Vec4 {
+    let material = &hit.material;
+    if material.texture_type != 1 {
+        return material.color;
+    }
+    let cell: f32 = (0..3)
+        .map(|axis| (hit.point[axis] / material.checker_scale).floor())
+        .sum();
+    if cell.rem_euclid(2.0) < 0.5 {
+        material.color
+    } else {
+        material.checker_color
+    }
+}
+
+// Attenuation and new direction of the ray.
+fn scatter(hit: &Hit, dir: Vec4, rng: &mut Rng) -> (Vec4, Vec4) {
+    let material = &hit.material;
+    match material.material_type {
+        1 => {
+            let reflected = reflect(normalize(dir), hit.normal);
+            let new_dir = add(reflected, scale(material.fuzz, rng.in_unit_ball()));
+            if dot(new_dir, hit.normal) <= 0.0 {
+                return ([0.0; 4], new_dir);
+            }
+            (material.color, new_dir)
+        }
+        2 => {
+            let ratio = if hit.front_face {
+                1.0 / material.refraction_index
+            } else {
+                material.refraction_index
+            };
+            let unit_dir = normalize(dir);
+            let cos_theta = dot(scale(-1.0, unit_dir), hit.normal).min(1.0);
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            let cannot_refract = ratio * sin_theta > 1.0;
+            let new_dir =
+                if cannot_refract || schlick_reflectance(cos_theta, ratio) > rng.next_f32() {
+                    reflect(unit_dir, hit.normal)
+                } else {
+                    refract(unit_dir, hit.normal, ratio)
+                };
+            (material.color, new_dir)
+        }
+        _ => {
+            let mut new_dir = add(hit.normal, rng.unit_vector());
+            if dot(new_dir, new_dir) < 1e-16 {
+                new_dir = hit.normal;
+            }
+            (albedo(hit), new_dir)
+        }
+    }
+}
+
+fn trace(world_data: &WorldData, mut origin: Point4, mut dir: Vec4, rng: &mut Rng) -> Vec4 {
+    let mut radiance = [0.0; 4];
+    let mut throughput = [1.0; 4];
+    let mut diffuse_bounces = 0;
+    for _ in 0..world_data.max_depth() {
+        let Some(hit) = hit_spheres(world_data, origin, dir) else {
+            return add(radiance, mul(throughput, world_data.sky_color(dir)));
+        };
+        let material = &hit.material;
+        if material.material_type == 2 && !hit.front_face {
+            let distance = hit.time * norm(dir);
+            let transmittance = material.absorption.map(|a| (-a * distance).exp());
+            throughput = mul(throughput, transmittance);
+        }
+        if material.material_type == 3 {
+            let emitted = scale(material.fuzz, material.color);
+            return add(radiance, mul(throughput, emitted));
+        }
+        let emission = scale(material.emission[3], material.emission);
+        radiance = add(radiance, mul(throughput, emission));
+
+        // Like the shader, only the bounces off the other materials count against this limit.
+        if material.material_type != 2 {
+            diffuse_bounces += 1;
+            if diffuse_bounces >= world_data.max_diffuse_depth() {
+                break;
+            }
+        }
+        let (attenuation, new_dir) = scatter(&hit, dir, rng);
+        throughput = mul(throughput, attenuation);
+        if xyz(throughput) == [0.0; 3] {
+            break;
+        }
+        origin = hit.point;
+        dir = new_dir;
+    }
+    radiance
+}
+
+fn encode_srgb(linear: f32) -> u8 {
+    let linear = linear.clamp(0.0, 1.0);
+    let srgb = if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+// RGBA8 image of the window size set on world_data, sRGB encoded like WgpuState::read_pixels.
+// The same seed gives the same image.
+pub fn render(world_data: &WorldData, samples: u32, seed: u64) -> Vec<u8> {
+    let (width, height) = world_data.window_size();
+    let samples = samples.max(1);
+    let mut rng = Rng::new(seed);
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0; 4];
+            for _ in 0..samples {
+                let (dx, dy) = if world_data.antialiasing() {
+                    (rng.next_f32() - 0.5, rng.next_f32() - 0.5)
+                } else {
+                    (0.0, 0.0)
+                };
+                let lens = rng.in_unit_disk();
+                let (origin, dir) = world_data.camera_ray(x as f32 + dx, y as f32 + dy, lens);
+                sum = add(sum, trace(world_data, origin, dir, &mut rng));
+            }
+            let color = scale(1.0 / samples as f32, sum);
+            pixels.extend(xyz(color).map(encode_srgb));
+            pixels.push(255);
+        }
+    }
+    pixels
+}
@@ -0,0 +1,73 @@
+// One iteration of the edge-avoiding à trous wavelet filter (Dammertz et al. 2010): a 5x5 B3 spline
+// kernel whose taps are tap_spacing pixels apart, weighted down across color, normal and albedo edges.
+override workgroup_size: u32;
+// Doubled at each iteration, so that a few small kernels cover a wide footprint.
+override tap_spacing: i32;
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+@group(0) @binding(1)
+var output: texture_storage_2d<bgra8unorm, write>;
+// Albedo and normal of the first hit, zero for the background.
+@group(0) @binding(2)
+var albedo_buffer: texture_2d<f32>;
+@group(0) @binding(3)
+var normal_buffer: texture_2d<f32>;
+
+// Color difference still treated as noise by the first iteration, the later ones halve it as the
+// image gets smoother.
+const color_sigma = 0.5;
+const normal_sigma = 0.2;
+const albedo_sigma = 0.1;
+
+@compute @workgroup_size(workgroup_size, workgroup_size, 1)
+fn main_denoise(@builtin(global_invocation_id) global_invocation_id: vec3<u32>) {
+    let size = vec2<i32>(textureDimensions(input));
+    let coords = vec2<i32>(global_invocation_id.xy);
+    if coords.x >= size.x || coords.y >= size.y {
+        return;
+    }
+
+    let color = textureLoad(input, coords, 0).xyz;
+    let normal = textureLoad(normal_buffer, coords, 0).xyz;
+    let albedo = textureLoad(albedo_buffer, coords, 0).xyz;
+    let sigma = color_sigma / f32(tap_spacing);
+
+    var sum = vec3(0.0, 0.0, 0.0);
+    var total_weight = 0.0;
+    for (var dy = -2; dy <= 2; dy++) {
+        for (var dx = -2; dx <= 2; dx++) {
+            let tap = coords + vec2(dx, dy) * tap_spacing;
+            if any(tap < vec2(0)) || any(tap >= size) {
+                continue;
+            }
+            let tap_color = textureLoad(input, tap, 0).xyz;
+            let color_diff = tap_color - color;
+            let normal_diff = textureLoad(normal_buffer, tap, 0).xyz - normal;
+            let albedo_diff = textureLoad(albedo_buffer, tap, 0).xyz - albedo;
+            let edge = dot(color_diff, color_diff) / (sigma * sigma)
+                + dot(normal_diff, normal_diff) / (normal_sigma * normal_sigma)
+                + dot(albedo_diff, albedo_diff) / (albedo_sigma * albedo_sigma);
+            let weight = kernel_weight(dx) * kernel_weight(dy) * exp(-edge);
+            sum += tap_color * weight;
+            total_weight += weight;
+        }
+    }
+    // The center tap always counts, total_weight is never 0.
+    textureStore(output, coords, vec4(sum / total_weight, 1.0));
+}
+
+// 1D B3 spline: 1/16, 1/4, 3/8, 1/4, 1/16.
+fn kernel_weight(offset: i32) -> f32 {
+    switch abs(offset) {
+        case 0: {
+            return 0.375;
+        }
+        case 1: {
+            return 0.25;
+        }
+        default: {
+            return 0.0625;
+        }
+    }
+}
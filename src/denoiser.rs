@@ -0,0 +1,165 @@
+use wgpu::{BindGroup, Extent3d, Texture, TextureViewDescriptor};
+
+// The taps of the last iteration are 2^(ITERATIONS - 1) pixels apart. Even, so that the result
+// ends up back in the output texture after going through the scratch one.
+const ITERATIONS: u32 = 4;
+
+// Edge-avoiding à trous filter run over the output texture after the trace, see denoise.wgsl.
+// It only smooths what is displayed, the accumulation keeps the noisy samples.
+pub struct Denoiser {
+    bind_group_layout: wgpu::BindGroupLayout,
+    // One per iteration, they only differ by the spacing of the taps.
+    pipelines: Vec<wgpu::ComputePipeline>,
+    workgroup_size: u32,
+    // Same size and format as the output texture.
+    scratch_texture: Texture,
+    // From the output texture to the scratch one and back, None once the textures are recreated.
+    bind_groups: Option<[BindGroup; 2]>,
+}
+
+fn create_scratch_texture(device: &wgpu::Device, size: Extent3d) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Bgra8Unorm,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        label: Some("denoise_scratch"),
+        view_formats: &[],
+    })
+}
+
+fn texture_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+impl Denoiser {
+    pub fn new(device: &wgpu::Device, size: Extent3d, workgroup_size: u32) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("denoise.wgsl"));
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Denoise Bind Group Layout"),
+            entries: &[
+                // Input color
+                texture_layout_entry(0),
+                // Output color
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        format: wgpu::TextureFormat::Bgra8Unorm,
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                    },
+                    count: None,
+                },
+                // Albedo and normal of the first hit
+                texture_layout_entry(2),
+                texture_layout_entry(3),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Denoise Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipelines = (0..ITERATIONS)
+            .map(|i| {
+                device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("Denoise Pipeline"),
+                    layout: Some(&pipeline_layout),
+                    module: &shader,
+                    entry_point: Some("main_denoise"),
+                    compilation_options: wgpu::PipelineCompilationOptions {
+                        constants: &[
+                            ("workgroup_size", workgroup_size as f64),
+                            ("tap_spacing", (1 << i) as f64),
+                        ],
+                        ..Default::default()
+                    },
+                    cache: None,
+                })
+            })
+            .collect();
+
+        Self {
+            bind_group_layout,
+            pipelines,
+            workgroup_size,
+            scratch_texture: create_scratch_texture(device, size),
+            bind_groups: None,
+        }
+    }
+
+    // Must be called whenever the output, albedo or normal textures are recreated.
+    pub fn resize(&mut self, device: &wgpu::Device, size: Extent3d) {
+        self.scratch_texture = create_scratch_texture(device, size);
+        self.bind_groups = None;
+    }
+
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        output: &Texture,
+        albedo: &Texture,
+        normal: &Texture,
+    ) {
+        let bind_groups = self.bind_groups.get_or_insert_with(|| {
+            let create = |input: &Texture, output: &Texture| {
+                let view =
+                    |texture: &Texture| texture.create_view(&TextureViewDescriptor::default());
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Denoise Bind Group"),
+                    layout: &self.bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&view(input)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&view(output)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&view(albedo)),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: wgpu::BindingResource::TextureView(&view(normal)),
+                        },
+                    ],
+                })
+            };
+            [
+                create(output, &self.scratch_texture),
+                create(&self.scratch_texture, output),
+            ]
+        });
+
+        let size = output.size();
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Denoise Pass"),
+            timestamp_writes: None,
+        });
+        for (i, pipeline) in self.pipelines.iter().enumerate() {
+            compute_pass.set_pipeline(pipeline);
+            compute_pass.set_bind_group(0, &bind_groups[i % 2], &[]);
+            compute_pass.dispatch_workgroups(
+                size.width.div_ceil(self.workgroup_size),
+                size.height.div_ceil(self.workgroup_size),
+                1,
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Two timestamps: the beginning and the end of the compute pass.
+const QUERY_COUNT: u32 = 2;
+const QUERY_BUFFER_SIZE: u64 = QUERY_COUNT as u64 * wgpu::QUERY_SIZE as u64;
+
+// Measures the duration of the compute pass with timestamp queries.
+// Only one measurement is in flight at a time, so reading it back never stalls the render loop:
+// frames rendered while the previous result is being mapped are simply not measured.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    // Nanoseconds per timestamp tick.
+    period: f32,
+    pending: bool,
+    ready: Arc<AtomicBool>,
+    last_time_ms: Option<f32>,
+}
+
+impl GpuTimer {
+    // Returns None when the device was created without TIMESTAMP_QUERY.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Compute Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: QUERY_COUNT,
+        });
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size: QUERY_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size: QUERY_BUFFER_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            pending: false,
+            ready: Arc::new(AtomicBool::new(false)),
+            last_time_ms: None,
+        })
+    }
+
+    // None while the previous measurement is still being read back.
+    pub fn compute_pass_writes(&self) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        if self.pending {
+            return None;
+        }
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(0),
+            end_of_pass_write_index: Some(1),
+        })
+    }
+
+    // Must be called after the compute pass, in the same encoder.
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.pending {
+            return;
+        }
+        encoder.resolve_query_set(&self.query_set, 0..QUERY_COUNT, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            QUERY_BUFFER_SIZE,
+        );
+    }
+
+    // Must be called once the encoder holding the resolve has been submitted.
+    pub fn after_submit(&mut self) {
+        if self.pending {
+            return;
+        }
+        self.pending = true;
+        let ready = self.ready.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                if result.is_ok() {
+                    ready.store(true, Ordering::Release);
+                }
+            });
+    }
+
+    // Pick up the measurement once the readback buffer is mapped.
+    pub fn poll(&mut self) {
+        if !self.ready.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let mapped = self.readback_buffer.slice(..).get_mapped_range();
+        let timestamps: &[u64] = bytemuck::cast_slice(&mapped);
+        let ticks = timestamps[1].wrapping_sub(timestamps[0]);
+        self.last_time_ms = Some(ticks as f32 * self.period / 1_000_000.0);
+        drop(mapped);
+        self.readback_buffer.unmap();
+        self.pending = false;
+    }
+
+    pub fn last_time_ms(&self) -> Option<f32> {
+        self.last_time_ms
+    }
+}
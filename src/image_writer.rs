@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::thread::JoinHandle;
+
+// Encodes and writes PNG images on a worker thread, so that saving a frame does not stall the
+// render loop. Dropping it waits for the images still queued.
+pub struct ImageWriter {
+    // Taken when finishing, closing the channel lets the worker return.
+    sender: Option<Sender<Image>>,
+    // Returns how many images could not be saved.
+    worker: Option<JoinHandle<usize>>,
+}
+
+struct Image {
+    path: PathBuf,
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl ImageWriter {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel::<Image>();
+        let worker = std::thread::spawn(move || {
+            let mut failures = 0;
+            for image in receiver {
+                let result = image::save_buffer(
+                    &image.path,
+                    &image.rgba,
+                    image.width,
+                    image.height,
+                    image::ExtendedColorType::Rgba8,
+                );
+                match result {
+                    Ok(()) => println!("Saved {}", image.path.display()),
+                    Err(err) => {
+                        eprintln!("Failed to save {}: {err}", image.path.display());
+                        failures += 1;
+                    }
+                }
+            }
+            failures
+        });
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+        }
+    }
+
+    // rgba is tightly packed like WgpuState::read_pixels returns it. Returns at once, the result
+    // is printed once the file is written.
+    pub fn save(&self, path: PathBuf, rgba: Vec<u8>, width: u32, height: u32) {
+        let image = Image {
+            path,
+            rgba,
+            width,
+            height,
+        };
+        let sender = self
+            .sender
+            .as_ref()
+            .expect("the writer is only finished once");
+        if let Err(mpsc::SendError(image)) = sender.send(image) {
+            eprintln!(
+                "Failed to save {}: the writer thread stopped",
+                image.path.display()
+            );
+        }
+    }
+
+    // Waits for every queued image, returns how many could not be saved.
+    pub fn finish(mut self) -> usize {
+        self.join()
+    }
+
+    fn join(&mut self) -> usize {
+        drop(self.sender.take());
+        match self.worker.take() {
+            // A panicking worker lost at least one image.
+            Some(worker) => worker.join().unwrap_or(1),
+            None => 0,
+        }
+    }
+}
+
+impl Default for ImageWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for ImageWriter {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
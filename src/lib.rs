@@ -0,0 +1,14 @@
+mod blue_noise;
+mod bvh;
+pub mod cpu_render;
+mod denoiser;
+mod gpu_timer;
+pub mod image_writer;
+pub mod material;
+pub mod math;
+pub mod scene;
+pub mod scene_builder;
+#[cfg(debug_assertions)]
+mod shader_reload;
+pub mod state;
+pub mod world_data;
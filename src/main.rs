@@ -1,48 +1,340 @@
-mod material;
-mod math;
-mod state;
-mod world_data;
+mod cli;
 
-use state::WgpuState;
-use world_data::WorldData;
+use cli::{HeadlessArgs, TurntableArgs};
+use gpu_raytracing::image_writer::ImageWriter;
+use gpu_raytracing::math::xyz;
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::{self, CameraType, WorldData};
 
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use wgpu::PresentMode;
 use winit::{
     application::ApplicationHandler,
-    event::WindowEvent,
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{
+        DeviceEvent, DeviceId, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent,
+    },
     event_loop::{ActiveEventLoop, ControlFlow, EventLoop},
-    window::{Window, WindowId},
+    keyboard::{KeyCode, PhysicalKey},
+    window::{Fullscreen, Window, WindowAttributes, WindowId},
 };
 
-use log::{log, Level};
+// Distance travelled per key press, in world units.
+const CAMERA_SPEED: f32 = 0.1;
+// Radians turned per pixel of mouse motion.
+const MOUSE_SENSITIVITY: f32 = 0.003;
+// Degrees of field of view per line of mouse wheel scrolling.
+const ZOOM_SPEED: f32 = 2.0;
+// Pixels of touchpad scrolling counted as one line.
+const PIXELS_PER_LINE: f32 = 20.0;
+// Stops of exposure per key press.
+const EXPOSURE_STEP: f32 = 0.5;
+// Factor applied to the strength of every light per key press.
+const LIGHT_INTENSITY_STEP: f32 = 1.25;
 
-use crate::material::Material;
+// Written by F5 and read back by F9.
+const CAMERA_FILE: &str = "camera.json";
+// Written by F6, pass it as the scene argument to open it again.
+const SCENE_FILE: &str = "scene.ron";
+
+// Number of frames the FPS shown in the title is averaged over.
+const FPS_WINDOW: usize = 30;
+// Frames without any change after which the camera counts as still.
+const STILL_FRAMES: u32 = 10;
+// Upper bound of the compute passes per presented frame set with the page keys.
+const MAX_PASSES_PER_FRAME: u32 = 64;
+// Samples per pixel at which the accumulation is reported as converging.
+const SAMPLE_MILESTONES: [u64; 2] = [100, 1000];
+
+// Wall-clock duration of the last few frames.
+struct FrameTimes {
+    last_frame: Instant,
+    durations: VecDeque<Duration>,
+}
+
+impl FrameTimes {
+    fn new() -> Self {
+        Self {
+            last_frame: Instant::now(),
+            durations: VecDeque::with_capacity(FPS_WINDOW),
+        }
+    }
+
+    // Record a new frame and return the average frame time.
+    fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        if self.durations.len() == FPS_WINDOW {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(now - self.last_frame);
+        self.last_frame = now;
+        self.durations.iter().sum::<Duration>() / self.durations.len() as u32
+    }
+
+    fn last(&self) -> Duration {
+        self.durations.back().copied().unwrap_or_default()
+    }
+}
+
+// Trades samples per pixel for framerate while the camera moves, then ramps them back up once it stops.
+struct AdaptiveSamples {
+    target: Duration,
+    // Reached again once the camera is still, follows the +/- keys.
+    still_samples: u32,
+}
+
+impl AdaptiveSamples {
+    fn new(target_fps: u32, still_samples: u32) -> Self {
+        Self {
+            target: Duration::from_secs(1) / target_fps,
+            still_samples,
+        }
+    }
+
+    // Samples per pixel for the next frame, kept to perfect squares like the +/- keys.
+    fn next_samples(&self, samples: u32, frame_time: Duration, moving: bool) -> u32 {
+        let grid = samples.isqrt();
+        let step_up = (grid + 1) * (grid + 1);
+        if !moving {
+            return if samples < self.still_samples {
+                step_up.min(self.still_samples)
+            } else {
+                samples
+            };
+        }
+        if frame_time > self.target.mul_f32(1.2) {
+            // The frame time grows about linearly with the samples.
+            let budget = samples as f32 * self.target.as_secs_f32() / frame_time.as_secs_f32();
+            (budget.sqrt() as u32).max(1).pow(2)
+        } else if frame_time < self.target.mul_f32(0.8) {
+            step_up
+        } else {
+            samples
+        }
+    }
+}
 
 struct App {
     state: Option<WgpuState>,
     world_data: WorldData,
+    // The view only follows the mouse while the right button is held.
+    looking: bool,
+    // Last position of the cursor in the window, in physical pixels.
+    cursor: PhysicalPosition<f64>,
+    // Keep showing the accumulated frame without tracing new ones.
+    paused: bool,
+    last_gpu_time_log: Instant,
+    frame_times: FrameTimes,
+    // None renders every frame with the same number of samples.
+    adaptive_samples: Option<AdaptiveSamples>,
+    // Frames accumulated before each present once the camera is still.
+    passes_per_frame: u32,
+    // Stop tracing once this many frames are accumulated, until something changes.
+    frame_limit: Option<u32>,
+    // The frame limit was reached, the last frame is only presented again.
+    frozen: bool,
+    image_writer: ImageWriter,
+    adapter: AdapterChoice,
+    // Given to the GPU state once it is created, see WgpuState::set_tile_size.
+    tile_size: Option<u32>,
+    window_attributes: WindowAttributes,
 }
 
 impl App {
-    fn new(world_data: WorldData) -> Self {
+    fn new(
+        world_data: WorldData,
+        target_fps: Option<u32>,
+        passes_per_frame: u32,
+        frame_limit: Option<u32>,
+        adapter: AdapterChoice,
+        tile_size: Option<u32>,
+        window_attributes: WindowAttributes,
+    ) -> Self {
+        let adaptive_samples =
+            target_fps.map(|fps| AdaptiveSamples::new(fps, world_data.sample_per_pixels()));
         Self {
             state: None,
             world_data,
+            looking: false,
+            cursor: PhysicalPosition::new(0.0, 0.0),
+            paused: false,
+            last_gpu_time_log: Instant::now(),
+            frame_times: FrameTimes::new(),
+            adaptive_samples,
+            passes_per_frame,
+            frame_limit,
+            frozen: false,
+            image_writer: ImageWriter::new(),
+            adapter,
+            tile_size,
+            window_attributes,
+        }
+    }
+
+    fn handle_key(&mut self, code: KeyCode) {
+        // Samples go through perfect squares so that they stay stratified.
+        let grid = self.world_data.sample_per_pixels().isqrt();
+        match code {
+            KeyCode::Equal | KeyCode::NumpadAdd => self.set_samples((grid + 1) * (grid + 1)),
+            KeyCode::Minus | KeyCode::NumpadSubtract => {
+                self.set_samples(grid.saturating_sub(1).pow(2))
+            }
+            KeyCode::BracketLeft | KeyCode::BracketRight => {
+                let depth = self.world_data.max_depth();
+                let depth = if code == KeyCode::BracketRight {
+                    depth + 1
+                } else {
+                    depth - 1
+                };
+                self.world_data.set_max_depth(depth);
+                println!("Max depth: {}", self.world_data.max_depth());
+            }
+            KeyCode::PageUp | KeyCode::PageDown => {
+                self.passes_per_frame = if code == KeyCode::PageUp {
+                    (self.passes_per_frame * 2).min(MAX_PASSES_PER_FRAME)
+                } else {
+                    (self.passes_per_frame / 2).max(1)
+                };
+                println!("Passes per frame: {}", self.passes_per_frame);
+            }
+            KeyCode::Comma | KeyCode::Period => {
+                let step = if code == KeyCode::Period {
+                    EXPOSURE_STEP
+                } else {
+                    -EXPOSURE_STEP
+                };
+                self.world_data
+                    .set_exposure(self.world_data.exposure() + step);
+                println!("Exposure: {:+.1} EV", self.world_data.exposure());
+            }
+            KeyCode::Semicolon | KeyCode::Quote => {
+                let factor = if code == KeyCode::Quote {
+                    LIGHT_INTENSITY_STEP
+                } else {
+                    1.0 / LIGHT_INTENSITY_STEP
+                };
+                self.world_data.scale_light_intensity(factor);
+                println!("Light intensity scaled by {factor:.2}");
+            }
+            KeyCode::KeyB => {
+                let mode = self.world_data.background_mode().next();
+                self.world_data.set_background_mode(mode);
+                println!("Background: {:?}", self.world_data.background_mode());
+            }
+            KeyCode::KeyO => match self.world_data.camera_type() {
+                CameraType::Perspective => {
+                    let scale = self.world_data.perspective_height();
+                    self.world_data.set_orthographic(scale);
+                }
+                CameraType::Orthographic => self.world_data.set_perspective(),
+            },
+            KeyCode::KeyX => {
+                if let Some(index) = self.world_data.sphere_at_view_center() {
+                    let (sphere, _) = self
+                        .world_data
+                        .sphere(index)
+                        .expect("the index comes from the same spheres");
+                    match self.world_data.remove_sphere(index) {
+                        Ok(()) => {
+                            self.world_data.build_bvh();
+                            println!(
+                                "Removed the sphere of radius {} at {:?}, {} left",
+                                sphere[3],
+                                xyz(sphere),
+                                self.world_data.sphere_count()
+                            );
+                        }
+                        Err(err) => eprintln!("Failed to remove the sphere: {err}"),
+                    }
+                }
+            }
+            KeyCode::KeyC => self.world_data.clear_scene(),
+            KeyCode::Home => self.world_data.frame_scene(),
+            KeyCode::KeyV => {
+                let mode = self.world_data.debug_mode().next();
+                self.world_data.set_debug_mode(mode);
+                println!("Debug view: {mode:?}");
+            }
+            KeyCode::KeyL => {
+                let enabled = !self.world_data.cosine_sampling();
+                self.world_data.set_cosine_sampling(enabled);
+                println!(
+                    "Lambertian scattering: {}",
+                    if enabled {
+                        "cosine hemisphere"
+                    } else {
+                        "unit vector"
+                    }
+                );
+            }
+            KeyCode::KeyF => {
+                let enabled = !self.world_data.denoise();
+                self.world_data.set_denoise(enabled);
+                println!("Denoiser {}", if enabled { "on" } else { "off" });
+            }
+            KeyCode::KeyG => {
+                let enabled = !self.world_data.blue_noise();
+                self.world_data.set_blue_noise(enabled);
+                println!("{} noise", if enabled { "Blue" } else { "White" });
+            }
+            KeyCode::KeyK => {
+                let enabled = !self.world_data.adaptive_pixel_sampling();
+                self.world_data.set_adaptive_pixel_sampling(enabled);
+                println!("Adaptive sampling {}", if enabled { "on" } else { "off" });
+            }
+            KeyCode::KeyT => {
+                let enabled = !self.world_data.antialiasing();
+                self.world_data.set_antialiasing(enabled);
+                println!("Anti-aliasing {}", if enabled { "on" } else { "off" });
+            }
+            KeyCode::KeyW => self.world_data.move_camera(0.0, 0.0, CAMERA_SPEED),
+            KeyCode::KeyS => self.world_data.move_camera(0.0, 0.0, -CAMERA_SPEED),
+            KeyCode::KeyA => self.world_data.move_camera(-CAMERA_SPEED, 0.0, 0.0),
+            KeyCode::KeyD => self.world_data.move_camera(CAMERA_SPEED, 0.0, 0.0),
+            KeyCode::KeyQ => self.world_data.move_camera(0.0, -CAMERA_SPEED, 0.0),
+            KeyCode::KeyE => self.world_data.move_camera(0.0, CAMERA_SPEED, 0.0),
+            _ => (),
         }
     }
+
+    fn set_samples(&mut self, n: u32) {
+        self.world_data.set_samples(n);
+        if let Some(adaptive_samples) = &mut self.adaptive_samples {
+            adaptive_samples.still_samples = self.world_data.sample_per_pixels();
+        }
+        println!("{} samples per pixel", self.world_data.sample_per_pixels());
+    }
 }
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         // Create window object
         let window = Arc::new(
             event_loop
-                .create_window(Window::default_attributes())
+                .create_window(self.window_attributes.clone())
                 .unwrap(),
         );
+        // Give the camera the real size before the first frame rather than waiting for a resize.
+        let size = window.inner_size();
+        if size.width > 0 && size.height > 0 {
+            self.world_data.update_size(size.width, size.height);
+        }
 
-        let state = pollster::block_on(WgpuState::new(window.clone(), self.world_data));
-        self.state = Some(state);
+        let new_state = WgpuState::new(window.clone(), &self.world_data, &self.adapter);
+        match pollster::block_on(new_state) {
+            Ok(mut state) => {
+                state.set_tile_size(self.tile_size);
+                self.state = Some(state);
+            }
+            Err(err) => {
+                eprintln!("Failed to set up the GPU: {err}");
+                event_loop.exit();
+                return;
+            }
+        }
 
         window.request_redraw();
     }
@@ -52,56 +344,451 @@ impl ApplicationHandler for App {
         match event {
             WindowEvent::CloseRequested => {
                 println!("The close button was pressed; stopping");
-                event_loop.exit();
+                shut_down(state, event_loop);
             }
             WindowEvent::RedrawRequested => {
+                if state.reload_shader_if_changed() {
+                    self.world_data.mark_changed();
+                }
+                // A change since freezing starts a new accumulation up to the frame limit.
+                if self.frozen && self.world_data.frames_since_change() == 0 {
+                    self.frozen = false;
+                    if !self.paused {
+                        event_loop.set_control_flow(ControlFlow::Poll);
+                    }
+                }
+                // Anything that restarted the accumulation, like a resize, still gets one new frame.
+                // Otherwise the accumulation is only tone mapped again, for the exposure keys.
+                if (self.paused || self.frozen) && self.world_data.frames_since_change() > 0 {
+                    state.rewrite_world_data(&self.world_data);
+                    state.resolve();
+                    return;
+                }
+                // A single pass while the camera moves keeps it responsive.
+                let mut passes = if self.world_data.frames_since_change() < STILL_FRAMES {
+                    1
+                } else {
+                    self.passes_per_frame
+                };
+                if let Some(limit) = self.frame_limit {
+                    let remaining = limit.saturating_sub(self.world_data.frames_since_change());
+                    passes = passes.min(remaining.max(1));
+                }
+                for _ in 1..passes {
+                    self.world_data.next_frame();
+                    state.rewrite_world_data(&self.world_data);
+                    state.dispatch();
+                }
                 self.world_data.next_frame();
-                state.rewrite_world_data(self.world_data);
+                state.rewrite_world_data(&self.world_data);
                 state.render();
-                state.get_window().request_redraw();
+                if let Some(limit) = self.frame_limit {
+                    if self.world_data.frames_since_change() >= limit {
+                        self.frozen = true;
+                        // Only wake up for input, see about_to_wait.
+                        event_loop.set_control_flow(ControlFlow::Wait);
+                        println!("Converged after {limit} frames");
+                    }
+                }
+                let frame_time = self.frame_times.tick().as_secs_f32();
+                let samples = self.world_data.accumulated_samples();
+                let previous = samples - (passes * self.world_data.sample_per_pixels()) as u64;
+                for milestone in SAMPLE_MILESTONES {
+                    if previous < milestone && samples >= milestone {
+                        println!("Reached {milestone} samples per pixel");
+                    }
+                }
+                if let Some(window) = state.get_window() {
+                    window.set_title(&format!(
+                        "GPU Raytracing - {:.1} FPS ({:.2} ms) - {samples} spp",
+                        1.0 / frame_time,
+                        frame_time * 1000.0
+                    ));
+                    if !self.paused && !self.frozen {
+                        window.request_redraw();
+                    }
+                }
+
+                if let Some(adaptive_samples) = &self.adaptive_samples {
+                    let moving = self.world_data.frames_since_change() < STILL_FRAMES;
+                    let samples = adaptive_samples.next_samples(
+                        self.world_data.sample_per_pixels(),
+                        self.frame_times.last(),
+                        moving,
+                    );
+                    self.world_data.adjust_samples(samples);
+                }
+
+                if self.last_gpu_time_log.elapsed() >= Duration::from_secs(1) {
+                    if let Some(gpu_time) = state.last_frame_gpu_time() {
+                        log::info!("Compute dispatch took {gpu_time:.2} ms on the GPU");
+                    }
+                    if self.world_data.adaptive_pixel_sampling() {
+                        match state.active_pixel_fraction() {
+                            Ok(fraction) => log::info!(
+                                "{:.1}% of the pixels are still sampled",
+                                fraction * 100.0
+                            ),
+                            Err(err) => log::warn!("Failed to read the active pixels: {err}"),
+                        }
+                    }
+                    self.last_gpu_time_log = Instant::now();
+                }
             }
             WindowEvent::Resized(size) => {
-                self.world_data.update_size(size.width, size.height);
+                if let Err(err) = state.resize(size) {
+                    eprintln!("Failed to resize the window: {err}");
+                    shut_down(state, event_loop);
+                    return;
+                }
 
-                state.resize(size);
+                // The camera would divide by zero while minimized, keep the previous size.
+                if size.width > 0 && size.height > 0 {
+                    let render_size = state.render_size();
+                    self.world_data
+                        .update_size(render_size.width, render_size.height);
+                }
+            }
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(code),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                if code == KeyCode::Escape {
+                    println!("Escape was pressed; stopping");
+                    shut_down(state, event_loop);
+                } else if code == KeyCode::KeyR {
+                    // Trade resolution for speed, the frame is upscaled to the window.
+                    let render_scale = match state.render_scale() {
+                        scale if scale > 0.5 => 0.5,
+                        scale if scale > 0.25 => 0.25,
+                        _ => 1.0,
+                    };
+                    state.set_render_scale(render_scale);
+                    let render_size = state.render_size();
+                    self.world_data
+                        .update_size(render_size.width, render_size.height);
+                    println!("Rendering at {}x{}", render_size.width, render_size.height);
+                } else if code == KeyCode::Space {
+                    self.paused = !self.paused;
+                    if self.paused {
+                        // Only wake up for input and for the redraws the system asks for.
+                        event_loop.set_control_flow(ControlFlow::Wait);
+                        println!("Paused");
+                    } else {
+                        // A frozen image stays frozen, nothing new would be traced.
+                        if !self.frozen {
+                            event_loop.set_control_flow(ControlFlow::Poll);
+                        }
+                        if let Some(window) = state.get_window() {
+                            window.request_redraw();
+                        }
+                        println!("Resumed");
+                    }
+                } else if code == KeyCode::KeyN {
+                    // Uncapped frames show the real throughput, vsync saves power.
+                    let mode = match state.present_mode() {
+                        PresentMode::AutoVsync => PresentMode::Immediate,
+                        _ => PresentMode::AutoVsync,
+                    };
+                    let mode = state.set_present_mode(mode);
+                    println!("Present mode: {mode:?}");
+                } else if code == KeyCode::F5 {
+                    match self.world_data.save_camera(Path::new(CAMERA_FILE)) {
+                        Ok(()) => println!("Saved the camera to {CAMERA_FILE}"),
+                        Err(err) => eprintln!("Failed to save the camera: {err}"),
+                    }
+                } else if code == KeyCode::F6 {
+                    match self.world_data.save_scene(Path::new(SCENE_FILE)) {
+                        Ok(()) => println!("Saved the scene to {SCENE_FILE}"),
+                        Err(err) => eprintln!("Failed to save the scene: {err}"),
+                    }
+                } else if code == KeyCode::F9 {
+                    match self.world_data.load_camera(Path::new(CAMERA_FILE)) {
+                        Ok(()) => println!("Loaded the camera from {CAMERA_FILE}"),
+                        Err(err) => eprintln!("Failed to load the camera: {err}"),
+                    }
+                } else if code == KeyCode::KeyM {
+                    state.report_memory();
+                } else if code == KeyCode::KeyP {
+                    // Printed by the writer once the file is written.
+                    let path = PathBuf::from("screenshot.png");
+                    if let Err(err) = state.queue_screenshot(&self.image_writer, path) {
+                        eprintln!("Failed to save a screenshot: {err}");
+                    }
+                } else {
+                    self.handle_key(code);
+                    // Nothing else redraws while paused or frozen.
+                    if self.paused || self.frozen {
+                        if let Some(window) = self.state.as_ref().and_then(WgpuState::get_window) {
+                            window.request_redraw();
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput {
+                state: button_state,
+                button: MouseButton::Right,
+                ..
+            } => {
+                self.looking = button_state == ElementState::Pressed;
+            }
+            WindowEvent::CursorMoved { position, .. } => self.cursor = position,
+            // Focus on whatever is under the cursor.
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                let render_scale = state.render_scale() as f64;
+                let x = (self.cursor.x * render_scale) as u32;
+                let y = (self.cursor.y * render_scale) as u32;
+                match state.read_depth_at(x, y) {
+                    Ok(Some(distance)) => {
+                        println!("Focusing at {distance:.3}");
+                        self.world_data.set_focus_dist(distance);
+                    }
+                    Ok(None) => println!("Nothing under the cursor"),
+                    Err(err) => eprintln!("Failed to read the depth: {err}"),
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Middle,
+                ..
+            } => {
+                let render_scale = state.render_scale() as f64;
+                let x = (self.cursor.x * render_scale) as u32;
+                let y = (self.cursor.y * render_scale) as u32;
+                match state.pick_sphere_at(x, y) {
+                    Ok(Some(index)) => println!("Picked sphere {index}"),
+                    Ok(None) => println!("No sphere under the cursor"),
+                    Err(err) => eprintln!("Failed to pick a sphere: {err}"),
+                }
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / PIXELS_PER_LINE,
+                };
+                // Scrolling up zooms in.
+                let vfov = self.world_data.vfov() - lines * ZOOM_SPEED;
+                self.world_data.set_vfov(vfov);
             }
             _ => (),
         }
     }
-}
 
-fn main() {
-    env_logger::init();
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        // Nothing asks for redraws once frozen, unless the input changed the scene.
+        if self.frozen && !self.paused && self.world_data.frames_since_change() == 0 {
+            if let Some(window) = self.state.as_ref().and_then(WgpuState::get_window) {
+                window.request_redraw();
+            }
+        }
+    }
 
-    let sample_per_pixels = 10;
-    let max_depth = 5;
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _id: DeviceId, event: DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (dx, dy) } = event {
+            if self.looking {
+                self.world_data.rotate_camera(
+                    dx as f32 * MOUSE_SENSITIVITY,
+                    -dy as f32 * MOUSE_SENSITIVITY,
+                );
+            }
+        }
+    }
+}
 
-    let vfov = 90.0;
+// The screenshots still queued are written when the App, and its ImageWriter, is dropped.
+fn shut_down(state: &WgpuState, event_loop: &ActiveEventLoop) {
+    if let Err(err) = state.wait_idle() {
+        eprintln!("Failed to wait for the GPU: {err}");
+    }
+    event_loop.exit();
+}
 
-    let lookfrom = [0.0, 0.0, 0.0, 0.0];
-    let lookat = [0.0, 0.0, -1.0, 0.0];
+fn main() {
+    env_logger::init();
 
-    let mut world_data = WorldData::new(0, 0, lookfrom, lookat, vfov, sample_per_pixels, max_depth);
+    let args = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
 
-    let sphere1 = [0.0, -100.5, -1.0, 100.0];
-    let sphere2 = [0.0, 0.0, -1.2, 0.5];
-    let sphere3 = [-1.0, 0.0, -1.0, 0.5];
-    let sphere4 = [1.0, 0.0, -1.0, 0.5];
+    let mut world_data = match args.random_seed {
+        Some(seed) => world_data::random_scene(seed),
+        None => match world_data::load_scene(&args.scene) {
+            Ok(world_data) => world_data,
+            Err(err) => {
+                eprintln!("Failed to load {}: {err}", args.scene.display());
+                std::process::exit(1);
+            }
+        },
+    };
 
-    let material1 = Material::lambertian([0.2, 0.8, 0.4, 1.0]);
-    let material2 = Material::lambertian([0.0, 1.0, 0.0, 1.0]);
-    let material3 = Material::lambertian([1.0, 0.0, 0.0, 1.0]);
-    let material4 = Material::lambertian([0.0, 1.0, 0.0, 1.0]);
+    if let Some(seed) = args.seed {
+        world_data.set_seed(seed);
+    }
+    world_data.set_gamma(args.gamma);
+    world_data.set_output_colorspace(args.colorspace);
+    if let Some(luminance) = args.firefly_clamp {
+        world_data.set_firefly_clamp(luminance);
+    }
 
-    world_data.add_sphere(sphere1, material1);
-    world_data.add_sphere(sphere2, material2);
-    world_data.add_sphere(sphere3, material3);
-    world_data.add_sphere(sphere4, material4);
+    if let Some(headless) = &args.headless {
+        render_headless(world_data, headless, &args.adapter, args.tile_size);
+        return;
+    }
 
     let event_loop = EventLoop::new().unwrap();
 
     event_loop.set_control_flow(ControlFlow::Poll);
 
-    let mut app = App::new(world_data);
+    let mut window_attributes = Window::default_attributes();
+    if let Some((width, height)) = args.window_size {
+        window_attributes = window_attributes.with_inner_size(PhysicalSize::new(width, height));
+    }
+    if args.fullscreen {
+        window_attributes = window_attributes.with_fullscreen(Some(Fullscreen::Borderless(None)));
+    }
+
+    let mut app = App::new(
+        world_data,
+        args.target_fps,
+        args.passes,
+        args.frames,
+        args.adapter,
+        args.tile_size,
+        window_attributes,
+    );
     event_loop.run_app(&mut app).unwrap();
 }
+
+fn render_headless(
+    mut world_data: WorldData,
+    args: &HeadlessArgs,
+    adapter: &AdapterChoice,
+    tile_size: Option<u32>,
+) {
+    world_data.update_size(args.width, args.height);
+    let new_state = WgpuState::new_headless(args.width, args.height, &world_data, adapter);
+    let mut state = match pollster::block_on(new_state) {
+        Ok(state) => state,
+        Err(err) => {
+            eprintln!("Failed to set up the GPU: {err}");
+            std::process::exit(1);
+        }
+    };
+    state.set_tile_size(tile_size);
+    if let Some(size) = state.tile_size() {
+        println!("Tracing in tiles of {size}x{size} pixels");
+    }
+
+    if let Some(turntable) = &args.turntable {
+        render_turntable(world_data, state, args, turntable);
+        return;
+    }
+
+    let frames = accumulate(&mut world_data, &mut state, args.samples);
+    match state.save_screenshot(&args.out) {
+        Ok(()) => println!("Saved {frames} frames to {}", args.out.display()),
+        Err(err) => {
+            eprintln!("Failed to save {}: {err}", args.out.display());
+            std::process::exit(1);
+        }
+    }
+    if args.gbuffer {
+        if let Err(err) = save_gbuffer(&state, &args.out) {
+            eprintln!("Failed to save the G-buffer: {err}");
+            std::process::exit(1);
+        }
+    }
+}
+
+// Every frame adds sample_per_pixels samples to the accumulation texture, returns the frame count.
+fn accumulate(world_data: &mut WorldData, state: &mut WgpuState, samples: u32) -> u32 {
+    let frames = samples.div_ceil(world_data.sample_per_pixels().max(1));
+    for _ in 0..frames {
+        world_data.next_frame();
+        state.rewrite_world_data(world_data);
+        state.render();
+    }
+    frames
+}
+
+// Saves frame_0000.png, frame_0001.png... into the out directory, the camera turning around lookat
+// by the same angle between two of them.
+fn render_turntable(
+    mut world_data: WorldData,
+    mut state: WgpuState,
+    args: &HeadlessArgs,
+    turntable: &TurntableArgs,
+) {
+    if let Err(err) = std::fs::create_dir_all(&args.out) {
+        eprintln!("Failed to create {}: {err}", args.out.display());
+        std::process::exit(1);
+    }
+    // The next frame renders while the previous one is encoded.
+    let image_writer = ImageWriter::new();
+    let step = std::f32::consts::TAU / turntable.frames as f32;
+    for i in 0..turntable.frames {
+        let angle = if i == 0 { 0.0 } else { step };
+        world_data.orbit_camera(angle, turntable.radius);
+        accumulate(&mut world_data, &mut state, args.samples);
+        let path = args.out.join(format!("frame_{i:04}.png"));
+        if let Err(err) = state.queue_screenshot(&image_writer, path.clone()) {
+            eprintln!("Failed to save {}: {err}", path.display());
+            std::process::exit(1);
+        }
+    }
+    if image_writer.finish() > 0 {
+        std::process::exit(1);
+    }
+}
+
+// Writes OUT_color.pfm, OUT_albedo.pfm and OUT_normal.pfm next to the image, the float format
+// denoisers such as OIDN read.
+fn save_gbuffer(state: &WgpuState, out: &Path) -> anyhow::Result<()> {
+    let gbuffer = state.read_gbuffer()?;
+    let size = state.render_size();
+    let floats = |bytes: &[u8]| -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+            .collect()
+    };
+    let (color, albedo, normal) = (
+        floats(&gbuffer.color),
+        floats(&gbuffer.albedo),
+        floats(&gbuffer.normal),
+    );
+    for (name, pixels) in [("color", color), ("albedo", albedo), ("normal", normal)] {
+        let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+        let path = out.with_file_name(format!("{stem}_{name}.pfm"));
+        write_pfm(&path, size.width, size.height, &pixels)?;
+        println!("Saved {}", path.display());
+    }
+    Ok(())
+}
+
+// Little endian RGB floats, the rows go from the bottom up. The alpha of the RGBA pixels is dropped.
+fn write_pfm(path: &Path, width: u32, height: u32, rgba: &[f32]) -> anyhow::Result<()> {
+    let mut bytes = format!("PF\n{width} {height}\n-1.0\n").into_bytes();
+    for row in rgba.chunks_exact(4 * width as usize).rev() {
+        for pixel in row.chunks_exact(4) {
+            for value in &pixel[..3] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+    }
+    std::fs::write(path, bytes)?;
+    Ok(())
+}
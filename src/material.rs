@@ -3,29 +3,158 @@ use crate::math::*;
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Material {
-    color: [f32; 4],
-    // 0 is Lambertian, 1 is Metallic
-    material_type: u32,
-    // specific to Metallic
-    fuzz: f32,
-    _padding: [f32; 2],
+    pub(crate) color: [f32; 4],
+    // second color of the checker pattern
+    pub(crate) checker_color: [f32; 4],
+    // Light given off by any material on top of what it scatters, rgb and strength in w.
+    pub(crate) emission: [f32; 4],
+    // specific to Dielectric, fraction of each channel lost per unit of distance inside, w unused
+    pub(crate) absorption: [f32; 4],
+    // 0 is Lambertian, 1 is Metallic, 2 is Dielectric, 3 is Emissive, 4 is BrushedMetal, 5 is Pbr
+    pub(crate) material_type: u32,
+    // specific to Metallic, holds the intensity for Emissive and the metalness for Pbr
+    pub(crate) fuzz: f32,
+    // specific to Dielectric
+    pub(crate) refraction_index: f32,
+    // 0 is Solid, 1 is Checker, 2 is Image
+    pub(crate) texture_type: u32,
+    // size of a checker cell in world units
+    pub(crate) checker_scale: f32,
+    // specific to BrushedMetal and Pbr, along the tangent and the bitangent
+    pub(crate) roughness_u: f32,
+    pub(crate) roughness_v: f32,
+    // layer of the images loaded by WorldData::load_texture, for the Image texture type
+    pub(crate) texture_index: u32,
 }
 
 impl Material {
-    pub fn lambertian(color: Point4) -> Self {
+    fn solid(color: Point4, material_type: u32) -> Self {
         Self {
             color,
-            material_type: 0,
+            checker_color: color,
+            emission: [0.0; 4],
+            absorption: [0.0; 4],
+            material_type,
             fuzz: 0.0,
-            _padding: [0.0; 2],
+            refraction_index: 0.0,
+            texture_type: 0,
+            checker_scale: 1.0,
+            roughness_u: 0.0,
+            roughness_v: 0.0,
+            texture_index: 0,
         }
     }
+    pub fn lambertian(color: Point4) -> Self {
+        Self::solid(color, 0)
+    }
+    // The fuzz is clamped to [0, 1], larger values scatter most rays below the surface.
     pub fn metallic(color: Point4, fuzz: f32) -> Self {
         Self {
-            color,
-            material_type: 1,
-            fuzz,
-            _padding: [0.0; 2],
+            fuzz: fuzz.clamp(0.0, 1.0),
+            ..Self::solid(color, 1)
+        }
+    }
+    // Anisotropic GGX metal, the highlights stretch along the rougher direction. The tangent follows
+    // the horizontal circles around the y axis. Roughnesses are clamped to [0.01, 1].
+    pub fn brushed_metal(color: Point4, roughness_u: f32, roughness_v: f32) -> Self {
+        Self {
+            roughness_u: roughness_u.clamp(0.01, 1.0),
+            roughness_v: roughness_v.clamp(0.01, 1.0),
+            ..Self::solid(color, 4)
+        }
+    }
+    // Metallic/roughness workflow: metallic 0 is a plastic-like Lambertian base under a clear coat,
+    // 1 is a metal tinted by the albedo, values in between blend the two. Roughness spreads the
+    // reflection like the fuzz of the metallic type. Both are clamped, the roughness to [0.01, 1].
+    // Takes the textures like the Lambertian type does. Lights are sampled directly for the base only,
+    // the specular layer finds them by chance.
+    pub fn pbr(albedo: Point4, metallic: f32, roughness: f32) -> Self {
+        let roughness = roughness.clamp(0.01, 1.0);
+        Self {
+            fuzz: metallic.clamp(0.0, 1.0),
+            roughness_u: roughness,
+            roughness_v: roughness,
+            ..Self::solid(albedo, 5)
         }
     }
-}
\ No newline at end of file
+    // Glass does not absorb anything, hence the white color.
+    pub fn dielectric(refraction_index: f32) -> Self {
+        Self {
+            refraction_index,
+            ..Self::solid([1.0, 1.0, 1.0, 1.0], 2)
+        }
+    }
+    // Tinted glass: the light going through loses exp(-absorption * distance) of each channel, so
+    // thick parts look darker. Negative coefficients are clamped to 0.
+    pub fn colored_glass(refraction_index: f32, absorption: Vec3) -> Self {
+        let [r, g, b] = absorption.map(|a| a.max(0.0));
+        Self {
+            absorption: [r, g, b, 0.0],
+            ..Self::dielectric(refraction_index)
+        }
+    }
+    pub fn emissive(color: Point4, intensity: f32) -> Self {
+        Self {
+            fuzz: intensity,
+            ..Self::solid(color, 3)
+        }
+    }
+    // Unlike the emissive type, the surface keeps scattering light. It is not sampled directly.
+    pub fn with_emission(self, color: Point4, strength: f32) -> Self {
+        Self {
+            emission: [color[0], color[1], color[2], strength],
+            ..self
+        }
+    }
+    pub fn is_emissive(&self) -> bool {
+        self.material_type == 3
+    }
+    // Both the emissive type and the emission of the other types.
+    pub(crate) fn scale_emission(&mut self, factor: f32) {
+        if self.is_emissive() {
+            self.fuzz *= factor;
+        }
+        self.emission[3] *= factor;
+    }
+    // NaN or infinite colors would spread through the accumulation and blacken the image.
+    pub(crate) fn is_finite(&self) -> bool {
+        let colors = [
+            self.color,
+            self.checker_color,
+            self.emission,
+            self.absorption,
+        ];
+        let scalars = [
+            self.fuzz,
+            self.refraction_index,
+            self.checker_scale,
+            self.roughness_u,
+            self.roughness_v,
+        ];
+        colors
+            .iter()
+            .flatten()
+            .chain(&scalars)
+            .all(|value| value.is_finite())
+    }
+
+    // Lambertian alternating between the two colors in a 3D grid of cubes of side scale.
+    pub fn checker(color_a: Point4, color_b: Point4, scale: f32) -> Self {
+        Self {
+            checker_color: color_b,
+            texture_type: 1,
+            checker_scale: scale,
+            ..Self::solid(color_a, 0)
+        }
+    }
+
+    // Lambertian colored by an image, see WorldData::load_texture for the index. Spheres wrap it
+    // around from pole to pole, quads stretch it over their whole surface.
+    pub fn textured(texture_index: u32) -> Self {
+        Self {
+            texture_type: 2,
+            texture_index,
+            ..Self::solid([1.0, 1.0, 1.0, 1.0], 0)
+        }
+    }
+}
@@ -1,12 +1,28 @@
+// Vectors are packed in [f32; 4] to match the GPU layout but only the first three components are
+// geometric: dot, norm, normalize and cross ignore w. Points carry w = 1 and directions w = 0, so
+// that subtracting two points gives a direction.
 pub type Point4 = [f32; 4];
 pub type Vec4 = [f32; 4];
+pub type Vec3 = [f32; 3];
+
+pub fn point(p: Vec3) -> Point4 {
+    [p[0], p[1], p[2], 1.0]
+}
+
+pub fn direction(d: Vec3) -> Vec4 {
+    [d[0], d[1], d[2], 0.0]
+}
+
+pub fn xyz(v: Vec4) -> Vec3 {
+    [v[0], v[1], v[2]]
+}
 
 pub fn add(v: Vec4, w: Vec4) -> Vec4 {
-    return [v[0] + w[0], v[1] + w[1], v[2] + w[2], v[3] + w[3]];
+    [v[0] + w[0], v[1] + w[1], v[2] + w[2], v[3] + w[3]]
 }
 
 pub fn sub(v: Vec4, w: Vec4) -> Vec4 {
-    return [v[0] - w[0], v[1] - w[1], v[2] - w[2], v[3] - w[3]];
+    [v[0] - w[0], v[1] - w[1], v[2] - w[2], v[3] - w[3]]
 }
 
 pub fn scale(scalar: f32, v: Vec4) -> Vec4 {
@@ -14,14 +30,16 @@ pub fn scale(scalar: f32, v: Vec4) -> Vec4 {
 }
 
 pub fn dot(v: Vec4, w: Vec4) -> f32 {
-    v[0] * w[0] + v[1] * w[1] + v[2] * w[2] + v[3] * w[3]
+    v[0] * w[0] + v[1] * w[1] + v[2] * w[2]
 }
 pub fn norm(v: Vec4) -> f32 {
     dot(v, v).sqrt()
 }
 
+// The result is a direction, whatever the w of v.
 pub fn normalize(v: Vec4) -> Vec4 {
-    scale(1.0 / norm(v), v)
+    let n = norm(v);
+    [v[0] / n, v[1] / n, v[2] / n, 0.0]
 }
 
 pub fn cross(v: Vec4, w: Vec4) -> Vec4 {
@@ -34,5 +52,64 @@ pub fn cross(v: Vec4, w: Vec4) -> Vec4 {
 }
 
 pub fn deg_to_rad(d: f32) -> f32 {
-    return d * std::f32::consts::PI / 180.0;
+    d * std::f32::consts::PI / 180.0
+}
+
+// Scales, then rotates around x, y and z in that order (Euler angles in degrees), then translates.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Vec3,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: [1.0; 3],
+        }
+    }
+}
+
+impl Transform {
+    // Rows of Rz * Ry * Rx.
+    fn rotation_rows(&self) -> [Vec3; 3] {
+        let [x, y, z] = self.rotation.map(deg_to_rad);
+        let (sx, cx) = x.sin_cos();
+        let (sy, cy) = y.sin_cos();
+        let (sz, cz) = z.sin_cos();
+        [
+            [cy * cz, sx * sy * cz - cx * sz, cx * sy * cz + sx * sz],
+            [cy * sz, sx * sy * sz + cx * cz, cx * sy * sz - sx * cz],
+            [-sy, sx * cy, cx * cy],
+        ]
+    }
+
+    pub fn apply_point(&self, p: Point4) -> Point4 {
+        let rows = self.rotation_rows();
+        let scaled: Vec3 = std::array::from_fn(|i| p[i] * self.scale[i]);
+        let moved: Vec3 = std::array::from_fn(|i| {
+            dot(direction(rows[i]), direction(scaled)) + self.translation[i]
+        });
+        point(moved)
+    }
+
+    // Columns of the matrix going from world space back to object space, the layout of a WGSL
+    // mat4x4. Every scale factor must be non-zero.
+    pub fn inverse_columns(&self) -> [Vec4; 4] {
+        let rows = self.rotation_rows();
+        // The inverse of R * S is S^-1 * R^T.
+        let inverse: [Vec4; 3] = std::array::from_fn(|i| {
+            direction([rows[0][i], rows[1][i], rows[2][i]].map(|r| r / self.scale[i]))
+        });
+        let offset = direction(self.translation);
+        [
+            [inverse[0][0], inverse[1][0], inverse[2][0], 0.0],
+            [inverse[0][1], inverse[1][1], inverse[2][1], 0.0],
+            [inverse[0][2], inverse[1][2], inverse[2][2], 0.0],
+            point(inverse.map(|row| -dot(row, offset))),
+        ]
+    }
 }
@@ -0,0 +1,561 @@
+use crate::material::Material;
+use crate::math::xyz;
+use crate::world_data::{OutputColorspace, PixelFilter, ToneMap};
+
+use std::fmt;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+// On-disk description of a scene, see scenes/default.ron for an example.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    // Defaults to the blue to white gradient.
+    #[serde(default)]
+    pub sky: Option<SkyDescription>,
+    // Flat color replacing the sky gradient, the environment still wins when there is one.
+    #[serde(default)]
+    pub background_color: Option<[f32; 3]>,
+    // Equirectangular HDR image replacing the sky, relative to the scene file.
+    #[serde(default)]
+    pub environment: Option<PathBuf>,
+    #[serde(default)]
+    pub tone_map: ToneMap,
+    #[serde(default)]
+    pub pixel_filter: PixelFilter,
+    // Stops applied before tone mapping, 0 keeps the radiance as is.
+    #[serde(default)]
+    pub exposure: f32,
+    // Plain power curve encoding the output, takes precedence over output_colorspace.
+    #[serde(default)]
+    pub gamma: Option<f32>,
+    #[serde(default)]
+    pub output_colorspace: OutputColorspace,
+    // PNG images for the Textured materials, relative to the scene file.
+    #[serde(default)]
+    pub textures: Vec<PathBuf>,
+    // Only used by Reinhard, defaults to no clipping at all.
+    #[serde(default)]
+    pub white_point: Option<f32>,
+    // Only used by the AmbientOcclusion debug view.
+    #[serde(default)]
+    pub ambient_occlusion: Option<AmbientOcclusionDescription>,
+    pub spheres: Vec<SphereDescription>,
+    #[serde(default)]
+    pub boxes: Vec<BoxDescription>,
+    #[serde(default)]
+    pub quads: Vec<QuadDescription>,
+    #[serde(default)]
+    pub area_lights: Vec<AreaLightDescription>,
+    #[serde(default)]
+    pub cylinders: Vec<CylinderDescription>,
+    #[serde(default)]
+    pub planes: Vec<PlaneDescription>,
+    #[serde(default)]
+    pub disks: Vec<DiskDescription>,
+    #[serde(default)]
+    pub transformed_spheres: Vec<TransformedSphereDescription>,
+    #[serde(default)]
+    pub instances: Vec<InstancesDescription>,
+    #[serde(default)]
+    pub meshes: Vec<MeshDescription>,
+    #[serde(default)]
+    pub lights: Vec<LightDescription>,
+    #[serde(default)]
+    pub spotlights: Vec<SpotlightDescription>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub lookfrom: [f32; 3],
+    pub lookat: [f32; 3],
+    // Defaults to +y.
+    #[serde(default)]
+    pub vup: Option<[f32; 3]>,
+    pub vfov: f32,
+    // Preferably a perfect square, samples are stratified over a grid in each pixel.
+    pub sample_per_pixels: u32,
+    pub max_depth: u32,
+    #[serde(default)]
+    pub aperture: f32,
+    // Defaults to the distance between lookfrom and lookat.
+    #[serde(default)]
+    pub focus_dist: Option<f32>,
+    // Fraction of the frame the shutter stays open, defaults to 1.
+    #[serde(default)]
+    pub shutter: Option<f32>,
+    // Height of the view in world units, switches to a parallel projection.
+    #[serde(default)]
+    pub orthographic: Option<f32>,
+    // Bounces before Russian roulette kicks in.
+    #[serde(default)]
+    pub rr_min_bounces: Option<u32>,
+    // Bounces off anything but glass, within max_depth. Defaults to no limit of its own.
+    #[serde(default)]
+    pub max_diffuse_depth: Option<u32>,
+    // Mixed into every random sequence, see WorldData::set_seed.
+    #[serde(default)]
+    pub seed: u32,
+    // Jitters the samples inside each pixel, defaults to true.
+    #[serde(default)]
+    pub antialiasing: Option<bool>,
+    // Cosine weighted Lambertian bounces, defaults to true.
+    #[serde(default)]
+    pub cosine_sampling: Option<bool>,
+    // Luminance above which a sample is scaled down, defaults to no clamping.
+    #[serde(default)]
+    pub firefly_clamp: Option<f32>,
+    #[serde(default)]
+    pub blue_noise: bool,
+    #[serde(default)]
+    pub adaptive_pixel_sampling: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SkyDescription {
+    pub top: [f32; 3],
+    pub bottom: [f32; 3],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AmbientOcclusionDescription {
+    // Rays per sample from the first hit.
+    pub rays: u32,
+    // Hits further than this do not occlude, in world units.
+    pub radius: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SphereDescription {
+    pub center: [f32; 3],
+    // Where the sphere has moved when the shutter closes, for motion blur.
+    #[serde(default)]
+    pub end_center: Option<[f32; 3]>,
+    pub radius: f32,
+    pub material: MaterialDescription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDescription {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// Point light shining only in a cone, the angles are in degrees from the direction.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpotlightDescription {
+    pub position: [f32; 3],
+    pub direction: [f32; 3],
+    // Full intensity up to inner, none past outer.
+    pub inner: f32,
+    pub outer: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BoxDescription {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    pub material: MaterialDescription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuadDescription {
+    pub corner: [f32; 3],
+    // The two edges starting from corner.
+    pub u: [f32; 3],
+    pub v: [f32; 3],
+    pub material: MaterialDescription,
+}
+
+// Emissive quad sampled directly, for soft shadows.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AreaLightDescription {
+    pub corner: [f32; 3],
+    pub u: [f32; 3],
+    pub v: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CylinderDescription {
+    // Center of the bottom cap.
+    pub base: [f32; 3],
+    pub axis: [f32; 3],
+    pub radius: f32,
+    pub height: f32,
+    pub material: MaterialDescription,
+}
+
+// Infinite plane through point.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlaneDescription {
+    pub point: [f32; 3],
+    pub normal: [f32; 3],
+    pub material: MaterialDescription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiskDescription {
+    pub center: [f32; 3],
+    pub normal: [f32; 3],
+    pub radius: f32,
+    pub material: MaterialDescription,
+}
+
+// Sphere around the origin, scaled, rotated then moved, see math::Transform.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformedSphereDescription {
+    pub translation: [f32; 3],
+    // Degrees around x, y then z, defaults to no rotation.
+    #[serde(default)]
+    pub rotation: Option<[f32; 3]>,
+    // Defaults to (1.0, 1.0, 1.0).
+    #[serde(default)]
+    pub scale: Option<[f32; 3]>,
+    pub radius: f32,
+    pub material: MaterialDescription,
+}
+
+// Identical spheres at each of the centers, cheaper than listing them in spheres.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstancesDescription {
+    pub centers: Vec<[f32; 3]>,
+    pub radius: f32,
+    pub material: MaterialDescription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDescription {
+    // OBJ file, relative to the scene file.
+    pub path: PathBuf,
+    pub material: MaterialDescription,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian {
+        color: [f32; 3],
+        #[serde(default)]
+        emission: Option<EmissionDescription>,
+    },
+    Metallic {
+        color: [f32; 3],
+        fuzz: f32,
+        #[serde(default)]
+        emission: Option<EmissionDescription>,
+    },
+    // Anisotropic metal, see Material::brushed_metal.
+    BrushedMetal {
+        color: [f32; 3],
+        roughness_u: f32,
+        roughness_v: f32,
+    },
+    // Metallic/roughness workflow, see Material::pbr.
+    Pbr {
+        albedo: [f32; 3],
+        metallic: f32,
+        roughness: f32,
+        #[serde(default)]
+        emission: Option<EmissionDescription>,
+    },
+    Dielectric {
+        refraction_index: f32,
+    },
+    // Dielectric losing part of each channel per unit of distance inside, see Material::colored_glass.
+    ColoredGlass {
+        refraction_index: f32,
+        absorption: [f32; 3],
+    },
+    Emissive {
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Checker {
+        color_a: [f32; 3],
+        color_b: [f32; 3],
+        scale: f32,
+    },
+    // Index in the textures of the scene.
+    Textured {
+        texture: u32,
+    },
+}
+
+// Glow added to a Lambertian, metallic or Pbr surface.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmissionDescription {
+    pub color: [f32; 3],
+    pub strength: f32,
+}
+
+impl MaterialDescription {
+    pub fn to_material(&self) -> Material {
+        let rgba = |c: [f32; 3]| [c[0], c[1], c[2], 1.0];
+        let with_emission =
+            |material: Material, emission: &Option<EmissionDescription>| match emission {
+                Some(emission) => material.with_emission(rgba(emission.color), emission.strength),
+                None => material,
+            };
+        match self {
+            MaterialDescription::Lambertian { color, emission } => {
+                with_emission(Material::lambertian(rgba(*color)), emission)
+            }
+            MaterialDescription::Metallic {
+                color,
+                fuzz,
+                emission,
+            } => with_emission(Material::metallic(rgba(*color), *fuzz), emission),
+            MaterialDescription::BrushedMetal {
+                color,
+                roughness_u,
+                roughness_v,
+            } => Material::brushed_metal(rgba(*color), *roughness_u, *roughness_v),
+            MaterialDescription::Pbr {
+                albedo,
+                metallic,
+                roughness,
+                emission,
+            } => with_emission(
+                Material::pbr(rgba(*albedo), *metallic, *roughness),
+                emission,
+            ),
+            MaterialDescription::Dielectric { refraction_index } => {
+                Material::dielectric(*refraction_index)
+            }
+            MaterialDescription::ColoredGlass {
+                refraction_index,
+                absorption,
+            } => Material::colored_glass(*refraction_index, *absorption),
+            MaterialDescription::Emissive { color, intensity } => {
+                Material::emissive(rgba(*color), *intensity)
+            }
+            MaterialDescription::Checker {
+                color_a,
+                color_b,
+                scale,
+            } => Material::checker(rgba(*color_a), rgba(*color_b), *scale),
+            MaterialDescription::Textured { texture } => Material::textured(*texture),
+        }
+    }
+
+    // Inverse of to_material, for WorldData::save_scene.
+    pub(crate) fn from_material(material: &Material) -> Self {
+        let color = xyz(material.color);
+        let emission = (material.emission != [0.0; 4]).then(|| EmissionDescription {
+            color: xyz(material.emission),
+            strength: material.emission[3],
+        });
+        match (material.material_type, material.texture_type) {
+            (0, 1) => MaterialDescription::Checker {
+                color_a: color,
+                color_b: xyz(material.checker_color),
+                scale: material.checker_scale,
+            },
+            (0, 2) => MaterialDescription::Textured {
+                texture: material.texture_index,
+            },
+            (1, _) => MaterialDescription::Metallic {
+                color,
+                fuzz: material.fuzz,
+                emission,
+            },
+            (2, _) if material.absorption == [0.0; 4] => MaterialDescription::Dielectric {
+                refraction_index: material.refraction_index,
+            },
+            (2, _) => MaterialDescription::ColoredGlass {
+                refraction_index: material.refraction_index,
+                absorption: xyz(material.absorption),
+            },
+            (3, _) => MaterialDescription::Emissive {
+                color,
+                intensity: material.fuzz,
+            },
+            (4, _) => MaterialDescription::BrushedMetal {
+                color,
+                roughness_u: material.roughness_u,
+                roughness_v: material.roughness_v,
+            },
+            (5, _) => MaterialDescription::Pbr {
+                albedo: color,
+                metallic: material.fuzz,
+                roughness: material.roughness_u,
+                emission,
+            },
+            _ => MaterialDescription::Lambertian { color, emission },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    // Syntax errors, unknown material types and missing fields all end up here.
+    Parse(ron::error::SpannedError),
+    Obj(tobj::LoadError),
+    Image(image::ImageError),
+    InvalidRadius {
+        sphere: usize,
+        radius: f32,
+    },
+    InvalidBox {
+        index: usize,
+    },
+    InvalidQuad {
+        index: usize,
+    },
+    InvalidCylinder {
+        index: usize,
+    },
+    InvalidPlane {
+        index: usize,
+    },
+    InvalidDisk {
+        index: usize,
+    },
+    InvalidTransformedSphere {
+        index: usize,
+    },
+    InvalidInstances {
+        index: usize,
+    },
+    InvalidSpotlight {
+        index: usize,
+    },
+    NoSuchSphere {
+        index: usize,
+        count: usize,
+    },
+    // Index among the primitives of the same kind.
+    NonFinite {
+        primitive: &'static str,
+        index: usize,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(err) => write!(f, "could not read the scene file: {err}"),
+            SceneError::Parse(err) => write!(f, "could not parse the scene file: {err}"),
+            SceneError::Obj(err) => write!(f, "could not load the mesh: {err}"),
+            SceneError::Image(err) => write!(f, "could not load an image: {err}"),
+            SceneError::InvalidRadius { sphere, radius } => {
+                write!(
+                    f,
+                    "sphere {sphere} has radius {radius}, it must be positive"
+                )
+            }
+            SceneError::InvalidBox { index } => {
+                write!(
+                    f,
+                    "box {index} must have min strictly below max on every axis"
+                )
+            }
+            SceneError::InvalidQuad { index } => {
+                write!(f, "quad {index} must have two non-parallel edges")
+            }
+            SceneError::InvalidCylinder { index } => {
+                write!(
+                    f,
+                    "cylinder {index} must have a non-zero axis and a positive radius and height"
+                )
+            }
+            SceneError::InvalidPlane { index } => {
+                write!(f, "plane {index} must have a non-zero normal")
+            }
+            SceneError::InvalidDisk { index } => {
+                write!(
+                    f,
+                    "disk {index} must have a non-zero normal and a positive radius"
+                )
+            }
+            SceneError::InvalidTransformedSphere { index } => {
+                write!(
+                    f,
+                    "transformed sphere {index} must have a positive radius and non-zero scale factors"
+                )
+            }
+            SceneError::InvalidInstances { index } => {
+                write!(f, "instances {index} must have a positive radius")
+            }
+            SceneError::InvalidSpotlight { index } => {
+                write!(f, "spotlight {index} must have a non-zero direction")
+            }
+            SceneError::NoSuchSphere { index, count } => {
+                write!(f, "there is no sphere {index}, the scene has {count}")
+            }
+            SceneError::NonFinite { primitive, index } => {
+                write!(f, "{primitive} {index} has a NaN or infinite value")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SceneError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SceneError::Io(err) => Some(err),
+            SceneError::Parse(err) => Some(err),
+            SceneError::Obj(err) => Some(err),
+            SceneError::Image(err) => Some(err),
+            SceneError::InvalidRadius { .. }
+            | SceneError::InvalidBox { .. }
+            | SceneError::InvalidQuad { .. }
+            | SceneError::InvalidCylinder { .. }
+            | SceneError::InvalidPlane { .. }
+            | SceneError::InvalidDisk { .. }
+            | SceneError::InvalidTransformedSphere { .. }
+            | SceneError::InvalidInstances { .. }
+            | SceneError::InvalidSpotlight { .. }
+            | SceneError::NoSuchSphere { .. }
+            | SceneError::NonFinite { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(err: std::io::Error) -> Self {
+        SceneError::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for SceneError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        SceneError::Parse(err)
+    }
+}
+
+impl From<tobj::LoadError> for SceneError {
+    fn from(err: tobj::LoadError) -> Self {
+        SceneError::Obj(err)
+    }
+}
+
+impl From<image::ImageError> for SceneError {
+    fn from(err: image::ImageError) -> Self {
+        SceneError::Image(err)
+    }
+}
@@ -0,0 +1,474 @@
+use crate::material::Material;
+use crate::math::*;
+use crate::scene::SceneError;
+use crate::world_data::{load_obj, WorldData};
+
+use std::path::PathBuf;
+
+fn color(c: [f32; 3]) -> Vec4 {
+    [c[0], c[1], c[2], 1.0]
+}
+
+struct Sphere {
+    center: [f32; 3],
+    // Position when the shutter closes, same as center for a static sphere.
+    end_center: [f32; 3],
+    radius: f32,
+    material: Material,
+}
+
+struct Aabb {
+    min: [f32; 3],
+    max: [f32; 3],
+    material: Material,
+}
+
+struct Quad {
+    corner: [f32; 3],
+    u: [f32; 3],
+    v: [f32; 3],
+    material: Material,
+}
+
+struct AreaLight {
+    corner: [f32; 3],
+    u: [f32; 3],
+    v: [f32; 3],
+    color: [f32; 3],
+    intensity: f32,
+}
+
+struct Cylinder {
+    base: [f32; 3],
+    axis: [f32; 3],
+    radius: f32,
+    height: f32,
+    material: Material,
+}
+
+struct Plane {
+    point: [f32; 3],
+    normal: [f32; 3],
+    material: Material,
+}
+
+struct Disk {
+    center: [f32; 3],
+    normal: [f32; 3],
+    radius: f32,
+    material: Material,
+}
+
+struct TransformedSphere {
+    transform: Transform,
+    radius: f32,
+    material: Material,
+}
+
+struct Instances {
+    centers: Vec<[f32; 3]>,
+    radius: f32,
+    material: Material,
+}
+
+struct Light {
+    position: [f32; 3],
+    color: [f32; 3],
+    intensity: f32,
+}
+
+struct Spotlight {
+    position: [f32; 3],
+    direction: [f32; 3],
+    inner: f32,
+    outer: f32,
+    color: [f32; 3],
+    intensity: f32,
+}
+
+// Collects the camera and the geometry, then checks everything at once in build.
+// Defaults to a camera at the origin looking toward -z with a 90° field of view.
+pub struct SceneBuilder {
+    lookfrom: [f32; 3],
+    lookat: [f32; 3],
+    vup: [f32; 3],
+    vfov: f32,
+    sample_per_pixels: u32,
+    max_depth: u32,
+    aperture: f32,
+    // Defaults to the distance between lookfrom and lookat.
+    focus_dist: Option<f32>,
+    // Viewport height of the orthographic camera, None for a perspective one.
+    ortho_scale: Option<f32>,
+    shutter: f32,
+    spheres: Vec<Sphere>,
+    boxes: Vec<Aabb>,
+    quads: Vec<Quad>,
+    area_lights: Vec<AreaLight>,
+    cylinders: Vec<Cylinder>,
+    planes: Vec<Plane>,
+    disks: Vec<Disk>,
+    transformed_spheres: Vec<TransformedSphere>,
+    instances: Vec<Instances>,
+    lights: Vec<Light>,
+    spotlights: Vec<Spotlight>,
+    meshes: Vec<(PathBuf, Material)>,
+    textures: Vec<PathBuf>,
+}
+
+impl Default for SceneBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        Self {
+            lookfrom: [0.0, 0.0, 0.0],
+            lookat: [0.0, 0.0, -1.0],
+            vup: [0.0, 1.0, 0.0],
+            vfov: 90.0,
+            sample_per_pixels: 9,
+            max_depth: 5,
+            aperture: 0.0,
+            focus_dist: None,
+            ortho_scale: None,
+            shutter: 1.0,
+            spheres: Vec::new(),
+            boxes: Vec::new(),
+            quads: Vec::new(),
+            area_lights: Vec::new(),
+            cylinders: Vec::new(),
+            planes: Vec::new(),
+            disks: Vec::new(),
+            transformed_spheres: Vec::new(),
+            instances: Vec::new(),
+            lights: Vec::new(),
+            spotlights: Vec::new(),
+            meshes: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+
+    pub fn camera(mut self, lookfrom: [f32; 3], lookat: [f32; 3], vfov: f32) -> Self {
+        self.lookfrom = lookfrom;
+        self.lookat = lookat;
+        self.vfov = vfov;
+        self
+    }
+
+    pub fn vup(mut self, vup: [f32; 3]) -> Self {
+        self.vup = vup;
+        self
+    }
+
+    pub fn samples(mut self, sample_per_pixels: u32) -> Self {
+        self.sample_per_pixels = sample_per_pixels;
+        self
+    }
+
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn aperture(mut self, aperture: f32) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn focus_dist(mut self, focus_dist: f32) -> Self {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    pub fn orthographic(mut self, scale: f32) -> Self {
+        self.ortho_scale = Some(scale);
+        self
+    }
+
+    pub fn shutter(mut self, shutter: f32) -> Self {
+        self.shutter = shutter;
+        self
+    }
+
+    pub fn sphere(self, center: [f32; 3], radius: f32, material: Material) -> Self {
+        self.moving_sphere(center, center, radius, material)
+    }
+
+    // Goes from center to end_center while the shutter is open.
+    pub fn moving_sphere(
+        mut self,
+        center: [f32; 3],
+        end_center: [f32; 3],
+        radius: f32,
+        material: Material,
+    ) -> Self {
+        self.spheres.push(Sphere {
+            center,
+            end_center,
+            radius,
+            material,
+        });
+        self
+    }
+
+    // Axis aligned box between two opposite corners.
+    pub fn aabb(mut self, min: [f32; 3], max: [f32; 3], material: Material) -> Self {
+        self.boxes.push(Aabb { min, max, material });
+        self
+    }
+
+    // Parallelogram with one corner at corner and its two edges along u and v.
+    pub fn quad(mut self, corner: [f32; 3], u: [f32; 3], v: [f32; 3], material: Material) -> Self {
+        self.quads.push(Quad {
+            corner,
+            u,
+            v,
+            material,
+        });
+        self
+    }
+
+    // Emissive quad, see WorldData::add_area_light.
+    pub fn area_light(
+        mut self,
+        corner: [f32; 3],
+        u: [f32; 3],
+        v: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        self.area_lights.push(AreaLight {
+            corner,
+            u,
+            v,
+            color,
+            intensity,
+        });
+        self
+    }
+
+    // Capped cylinder whose bottom is centered on base, the axis does not need to be normalized.
+    pub fn cylinder(
+        mut self,
+        base: [f32; 3],
+        axis: [f32; 3],
+        radius: f32,
+        height: f32,
+        material: Material,
+    ) -> Self {
+        self.cylinders.push(Cylinder {
+            base,
+            axis,
+            radius,
+            height,
+            material,
+        });
+        self
+    }
+
+    pub fn plane(mut self, point: [f32; 3], normal: [f32; 3], material: Material) -> Self {
+        self.planes.push(Plane {
+            point,
+            normal,
+            material,
+        });
+        self
+    }
+
+    pub fn disk(
+        mut self,
+        center: [f32; 3],
+        normal: [f32; 3],
+        radius: f32,
+        material: Material,
+    ) -> Self {
+        self.disks.push(Disk {
+            center,
+            normal,
+            radius,
+            material,
+        });
+        self
+    }
+
+    pub fn transformed_sphere(
+        mut self,
+        transform: Transform,
+        radius: f32,
+        material: Material,
+    ) -> Self {
+        self.transformed_spheres.push(TransformedSphere {
+            transform,
+            radius,
+            material,
+        });
+        self
+    }
+
+    // Spheres of the same radius and material, see WorldData::add_instances.
+    pub fn instances(mut self, centers: &[[f32; 3]], radius: f32, material: Material) -> Self {
+        self.instances.push(Instances {
+            centers: centers.to_vec(),
+            radius,
+            material,
+        });
+        self
+    }
+
+    pub fn point_light(mut self, position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+        self.lights.push(Light {
+            position,
+            color,
+            intensity,
+        });
+        self
+    }
+
+    // Cone angles in degrees from the direction, see WorldData::add_spotlight.
+    pub fn spotlight(
+        mut self,
+        position: [f32; 3],
+        direction: [f32; 3],
+        inner: f32,
+        outer: f32,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        self.spotlights.push(Spotlight {
+            position,
+            direction,
+            inner,
+            outer,
+            color,
+            intensity,
+        });
+        self
+    }
+
+    // Image for Material::textured, the indices follow the order of the calls. Read by build.
+    pub fn texture(mut self, path: impl Into<PathBuf>) -> Self {
+        self.textures.push(path.into());
+        self
+    }
+
+    // The OBJ file is only read by build.
+    pub fn mesh(mut self, path: impl Into<PathBuf>, material: Material) -> Self {
+        self.meshes.push((path.into(), material));
+        self
+    }
+
+    // Fails on the first invalid primitive, or on a mesh that cannot be loaded.
+    pub fn build(self) -> Result<WorldData, SceneError> {
+        let lookfrom = point(self.lookfrom);
+        let lookat = point(self.lookat);
+        let mut world_data = WorldData::new(
+            lookfrom,
+            lookat,
+            direction(self.vup),
+            self.vfov,
+            self.sample_per_pixels,
+            self.max_depth,
+        );
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| norm(sub(lookfrom, lookat)));
+        world_data.set_defocus(self.aperture, focus_dist);
+        if let Some(scale) = self.ortho_scale {
+            world_data.set_orthographic(scale);
+        }
+        world_data.set_shutter(self.shutter);
+
+        for path in &self.textures {
+            world_data.load_texture(path)?;
+        }
+
+        for sphere in &self.spheres {
+            if sphere.end_center == sphere.center {
+                // The radius goes in the fourth component, see WorldData::add_sphere.
+                let [x, y, z] = sphere.center;
+                world_data.add_sphere([x, y, z, sphere.radius], sphere.material)?;
+            } else {
+                world_data.add_moving_sphere(
+                    point(sphere.center),
+                    point(sphere.end_center),
+                    sphere.radius,
+                    sphere.material,
+                )?;
+            }
+        }
+
+        for aabb in &self.boxes {
+            world_data.add_box(point(aabb.min), point(aabb.max), aabb.material)?;
+        }
+
+        for quad in &self.quads {
+            let (u, v) = (direction(quad.u), direction(quad.v));
+            world_data.add_quad(point(quad.corner), u, v, quad.material)?;
+        }
+
+        for light in &self.area_lights {
+            let (u, v) = (direction(light.u), direction(light.v));
+            let (corner, light_color) = (point(light.corner), color(light.color));
+            world_data.add_area_light(corner, u, v, light_color, light.intensity)?;
+        }
+
+        for cylinder in &self.cylinders {
+            world_data.add_cylinder(
+                point(cylinder.base),
+                direction(cylinder.axis),
+                cylinder.radius,
+                cylinder.height,
+                cylinder.material,
+            )?;
+        }
+
+        for plane in &self.planes {
+            world_data.add_plane(point(plane.point), direction(plane.normal), plane.material)?;
+        }
+
+        for disk in &self.disks {
+            let normal = direction(disk.normal);
+            world_data.add_disk(point(disk.center), normal, disk.radius, disk.material)?;
+        }
+
+        for sphere in &self.transformed_spheres {
+            world_data.add_transformed_sphere(sphere.transform, sphere.radius, sphere.material)?;
+        }
+
+        for instances in &self.instances {
+            let spheres: Vec<Vec4> = instances
+                .centers
+                .iter()
+                .map(|&[x, y, z]| [x, y, z, instances.radius])
+                .collect();
+            world_data.add_instances(&spheres, instances.material)?;
+        }
+
+        for light in &self.lights {
+            let position = point(light.position);
+            world_data.add_point_light(position, color(light.color), light.intensity)?;
+        }
+
+        for light in &self.spotlights {
+            let (position, light_direction) = (point(light.position), direction(light.direction));
+            world_data.add_spotlight(
+                position,
+                light_direction,
+                light.inner,
+                light.outer,
+                color(light.color),
+                light.intensity,
+            )?;
+        }
+
+        for (path, material) in &self.meshes {
+            load_obj(&mut world_data, path, *material)?;
+        }
+
+        world_data.build_bvh();
+        Ok(world_data)
+    }
+}
@@ -0,0 +1,44 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+
+// Read at runtime in debug builds, release builds only use the copy embedded by include_wgsl.
+pub const COMPUTE_SHADER_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/compute.wgsl");
+
+// Watches compute.wgsl so the pipeline can be rebuilt without recompiling the crate.
+pub struct ShaderWatcher {
+    // Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+    events: Receiver<()>,
+}
+
+impl ShaderWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let shader = Path::new(COMPUTE_SHADER_PATH);
+        let (sender, events) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let Ok(event) = event else {
+                    return;
+                };
+                let touches_shader = event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == shader.file_name());
+                if touches_shader && (event.kind.is_modify() || event.kind.is_create()) {
+                    let _ = sender.send(());
+                }
+            })?;
+        // Editors often save by replacing the file, which would end a watch on the file itself.
+        watcher.watch(shader.parent().unwrap(), RecursiveMode::NonRecursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    // True when the shader changed since the last call, a save usually sends several events.
+    pub fn changed(&self) -> bool {
+        self.events.try_iter().count() > 0
+    }
+}
@@ -1,193 +1,1218 @@
-use crate::world_data::WorldData;
+use crate::blue_noise;
+use crate::denoiser::Denoiser;
+use crate::gpu_timer::GpuTimer;
+use crate::image_writer::ImageWriter;
+#[cfg(debug_assertions)]
+use crate::shader_reload::{ShaderWatcher, COMPUTE_SHADER_PATH};
+use crate::world_data::{Environment, WorldData, TEXTURE_SIZE};
 
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use winit::window::Window;
 
-use wgpu::{util::DeviceExt, Buffer, Extent3d, ShaderModule, Texture, TextureViewDescriptor};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, Buffer, ComputePipeline, Extent3d, Texture,
+    TextureViewDescriptor,
+};
 
-pub struct WgpuState {
+// Side of the square workgroups, passed to the shader as the `workgroup_size` override.
+const WORKGROUP_SIZE: u32 = 8;
+// Side of the blue noise tile, repeated over the frame.
+const BLUE_NOISE_SIZE: u32 = 64;
+
+// The window and the surface the frames are presented on.
+struct SurfaceTarget {
     window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    format: wgpu::TextureFormat,
+    present_modes: Vec<wgpu::PresentMode>,
+    // Draws the compute texture onto the surface, scaling it to the window size.
+    blit_bind_group_layout: BindGroupLayout,
+    // Writes through the sRGB view, which encodes the linear colors of the compute texture.
+    srgb_blit_pipeline: wgpu::RenderPipeline,
+    // Writes the colors as they are, for frames the shader already encoded or left linear.
+    linear_blit_pipeline: wgpu::RenderPipeline,
+    blit_sampler: wgpu::Sampler,
+}
+
+// Which GPU to run on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum AdapterChoice {
+    // Left to wgpu, which may pick an integrated GPU on a laptop.
+    #[default]
+    Default,
+    HighPerformance,
+    LowPower,
+    // The first adapter whose name contains this, ignoring case.
+    Name(String),
+}
+
+// Work done on the compute texture before it is presented.
+#[derive(Clone, Copy, PartialEq)]
+enum Compute {
+    // Trace a new frame into the accumulation.
+    Trace,
+    // Tone map the accumulation again, see WgpuState::resolve.
+    Resolve,
+    Nothing,
+}
+
+// Images of the last frame for an external denoiser, rows are tightly packed from the top.
+pub struct GBuffer {
+    // RGBA of little endian f32, the linear radiance averaged over the accumulation, before the
+    // exposure and tone mapping. Alpha is 1.
+    pub color: Vec<u8>,
+    // Same layout, alpha is unused.
+    pub albedo: Vec<u8>,
+    // Same layout, w holds the index of the hit sphere, or -1 when no sphere was hit.
+    pub normal: Vec<u8>,
+}
+
+pub struct WgpuState {
+    // None when rendering headless.
+    target: Option<SurfaceTarget>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     size: winit::dpi::PhysicalSize<u32>,
-    surface: wgpu::Surface<'static>,
-    surface_format: wgpu::TextureFormat,
-    compute_shader: ShaderModule,
+    // Fraction of the window resolution the scene is traced at.
+    render_scale: f32,
     compute_texture_size: Extent3d,
     compute_texture: Texture,
+    // Sum of every frame rendered since the last change, read back and averaged by the shader.
+    accumulation_texture: Texture,
+    weight_texture: Texture,
+    // Distance to the first surface hit through the center of each pixel, -1 where nothing is hit.
+    depth_texture: Texture,
+    // Albedo and world space normal at the same hit, zero where nothing is hit. See read_gbuffer.
+    albedo_texture: Texture,
+    normal_texture: Texture,
     world_uniform: Buffer,
+    sphere_buffer: Buffer,
+    material_buffer: Buffer,
+    triangle_buffer: Buffer,
+    box_buffer: Buffer,
+    quad_buffer: Buffer,
+    cylinder_buffer: Buffer,
+    plane_buffer: Buffer,
+    transformed_sphere_buffer: Buffer,
+    instance_buffer: Buffer,
+    instance_group_buffer: Buffer,
+    instance_bvh_buffer: Buffer,
+    bvh_buffer: Buffer,
+    light_buffer: Buffer,
+    emissive_sphere_buffer: Buffer,
+    sphere_velocity_buffer: Buffer,
+    // Count of the pixels traced by the last frame, see WorldData::set_adaptive_pixel_sampling.
+    active_pixel_buffer: Buffer,
+    // Top left pixel of the tile being traced, see set_tile_size.
+    tile_origin_buffer: Buffer,
+    // Uploaded once, the environment can only be set while loading the scene.
+    environment_texture: Texture,
+    environment_sampler: wgpu::Sampler,
+    // Read by the shader instead of white noise, see WorldData::set_blue_noise.
+    blue_noise_texture: Texture,
+    // One layer per WorldData::textures, uploaded once like the environment.
+    image_texture: Texture,
+    image_sampler: wgpu::Sampler,
+    compute_bind_group_layout: BindGroupLayout,
+    // Kept to rebuild the pipeline when the shader is reloaded.
+    #[cfg(debug_assertions)]
+    pipeline_layout: wgpu::PipelineLayout,
+    compute_pipeline: ComputePipeline,
+    // Tone maps the accumulation again without tracing, see resolve.
+    resolve_pipeline: ComputePipeline,
+    // Invalidated whenever one of the bound resources gets recreated, and rebuilt on the next render.
+    compute_bind_group: Option<BindGroup>,
+    // Reads the compute texture, so it is invalidated along with it.
+    blit_bind_group: Option<BindGroup>,
+    // False once the shader applies its own encoding, see WorldData::set_gamma and
+    // WorldData::set_output_colorspace.
+    srgb_output: bool,
+    // Starts with vsync, see set_present_mode.
+    present_mode: wgpu::PresentMode,
+    denoiser: Denoiser,
+    // Copied from WorldData::denoise, runs the denoiser after each trace.
+    denoise: bool,
+    // None when the adapter does not support timestamp queries.
+    gpu_timer: Option<GpuTimer>,
+    // Side of the tiles each dispatch is split into, None traces the whole image at once.
+    tile_size: Option<u32>,
+    // Only watched for windows, see reload_shader_if_changed.
+    #[cfg(debug_assertions)]
+    shader_watcher: Option<ShaderWatcher>,
+}
+
+// Storage buffers cannot be empty, so an empty slice still gets room for one zeroed element.
+fn create_storage_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    label: &str,
+    data: &[T],
+) -> Buffer {
+    let zeroed = [T::zeroed()];
+    let contents = if data.is_empty() { &zeroed[..] } else { data };
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: bytemuck::cast_slice(contents),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+// Rewrite the content of a storage buffer, recreating it when the slice no longer has the same size.
+// Returns whether the buffer was recreated, in which case the bind group must be rebuilt.
+fn write_storage_buffer<T: bytemuck::Pod>(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &mut Buffer,
+    label: &str,
+    data: &[T],
+) -> bool {
+    let size = (data.len().max(1) * std::mem::size_of::<T>()) as u64;
+    if buffer.size() != size {
+        *buffer = create_storage_buffer(device, label, data);
+        return true;
+    }
+    if !data.is_empty() {
+        queue.write_buffer(buffer, 0, bytemuck::cast_slice(data));
+    }
+    false
+}
+
+fn create_accumulation_texture(device: &wgpu::Device, size: Extent3d) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        label: Some("accumulation_buffer"),
+        view_formats: &[],
+    })
+}
+
+// Sum of the pixel filter weights of every sample in the accumulation, its color sums are divided by
+// it rather than by the frame count.
+fn create_weight_texture(device: &wgpu::Device, size: Extent3d) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        label: Some("weight_buffer"),
+        view_formats: &[],
+    })
+}
+
+fn create_depth_texture(device: &wgpu::Device, size: Extent3d) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R32Float,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+        label: Some("depth_buffer"),
+        view_formats: &[],
+    })
+}
+
+fn create_gbuffer_texture(device: &wgpu::Device, size: Extent3d, label: &str) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba32Float,
+        // Also read by the denoiser.
+        usage: wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::TEXTURE_BINDING,
+        label: Some(label),
+        view_formats: &[],
+    })
+}
+
+// A black 1x1 texture stands in for a missing environment so that the bind group stays the same.
+// An environment larger than the textures of the GPU, like many 8K HDRIs, is scaled down to fit.
+fn create_environment_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    environment: Option<&Environment>,
+) -> Texture {
+    let max = device.limits().max_texture_dimension_2d;
+    let (width, height, pixels) = match environment {
+        Some(env) if env.width() > max || env.height() > max => {
+            let ratio = max as f32 / env.width().max(env.height()) as f32;
+            let fit = |side: u32| ((side as f32 * ratio) as u32).clamp(1, max);
+            let (width, height) = (fit(env.width()), fit(env.height()));
+            log::warn!(
+                "Scaling the {}x{} environment map down to {width}x{height}, the largest texture of \
+                 this GPU",
+                env.width(),
+                env.height()
+            );
+            let image =
+                image::Rgba32FImage::from_raw(env.width(), env.height(), env.pixels().to_vec())
+                    .expect("the pixels match the size");
+            let image = image::imageops::resize(
+                &image,
+                width,
+                height,
+                image::imageops::FilterType::Triangle,
+            );
+            (width, height, Cow::Owned(image.into_raw()))
+        }
+        Some(env) => (env.width(), env.height(), Cow::Borrowed(env.pixels())),
+        None => (1, 1, Cow::Borrowed(&[0.0; 4][..])),
+    };
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("environment_map"),
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        bytemuck::cast_slice(&pixels),
+    )
+}
+
+// White 1x1 layers stand in when no texture was loaded, like for the environment.
+fn create_image_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    textures: &[Arc<[u8]>],
+) -> Texture {
+    let (size, mut pixels) = if textures.is_empty() {
+        (1, vec![255; 4])
+    } else {
+        (TEXTURE_SIZE, textures.concat())
+    };
+    // GL picks the view dimension when the texture is created, and a single layer makes it a plain
+    // 2D texture. A copy of the last layer keeps it an array.
+    let layer_bytes = (size * size * 4) as usize;
+    if pixels.len() == layer_bytes {
+        pixels.extend_from_within(..);
+    }
+    let layers = (pixels.len() / layer_bytes) as u32;
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: layers,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("image_textures"),
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        &pixels,
+    )
+}
+
+// Binary units, with one decimal past the bytes.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+fn create_blue_noise_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> Texture {
+    let values = blue_noise::generate(BLUE_NOISE_SIZE as usize, 0);
+    device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            size: Extent3d {
+                width: BLUE_NOISE_SIZE,
+                height: BLUE_NOISE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            label: Some("blue_noise"),
+            view_formats: &[],
+        },
+        wgpu::util::TextureDataOrder::LayerMajor,
+        bytemuck::cast_slice(&values),
+    )
+}
+
+// The frames are drawn through an sRGB view created with add_srgb_suffix, so the surface itself must
+// use the linear variant.
+fn choose_surface_format(formats: &[wgpu::TextureFormat]) -> wgpu::TextureFormat {
+    const PREFERRED: [wgpu::TextureFormat; 2] = [
+        wgpu::TextureFormat::Bgra8Unorm,
+        wgpu::TextureFormat::Rgba8Unorm,
+    ];
+    PREFERRED
+        .into_iter()
+        .find(|format| formats.contains(format))
+        .or_else(|| formats.iter().copied().find(|format| !format.is_srgb()))
+        .unwrap_or(formats[0])
+}
+
+// The compute texture holds linear values, the sRGB encoding normally happens in the surface view.
+fn linear_to_srgb(value: u8) -> u8 {
+    let linear = value as f32 / 255.0;
+    let srgb = if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0).round() as u8
+}
+
+// Never empty, even when the scale would round a side down to zero.
+// Every texture the size of the frame would fail validation, and wgpu panics on those.
+fn check_size(device: &wgpu::Device, size: winit::dpi::PhysicalSize<u32>) -> anyhow::Result<()> {
+    let max = device.limits().max_texture_dimension_2d;
+    if size.width > max || size.height > max {
+        anyhow::bail!(
+            "{}x{} is larger than the {max}x{max} textures of this GPU",
+            size.width,
+            size.height
+        );
+    }
+    Ok(())
+}
+
+// A render scale above 1 can go past the largest texture, max keeps the sides within it.
+fn scaled_size(size: winit::dpi::PhysicalSize<u32>, render_scale: f32, max: u32) -> Extent3d {
+    let scale = |side: u32| ((side as f32 * render_scale).round() as u32).clamp(1, max);
+    Extent3d {
+        width: scale(size.width),
+        height: scale(size.height),
+        depth_or_array_layers: 1,
+    }
+}
+
+fn create_compute_texture(device: &wgpu::Device, size: Extent3d) -> Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Bgra8Unorm,
+        usage: wgpu::TextureUsages::COPY_SRC
+            | wgpu::TextureUsages::STORAGE_BINDING
+            | wgpu::TextureUsages::TEXTURE_BINDING,
+        label: Some("color_buffer"),
+        view_formats: &[],
+    })
+}
+
+async fn choose_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'static>>,
+    choice: &AdapterChoice,
+) -> anyhow::Result<wgpu::Adapter> {
+    let adapters = instance.enumerate_adapters(wgpu::Backends::all());
+    for adapter in &adapters {
+        let info = adapter.get_info();
+        println!(
+            "Found adapter: {} ({:?}, {:?})",
+            info.name, info.device_type, info.backend
+        );
+    }
+
+    let power_preference = match choice {
+        AdapterChoice::Default => wgpu::PowerPreference::default(),
+        AdapterChoice::HighPerformance => wgpu::PowerPreference::HighPerformance,
+        AdapterChoice::LowPower => wgpu::PowerPreference::LowPower,
+        AdapterChoice::Name(name) => {
+            let name = name.to_lowercase();
+            return adapters
+                .into_iter()
+                .find(|adapter| {
+                    adapter.get_info().name.to_lowercase().contains(&name)
+                        && surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+                })
+                .ok_or_else(|| anyhow::anyhow!("no adapter named like {name:?} can render here"));
+        }
+    };
+    Ok(instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference,
+            compatible_surface: surface,
+            ..Default::default()
+        })
+        .await?)
+}
+
+fn create_surface_target(
+    device: &wgpu::Device,
+    window: Arc<Window>,
+    surface: wgpu::Surface<'static>,
+    format: wgpu::TextureFormat,
+    present_modes: Vec<wgpu::PresentMode>,
+) -> SurfaceTarget {
+    let blit_shader = device.create_shader_module(wgpu::include_wgsl!("blit.wgsl"));
+    let blit_bind_group_layout =
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Blit Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: None,
+        bind_group_layouts: &[&blit_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let create_pipeline = |target_format: wgpu::TextureFormat| {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Blit Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &blit_shader,
+                entry_point: Some("main_vertex"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &blit_shader,
+                entry_point: Some("main_fragment"),
+                compilation_options: Default::default(),
+                targets: &[Some(target_format.into())],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    };
+    let srgb_blit_pipeline = create_pipeline(format.add_srgb_suffix());
+    let linear_blit_pipeline = create_pipeline(format);
+    let blit_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("Blit Sampler"),
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+    SurfaceTarget {
+        window,
+        surface,
+        format,
+        present_modes,
+        blit_bind_group_layout,
+        srgb_blit_pipeline,
+        linear_blit_pipeline,
+        blit_sampler,
+    }
+}
+
+// A WGSL error would otherwise reach the uncaptured error handler, which panics deep inside wgpu.
+// The message of the validation error points at the failing line of the shader.
+async fn validated<T>(
+    device: &wgpu::Device,
+    shader: &str,
+    create: impl FnOnce() -> T,
+) -> anyhow::Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    match device.pop_error_scope().await {
+        Some(err) => Err(anyhow::anyhow!("Failed to build {shader}: {err}")),
+        None => Ok(value),
+    }
+}
+
+fn create_compute_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> ComputePipeline {
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: None,
+        layout: Some(layout),
+        module,
+        entry_point: Some(entry_point),
+        compilation_options: wgpu::PipelineCompilationOptions {
+            constants: &[("workgroup_size", WORKGROUP_SIZE as f64)],
+            ..Default::default()
+        },
+        cache: None,
+    })
+}
+
+fn gbuffer_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            format: wgpu::TextureFormat::Rgba32Float,
+            access: wgpu::StorageTextureAccess::WriteOnly,
+        },
+        count: None,
+    }
+}
+
+fn storage_buffer_layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
 }
 
 impl WgpuState {
-    pub async fn new(window: Arc<Window>, world_data: WorldData) -> WgpuState {
+    // Fails when no adapter fits, the window is larger than the textures of the GPU or one of the
+    // shaders does not compile.
+    pub async fn new(
+        window: Arc<Window>,
+        world_data: &WorldData,
+        adapter: &AdapterChoice,
+    ) -> anyhow::Result<WgpuState> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let size = window.inner_size();
+        let surface = instance.create_surface(window.clone()).unwrap();
+        Self::with_target(instance, Some((window, surface)), size, world_data, adapter).await
+    }
+
+    // Render to an offscreen texture only, read it back with read_pixels or save_screenshot.
+    // Fails when no adapter supports the features the shader needs or a texture of that size.
+    pub async fn new_headless(
+        width: u32,
+        height: u32,
+        world_data: &WorldData,
+        adapter: &AdapterChoice,
+    ) -> anyhow::Result<WgpuState> {
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions::default())
-            .await
-            .unwrap();
+        let size = winit::dpi::PhysicalSize::new(width, height);
+        Self::with_target(instance, None, size, world_data, adapter).await
+    }
+
+    async fn with_target(
+        instance: wgpu::Instance,
+        window_surface: Option<(Arc<Window>, wgpu::Surface<'static>)>,
+        size: winit::dpi::PhysicalSize<u32>,
+        world_data: &WorldData,
+        adapter: &AdapterChoice,
+    ) -> anyhow::Result<WgpuState> {
+        let surface = window_surface.as_ref().map(|(_, surface)| surface);
+        let adapter = choose_adapter(&instance, surface, adapter).await?;
         println!("Running on Adapter: {:#?}", adapter.get_info());
         let (device, queue) = adapter
             .request_device(&wgpu::DeviceDescriptor {
                 label: None,
-                required_features: wgpu::Features::BGRA8UNORM_STORAGE,
-                required_limits: wgpu::Limits::downlevel_defaults(),
+                // Read-write access to the Rgba32Float accumulation texture is adapter specific.
+                // Timestamp queries are only used for profiling, so they are optional.
+                required_features: wgpu::Features::BGRA8UNORM_STORAGE
+                    | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+                    | (adapter.features() & wgpu::Features::TIMESTAMP_QUERY),
+                // The downlevel defaults only allow 4 storage buffers and textures per stage, and
+                // textures up to 2048 pixels wide, less than a 4K frame.
+                required_limits: wgpu::Limits {
+                    max_storage_buffers_per_shader_stage: 16,
+                    max_storage_textures_per_shader_stage: 6,
+                    max_texture_dimension_2d: adapter.limits().max_texture_dimension_2d,
+                    ..wgpu::Limits::downlevel_defaults()
+                },
                 experimental_features: wgpu::ExperimentalFeatures::disabled(),
                 memory_hints: wgpu::MemoryHints::Performance,
                 trace: wgpu::Trace::Off,
             })
-            .await
-            .unwrap();
-
-        let size = window.inner_size();
-
-        let surface = instance.create_surface(window.clone()).unwrap();
-        let cap = surface.get_capabilities(&adapter);
-        let surface_format = cap.formats[0];
+            .await?;
+        check_size(&device, size)?;
 
-        let compute_shader = device.create_shader_module(wgpu::include_wgsl!("compute.wgsl"));
+        let target = validated(&device, "blit.wgsl", || {
+            window_surface.map(|(window, surface)| {
+                let cap = surface.get_capabilities(&adapter);
+                let format = choose_surface_format(&cap.formats);
+                log::info!("Using surface format {format:?}");
+                create_surface_target(&device, window, surface, format, cap.present_modes)
+            })
+        })
+        .await?;
 
-        let compute_texture_size = wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
+        let compute_shader = validated(&device, "compute.wgsl", || {
+            device.create_shader_module(wgpu::include_wgsl!("compute.wgsl"))
+        })
+        .await?;
 
-            depth_or_array_layers: 1,
-        };
+        let render_scale = 1.0;
+        let max_size = device.limits().max_texture_dimension_2d;
+        let compute_texture_size = scaled_size(size, render_scale, max_size);
+        let compute_texture = create_compute_texture(&device, compute_texture_size);
 
-        let compute_texture = device.create_texture(&wgpu::TextureDescriptor {
-            size: compute_texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8Unorm,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::STORAGE_BINDING,
-            label: Some("color_buffer"),
-            view_formats: &[],
-        });
+        let accumulation_texture = create_accumulation_texture(&device, compute_texture_size);
+        let weight_texture = create_weight_texture(&device, compute_texture_size);
+        let depth_texture = create_depth_texture(&device, compute_texture_size);
+        let albedo_texture = create_gbuffer_texture(&device, compute_texture_size, "albedo_buffer");
+        let normal_texture = create_gbuffer_texture(&device, compute_texture_size, "normal_buffer");
 
         let world_uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("World Buffer"),
-            contents: bytemuck::cast_slice(&[world_data]),
+            contents: bytemuck::cast_slice(&[*world_data.uniform()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let sphere_buffer = create_storage_buffer(&device, "Sphere Buffer", world_data.spheres());
+        let material_buffer =
+            create_storage_buffer(&device, "Material Buffer", world_data.materials());
+        let triangle_buffer =
+            create_storage_buffer(&device, "Triangle Buffer", world_data.triangles());
+        let box_buffer = create_storage_buffer(&device, "Box Buffer", world_data.boxes());
+        let quad_buffer = create_storage_buffer(&device, "Quad Buffer", world_data.quads());
+        let cylinder_buffer =
+            create_storage_buffer(&device, "Cylinder Buffer", world_data.cylinders());
+        let plane_buffer = create_storage_buffer(&device, "Plane Buffer", world_data.planes());
+        let transformed_sphere_buffer = create_storage_buffer(
+            &device,
+            "Transformed Sphere Buffer",
+            world_data.transformed_spheres(),
+        );
+        let instance_buffer =
+            create_storage_buffer(&device, "Instance Buffer", world_data.instances());
+        let instance_group_buffer = create_storage_buffer(
+            &device,
+            "Instance Group Buffer",
+            world_data.instance_groups(),
+        );
+        let instance_bvh_buffer = create_storage_buffer(
+            &device,
+            "Instance BVH Buffer",
+            world_data.instance_bvh_nodes(),
+        );
+        let bvh_buffer = create_storage_buffer(&device, "BVH Buffer", world_data.bvh_nodes());
+        let light_buffer = create_storage_buffer(&device, "Light Buffer", world_data.lights());
+        let emissive_sphere_buffer = create_storage_buffer(
+            &device,
+            "Emissive Sphere Buffer",
+            world_data.emissive_spheres(),
+        );
+        let sphere_velocity_buffer = create_storage_buffer(
+            &device,
+            "Sphere Velocity Buffer",
+            world_data.sphere_velocities(),
+        );
+        let active_pixel_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Active Pixel Buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let tile_origin_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile Origin Buffer"),
+            size: size_of::<[u32; 2]>() as u64,
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let environment_texture =
+            create_environment_texture(&device, &queue, world_data.environment());
+        // 32-bit float textures are not filterable without an optional feature.
+        let environment_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let blue_noise_texture = create_blue_noise_texture(&device, &queue);
+        let image_texture = create_image_texture(&device, &queue, world_data.textures());
+        // Wraps around the spheres horizontally.
+        let image_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Image Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let compute_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // Output texture
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            format: wgpu::TextureFormat::Bgra8Unorm,
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Spheres
+                    storage_buffer_layout_entry(2),
+                    // Materials
+                    storage_buffer_layout_entry(3),
+                    // Accumulation texture
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            format: wgpu::TextureFormat::Rgba32Float,
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                        },
+                        count: None,
+                    },
+                    // Triangles
+                    storage_buffer_layout_entry(5),
+                    // Boxes
+                    storage_buffer_layout_entry(6),
+                    // BVH nodes
+                    storage_buffer_layout_entry(7),
+                    // Environment map
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                        count: None,
+                    },
+                    // Point lights
+                    storage_buffer_layout_entry(10),
+                    // Emissive sphere indices
+                    storage_buffer_layout_entry(11),
+                    // Sphere velocities
+                    storage_buffer_layout_entry(12),
+                    // Quads
+                    storage_buffer_layout_entry(13),
+                    // Cylinders
+                    storage_buffer_layout_entry(14),
+                    // Depth of the first hit
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 15,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            format: wgpu::TextureFormat::R32Float,
+                            access: wgpu::StorageTextureAccess::WriteOnly,
+                        },
+                        count: None,
+                    },
+                    // Albedo and normal of the first hit
+                    gbuffer_layout_entry(16),
+                    gbuffer_layout_entry(17),
+                    // Planes and disks
+                    storage_buffer_layout_entry(18),
+                    // Transformed spheres
+                    storage_buffer_layout_entry(19),
+                    // Instanced spheres, their groups and the hierarchy over each group
+                    storage_buffer_layout_entry(20),
+                    storage_buffer_layout_entry(21),
+                    storage_buffer_layout_entry(28),
+                    // Blue noise tile
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 22,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // Image textures
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 23,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 24,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // Active pixel counter
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 25,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Tile origin
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 26,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Filter weights of the accumulation
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 27,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::StorageTexture {
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            format: wgpu::TextureFormat::R32Float,
+                            access: wgpu::StorageTextureAccess::ReadWrite,
+                        },
+                        count: None,
+                    },
+                ],
+            });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: None,
+            bind_group_layouts: &[&compute_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let (compute_pipeline, resolve_pipeline) = validated(&device, "compute.wgsl", || {
+            (
+                create_compute_pipeline(&device, &pipeline_layout, &compute_shader, "main_compute"),
+                create_compute_pipeline(&device, &pipeline_layout, &compute_shader, "resolve"),
+            )
+        })
+        .await?;
+
+        let denoiser = validated(&device, "denoise.wgsl", || {
+            Denoiser::new(&device, compute_texture_size, WORKGROUP_SIZE)
+        })
+        .await?;
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        // Headless renders are done before anyone could edit the shader.
+        #[cfg(debug_assertions)]
+        let shader_watcher = target.as_ref().and_then(|_| {
+            ShaderWatcher::new()
+                .inspect_err(|err| log::warn!("Not watching {COMPUTE_SHADER_PATH}: {err}"))
+                .ok()
         });
 
         let state = WgpuState {
-            window,
+            target,
             device,
             queue,
             size,
-            surface,
-            surface_format,
-            compute_shader,
+            render_scale,
             compute_texture,
             compute_texture_size,
+            accumulation_texture,
+            weight_texture,
+            depth_texture,
+            albedo_texture,
+            normal_texture,
             world_uniform,
+            sphere_buffer,
+            material_buffer,
+            triangle_buffer,
+            box_buffer,
+            quad_buffer,
+            cylinder_buffer,
+            plane_buffer,
+            transformed_sphere_buffer,
+            instance_buffer,
+            instance_group_buffer,
+            instance_bvh_buffer,
+            bvh_buffer,
+            light_buffer,
+            emissive_sphere_buffer,
+            sphere_velocity_buffer,
+            active_pixel_buffer,
+            tile_origin_buffer,
+            environment_texture,
+            environment_sampler,
+            blue_noise_texture,
+            image_texture,
+            image_sampler,
+            compute_bind_group_layout,
+            #[cfg(debug_assertions)]
+            pipeline_layout,
+            compute_pipeline,
+            resolve_pipeline,
+            compute_bind_group: None,
+            blit_bind_group: None,
+            srgb_output: world_data.srgb_output(),
+            present_mode: wgpu::PresentMode::AutoVsync,
+            denoiser,
+            denoise: world_data.denoise(),
+            gpu_timer,
+            tile_size: None,
+            #[cfg(debug_assertions)]
+            shader_watcher,
         };
 
         // Configure surface for the first time
         state.configure_surface();
 
-        state
+        Ok(state)
+    }
+
+    pub fn get_window(&self) -> Option<&Window> {
+        self.target.as_ref().map(|target| &*target.window)
     }
 
-    pub fn get_window(&self) -> &Window {
-        &self.window
+    // Duration of the last measured compute dispatch, in milliseconds.
+    pub fn last_frame_gpu_time(&self) -> Option<f32> {
+        self.gpu_timer.as_ref().and_then(GpuTimer::last_time_ms)
     }
 
     fn configure_surface(&self) {
+        let Some(target) = &self.target else {
+            return;
+        };
         let surface_config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_DST,
-            format: self.surface_format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: target.format,
             // Request compatibility with the sRGB-format texture view we‘re going to create later.
-            view_formats: vec![self.surface_format.add_srgb_suffix()],
+            view_formats: vec![target.format.add_srgb_suffix()],
             alpha_mode: wgpu::CompositeAlphaMode::Auto,
             width: self.size.width,
             height: self.size.height,
             desired_maximum_frame_latency: 2,
-            present_mode: wgpu::PresentMode::AutoVsync,
+            present_mode: self.present_mode,
         };
-        self.surface.configure(&self.device, &surface_config);
+        target.surface.configure(&self.device, &surface_config);
     }
-    fn reconfigure_compute_texture(&mut self) {
-        
-        self.compute_texture_size = wgpu::Extent3d {
-            width: self.size.width,
-            height: self.size.height,
 
-            depth_or_array_layers: 1,
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.present_mode
+    }
+
+    // Modes the surface does not support fall back to the automatic one with the same vsync
+    // behaviour, which is always available. Returns the mode actually used.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> wgpu::PresentMode {
+        let Some(target) = &self.target else {
+            return self.present_mode;
         };
+        self.present_mode = match mode {
+            wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync => mode,
+            _ if target.present_modes.contains(&mode) => mode,
+            wgpu::PresentMode::Fifo | wgpu::PresentMode::FifoRelaxed => {
+                wgpu::PresentMode::AutoVsync
+            }
+            _ => wgpu::PresentMode::AutoNoVsync,
+        };
+        if !self.is_minimized() {
+            self.configure_surface();
+        }
+        self.present_mode
+    }
 
-        self.compute_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            size: self.compute_texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Bgra8Unorm,
-            usage: wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::STORAGE_BINDING,
-            label: Some("color_buffer"),
-            view_formats: &[],
-        });
+    fn reconfigure_compute_texture(&mut self) {
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        self.compute_texture_size = scaled_size(self.size, self.render_scale, max_size);
+        self.compute_texture = create_compute_texture(&self.device, self.compute_texture_size);
+        self.accumulation_texture =
+            create_accumulation_texture(&self.device, self.compute_texture_size);
+        self.weight_texture = create_weight_texture(&self.device, self.compute_texture_size);
+        self.depth_texture = create_depth_texture(&self.device, self.compute_texture_size);
+        self.albedo_texture =
+            create_gbuffer_texture(&self.device, self.compute_texture_size, "albedo_buffer");
+        self.normal_texture =
+            create_gbuffer_texture(&self.device, self.compute_texture_size, "normal_buffer");
+        self.denoiser
+            .resize(&self.device, self.compute_texture_size);
+        self.compute_bind_group = None;
+        self.blit_bind_group = None;
+    }
 
-    } 
-    pub fn rewrite_world_data(&mut self, world_data: WorldData) {
-        self.queue
-            .write_buffer(&self.world_uniform, 0, bytemuck::cast_slice(&[world_data]));
+    // Resolution the scene is traced at, the camera must be given this size rather than the window's.
+    pub fn render_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(
+            self.compute_texture_size.width,
+            self.compute_texture_size.height,
+        )
     }
-    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
-        self.size = new_size;
-        self.reconfigure_compute_texture();
-        self.configure_surface();
+
+    pub fn render_scale(&self) -> f32 {
+        self.render_scale
     }
 
-    pub fn render(&mut self) {
-        let mut encoder = self.device.create_command_encoder(&Default::default());
+    // Prints the size of every texture and buffer the compute shader reads, next to the device limits
+    // the scene could run into. The denoiser and the surface are left out.
+    pub fn report_memory(&self) {
+        let textures = [
+            ("Compute texture", &self.compute_texture),
+            ("Accumulation texture", &self.accumulation_texture),
+            ("Weight texture", &self.weight_texture),
+            ("Depth texture", &self.depth_texture),
+            ("Albedo texture", &self.albedo_texture),
+            ("Normal texture", &self.normal_texture),
+            ("Environment map", &self.environment_texture),
+            ("Blue noise tile", &self.blue_noise_texture),
+            ("Image textures", &self.image_texture),
+        ];
+        let storage_buffers = [
+            ("Spheres", &self.sphere_buffer),
+            ("Materials", &self.material_buffer),
+            ("Triangles", &self.triangle_buffer),
+            ("Boxes", &self.box_buffer),
+            ("Quads", &self.quad_buffer),
+            ("Cylinders", &self.cylinder_buffer),
+            ("Planes", &self.plane_buffer),
+            ("Transformed spheres", &self.transformed_sphere_buffer),
+            ("Instances", &self.instance_buffer),
+            ("Instance groups", &self.instance_group_buffer),
+            ("Instance BVH", &self.instance_bvh_buffer),
+            ("BVH nodes", &self.bvh_buffer),
+            ("Point lights", &self.light_buffer),
+            ("Emissive spheres", &self.emissive_sphere_buffer),
+            ("Sphere velocities", &self.sphere_velocity_buffer),
+            ("Active pixels", &self.active_pixel_buffer),
+            ("Tile origin", &self.tile_origin_buffer),
+        ];
 
-        // Create texture view
-        let surface_texture = self
-            .surface
-            .get_current_texture()
-            .expect("failed to acquire next swapchain texture");
-
-        let surface_view = surface_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor {
-                // Without add_srgb_suffix() the image we will be working with
-                // might not be "gamma correct".
-                format: Some(self.surface_format.add_srgb_suffix()),
-                ..Default::default()
-            });
+        let mut total = 0;
+        println!("GPU memory:");
+        for (name, texture) in textures {
+            let size = texture.size();
+            let bytes_per_pixel = texture.format().block_copy_size(None).unwrap_or(0);
+            let pixels = size.width as u64 * size.height as u64 * size.depth_or_array_layers as u64;
+            let bytes = pixels * bytes_per_pixel as u64;
+            total += bytes;
+            println!(
+                "  {name:<22}{:>12}  {}x{} {:?}",
+                format_bytes(bytes),
+                size.width,
+                size.height,
+                texture.format()
+            );
+        }
+        let uniform = self.world_uniform.size();
+        total += uniform;
+        println!("  {:<22}{:>12}", "World uniform", format_bytes(uniform));
+        let mut largest_storage = 0;
+        for (name, buffer) in storage_buffers {
+            total += buffer.size();
+            largest_storage = largest_storage.max(buffer.size());
+            println!("  {name:<22}{:>12}", format_bytes(buffer.size()));
+        }
+        println!("  {:<22}{:>12}", "Total", format_bytes(total));
 
-        let bind_group_layout =
-            self.device
-                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                    label: None,
-                    entries: &[
-                        // Input buffer
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 0,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::StorageTexture {
-                                view_dimension: wgpu::TextureViewDimension::D2,
-                                format: wgpu::TextureFormat::Bgra8Unorm,
-                                access: wgpu::StorageTextureAccess::WriteOnly,
-                            },
-                            count: None,
-                        },
-                        wgpu::BindGroupLayoutEntry {
-                            binding: 1,
-                            visibility: wgpu::ShaderStages::COMPUTE,
-                            ty: wgpu::BindingType::Buffer {
-                                ty: wgpu::BufferBindingType::Uniform,
-                                has_dynamic_offset: false,
-                                min_binding_size: None,
-                            },
-                            count: None,
-                        },
-                    ],
-                });
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let limits = self.device.limits();
+        println!("Device limits:");
+        println!(
+            "  Largest storage buffer {} of {} per binding",
+            format_bytes(largest_storage),
+            format_bytes(limits.max_storage_buffer_binding_size as u64)
+        );
+        println!(
+            "  World uniform {} of {} per binding",
+            format_bytes(uniform),
+            format_bytes(limits.max_uniform_buffer_binding_size as u64)
+        );
+        println!(
+            "  Buffer size up to {}",
+            format_bytes(limits.max_buffer_size)
+        );
+        println!("  Textures up to {0}x{0}", limits.max_texture_dimension_2d);
+    }
+
+    pub fn set_render_scale(&mut self, render_scale: f32) {
+        self.render_scale = render_scale.clamp(0.1, 2.0);
+        if !self.is_minimized() {
+            self.reconfigure_compute_texture();
+        }
+    }
+
+    fn create_blit_bind_group(&self, target: &SurfaceTarget) -> BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Blit Bind Group"),
+            layout: &target.blit_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .compute_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&target.blit_sampler),
+                },
+            ],
+        })
+    }
+    fn create_compute_bind_group(&self) -> BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout: &bind_group_layout,
+            layout: &self.compute_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -201,77 +1226,727 @@ impl WgpuState {
                     binding: 1,
                     resource: self.world_uniform.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.material_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .accumulation_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.triangle_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.box_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: self.bvh_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .environment_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&self.environment_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: self.emissive_sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: self.sphere_velocity_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: self.quad_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: self.cylinder_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .depth_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 16,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .albedo_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 17,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .normal_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 18,
+                    resource: self.plane_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 19,
+                    resource: self.transformed_sphere_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 20,
+                    resource: self.instance_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 21,
+                    resource: self.instance_group_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 28,
+                    resource: self.instance_bvh_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 22,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .blue_noise_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 23,
+                    resource: wgpu::BindingResource::TextureView(&self.image_texture.create_view(
+                        &TextureViewDescriptor {
+                            dimension: Some(wgpu::TextureViewDimension::D2Array),
+                            ..Default::default()
+                        },
+                    )),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 24,
+                    resource: wgpu::BindingResource::Sampler(&self.image_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 25,
+                    resource: self.active_pixel_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 26,
+                    resource: self.tile_origin_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 27,
+                    resource: wgpu::BindingResource::TextureView(
+                        &self
+                            .weight_texture
+                            .create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
             ],
+        })
+    }
+    // Copy a whole texture back to the CPU, rows are returned tightly packed.
+    fn read_texture(&self, texture: &Texture, bytes_per_pixel: u32) -> anyhow::Result<Vec<u8>> {
+        self.read_texture_region(
+            texture,
+            wgpu::Origin3d::ZERO,
+            texture.size(),
+            bytes_per_pixel,
+        )
+    }
+
+    fn read_texture_region(
+        &self,
+        texture: &Texture,
+        origin: wgpu::Origin3d,
+        size: Extent3d,
+        bytes_per_pixel: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        let unpadded_bytes_per_row = size.width * bytes_per_pixel;
+        // copy_texture_to_buffer requires rows aligned to 256 bytes.
+        let padded_bytes_per_row = unpadded_bytes_per_row
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * size.height) as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
         });
 
-        let pipeline_layout = self
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: None,
-                bind_group_layouts: &[&bind_group_layout],
-                push_constant_ranges: &[],
-            });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        self.queue.submit([encoder.finish()]);
 
-        let pipeline = self
-            .device
-            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: None,
-                layout: Some(&pipeline_layout),
-                module: &self.compute_shader,
-                entry_point: Some("main_compute"),
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-                cache: None,
-            });
+        let slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
 
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: None,
-            timestamp_writes: None,
+        let mapped = slice.get_mapped_range();
+        let mut bytes = Vec::with_capacity((unpadded_bytes_per_row * size.height) as usize);
+        for row in mapped.chunks(padded_bytes_per_row as usize) {
+            bytes.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+        drop(mapped);
+        readback_buffer.unmap();
+
+        Ok(bytes)
+    }
+
+    // The last rendered frame as RGBA8, in the resolution returned by render_size.
+    // Encoded like the window shows it: sRGB, or whatever the shader applied, see
+    // WorldData::set_output_colorspace.
+    pub fn read_pixels(&self) -> anyhow::Result<Vec<u8>> {
+        let bgra = self.read_texture(&self.compute_texture, 4)?;
+        let encode = |value: u8| {
+            if self.srgb_output {
+                linear_to_srgb(value)
+            } else {
+                value
+            }
+        };
+        Ok(bgra
+            .chunks_exact(4)
+            .flat_map(|p| [encode(p[2]), encode(p[1]), encode(p[0]), p[3]])
+            .collect())
+    }
+
+    // Distance to the surface seen through the center of a pixel of the last frame, in render_size
+    // coordinates. None when the pixel shows the background.
+    pub fn read_depth_at(&self, x: u32, y: u32) -> anyhow::Result<Option<f32>> {
+        let bytes = self.read_pixel_at(&self.depth_texture, x, y, 4)?;
+        let depth = f32::from_le_bytes(bytes[..4].try_into()?);
+        Ok((depth >= 0.0).then_some(depth))
+    }
+
+    // Index in WorldData::spheres of the sphere seen through the center of a pixel of the last
+    // frame, in render_size coordinates. None when the pixel shows anything else.
+    pub fn pick_sphere_at(&self, x: u32, y: u32) -> anyhow::Result<Option<usize>> {
+        let bytes = self.read_pixel_at(&self.normal_texture, x, y, 16)?;
+        let sphere = f32::from_le_bytes(bytes[12..16].try_into()?);
+        Ok((sphere >= 0.0).then_some(sphere as usize))
+    }
+
+    fn read_pixel_at(
+        &self,
+        texture: &Texture,
+        x: u32,
+        y: u32,
+        bytes_per_pixel: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        let size = self.compute_texture_size;
+        let origin = wgpu::Origin3d {
+            x: x.min(size.width - 1),
+            y: y.min(size.height - 1),
+            z: 0,
+        };
+        let pixel = Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        self.read_texture_region(texture, origin, pixel, bytes_per_pixel)
+    }
+
+    // Blocks until every frame submitted so far has been traced.
+    pub fn wait_idle(&self) -> anyhow::Result<()> {
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        Ok(())
+    }
+
+    // Fraction of the pixels the last frame traced, the others had converged. Only counted with
+    // adaptive sampling on.
+    pub fn active_pixel_fraction(&self) -> anyhow::Result<f32> {
+        let readback_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Active Pixel Readback Buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        encoder.copy_buffer_to_buffer(&self.active_pixel_buffer, 0, &readback_buffer, 0, None);
+        self.queue.submit([encoder.finish()]);
+
+        let slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
         });
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
 
-        // Set the pipeline that we want to use
-        compute_pass.set_pipeline(&pipeline);
-        // Set the bind group that we want to use
-        compute_pass.set_bind_group(0, &bind_group, &[]);
+        let count: u32 = bytemuck::pod_read_unaligned(&slice.get_mapped_range());
+        let size = self.compute_texture_size;
+        Ok(count as f32 / (size.width * size.height) as f32)
+    }
 
-        compute_pass.dispatch_workgroups(
-            (self.size.width).div_ceil(8),
-            (self.size.height).div_ceil(8),
-            1,
-        );
+    pub fn read_gbuffer(&self) -> anyhow::Result<GBuffer> {
+        let accumulated = self.read_texture(&self.accumulation_texture, 16)?;
+        let weights = self.read_texture(&self.weight_texture, 4)?;
+        let color = accumulated
+            .chunks_exact(16)
+            .zip(weights.chunks_exact(4))
+            .flat_map(|(sums, weight)| {
+                let sums: [f32; 4] = bytemuck::pod_read_unaligned(sums);
+                let weight = bytemuck::pod_read_unaligned::<f32>(weight).max(1e-8);
+                let [r, g, b] = [sums[0], sums[1], sums[2]].map(|sum| sum / weight);
+                bytemuck::cast::<_, [u8; 16]>([r, g, b, 1.0])
+            })
+            .collect();
+        Ok(GBuffer {
+            color,
+            albedo: self.read_texture(&self.albedo_texture, 16)?,
+            normal: self.read_texture(&self.normal_texture, 16)?,
+        })
+    }
+
+    pub fn save_screenshot(&self, path: &Path) -> anyhow::Result<()> {
+        let rgba = self.read_pixels()?;
+        image::save_buffer(
+            path,
+            &rgba,
+            self.compute_texture_size.width,
+            self.compute_texture_size.height,
+            image::ExtendedColorType::Rgba8,
+        )?;
+        Ok(())
+    }
 
-        drop(compute_pass);
+    // Like save_screenshot, but only the readback happens here. The PNG is encoded and written by
+    // the thread of writer.
+    pub fn queue_screenshot(&self, writer: &ImageWriter, path: PathBuf) -> anyhow::Result<()> {
+        let rgba = self.read_pixels()?;
+        let size = self.compute_texture_size;
+        writer.save(path, rgba, size.width, size.height);
+        Ok(())
+    }
 
-        encoder.copy_texture_to_texture(
-            self.compute_texture.as_image_copy(),
-            surface_texture.texture.as_image_copy(),
-            self.compute_texture_size,
+    pub fn rewrite_world_data(&mut self, world_data: &WorldData) {
+        self.srgb_output = world_data.srgb_output();
+        self.denoise = world_data.denoise();
+        self.queue.write_buffer(
+            &self.world_uniform,
+            0,
+            bytemuck::cast_slice(&[*world_data.uniform()]),
+        );
+        let mut recreated = write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.sphere_buffer,
+            "Sphere Buffer",
+            world_data.spheres(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.material_buffer,
+            "Material Buffer",
+            world_data.materials(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.triangle_buffer,
+            "Triangle Buffer",
+            world_data.triangles(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.box_buffer,
+            "Box Buffer",
+            world_data.boxes(),
         );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.bvh_buffer,
+            "BVH Buffer",
+            world_data.bvh_nodes(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.light_buffer,
+            "Light Buffer",
+            world_data.lights(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.emissive_sphere_buffer,
+            "Emissive Sphere Buffer",
+            world_data.emissive_spheres(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.sphere_velocity_buffer,
+            "Sphere Velocity Buffer",
+            world_data.sphere_velocities(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.quad_buffer,
+            "Quad Buffer",
+            world_data.quads(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.cylinder_buffer,
+            "Cylinder Buffer",
+            world_data.cylinders(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.plane_buffer,
+            "Plane Buffer",
+            world_data.planes(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.transformed_sphere_buffer,
+            "Transformed Sphere Buffer",
+            world_data.transformed_spheres(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.instance_buffer,
+            "Instance Buffer",
+            world_data.instances(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.instance_group_buffer,
+            "Instance Group Buffer",
+            world_data.instance_groups(),
+        );
+        recreated |= write_storage_buffer(
+            &self.device,
+            &self.queue,
+            &mut self.instance_bvh_buffer,
+            "Instance BVH Buffer",
+            world_data.instance_bvh_nodes(),
+        );
+        if recreated {
+            self.compute_bind_group = None;
+        }
+    }
+    // A minimized window reports a zero size: keep the old textures and stop rendering until restored.
+    // Fails and keeps the old size when the window is larger than the textures of the GPU.
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) -> anyhow::Result<()> {
+        check_size(&self.device, new_size)?;
+        self.size = new_size;
+        if self.is_minimized() {
+            return Ok(());
+        }
+        self.reconfigure_compute_texture();
+        self.configure_surface();
+        Ok(())
+    }
 
-        let renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: None,
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: &surface_view,
-                depth_slice: None,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Load,
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            timestamp_writes: None,
-            occlusion_query_set: None,
+    fn is_minimized(&self) -> bool {
+        self.size.width == 0 || self.size.height == 0
+    }
+
+    // Debug builds rebuild the compute pipeline from compute.wgsl on disk whenever it changes, and
+    // keep the previous one if it does not compile. True when the pipeline was replaced, the
+    // accumulated samples then come from the old shader.
+    pub fn reload_shader_if_changed(&mut self) -> bool {
+        #[cfg(debug_assertions)]
+        if self
+            .shader_watcher
+            .as_ref()
+            .is_some_and(ShaderWatcher::changed)
+        {
+            return self.reload_compute_shader();
+        }
+        false
+    }
+
+    #[cfg(debug_assertions)]
+    fn reload_compute_shader(&mut self) -> bool {
+        let source = match std::fs::read_to_string(COMPUTE_SHADER_PATH) {
+            Ok(source) => source,
+            Err(err) => {
+                log::error!("Failed to read {COMPUTE_SHADER_PATH}: {err}");
+                return false;
+            }
+        };
+        let pipeline = validated(&self.device, "compute.wgsl", || {
+            let module = self
+                .device
+                .create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some("compute.wgsl"),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+            (
+                create_compute_pipeline(
+                    &self.device,
+                    &self.pipeline_layout,
+                    &module,
+                    "main_compute",
+                ),
+                create_compute_pipeline(&self.device, &self.pipeline_layout, &module, "resolve"),
+            )
         });
+        match pollster::block_on(pipeline) {
+            Ok((compute_pipeline, resolve_pipeline)) => {
+                self.compute_pipeline = compute_pipeline;
+                self.resolve_pipeline = resolve_pipeline;
+            }
+            Err(err) => {
+                log::error!("{err}");
+                return false;
+            }
+        }
+        println!("Reloaded compute.wgsl");
+        true
+    }
+
+    pub fn tile_size(&self) -> Option<u32> {
+        self.tile_size
+    }
+
+    // Split each dispatch into square tiles submitted one after the other, so that no command
+    // buffer runs long enough for the OS to reset the GPU, which happens on Windows after two
+    // seconds. Rounded up to a multiple of the workgroup size. The GPU time is only measured
+    // without tiles.
+    pub fn set_tile_size(&mut self, tile_size: Option<u32>) {
+        self.tile_size = tile_size.map(|size| size.max(1).next_multiple_of(WORKGROUP_SIZE));
+    }
+
+    // Top left corner and size of each tile covering the compute texture, row by row.
+    fn tiles(&self) -> Vec<[u32; 4]> {
+        let Extent3d { width, height, .. } = self.compute_texture_size;
+        let Some(tile_size) = self.tile_size else {
+            return vec![[0, 0, width, height]];
+        };
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(tile_size as usize) {
+            for x in (0..width).step_by(tile_size as usize) {
+                tiles.push([x, y, tile_size.min(width - x), tile_size.min(height - y)]);
+            }
+        }
+        tiles
+    }
+
+    // Trace a new frame and present it.
+    pub fn render(&mut self) {
+        self.draw(Compute::Trace, true);
+    }
+
+    // Present the last frame again without tracing anything.
+    pub fn present(&mut self) {
+        self.draw(Compute::Nothing, true);
+    }
 
-        // If you wanted to call any drawing commands, they would go here.
+    // Trace a frame into the accumulation without presenting it, the next render shows them all.
+    pub fn dispatch(&mut self) {
+        self.draw(Compute::Trace, false);
+    }
+
+    // Present the accumulated frames again with the current exposure, tone map and output
+    // encoding, without tracing anything.
+    pub fn resolve(&mut self) {
+        self.draw(Compute::Resolve, true);
+    }
+
+    fn draw(&mut self, compute: Compute, present: bool) {
+        if self.is_minimized() {
+            return;
+        }
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            self.device.poll(wgpu::PollType::Poll).unwrap();
+            gpu_timer.poll();
+        }
+
+        if self.compute_bind_group.is_none() {
+            self.compute_bind_group = Some(self.create_compute_bind_group());
+        }
+        if let (Some(target), None) = (&self.target, &self.blit_bind_group) {
+            self.blit_bind_group = Some(self.create_blit_bind_group(target));
+        }
+        let bind_group = self.compute_bind_group.as_ref().unwrap();
+
+        // Nothing to present when rendering headless, the result stays in compute_texture.
+        let surface_texture = match self.target.as_ref().filter(|_| present) {
+            Some(target) => match target.surface.get_current_texture() {
+                Ok(surface_texture) => Some(surface_texture),
+                // Happens while resizing or after a GPU reset, the next frame gets a fresh texture.
+                Err(wgpu::SurfaceError::Outdated | wgpu::SurfaceError::Lost) => {
+                    self.configure_surface();
+                    return;
+                }
+                Err(wgpu::SurfaceError::OutOfMemory) => {
+                    panic!("out of memory while acquiring the next swapchain texture")
+                }
+                Err(err) => {
+                    log::warn!("Skipping a frame: {err}");
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        let gpu_timer = self.gpu_timer.as_ref().filter(|_| self.tile_size.is_none());
+
+        if compute == Compute::Trace {
+            encoder.clear_buffer(&self.active_pixel_buffer, 0, None);
+            for (i, [x, y, width, height]) in self.tiles().into_iter().enumerate() {
+                if i > 0 {
+                    self.queue.submit([encoder.finish()]);
+                    encoder = self.device.create_command_encoder(&Default::default());
+                }
+                // Written before the submission of the tile, after the one of the previous tile.
+                self.queue
+                    .write_buffer(&self.tile_origin_buffer, 0, bytemuck::cast_slice(&[x, y]));
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: None,
+                    timestamp_writes: gpu_timer.and_then(GpuTimer::compute_pass_writes),
+                });
+
+                // Set the pipeline that we want to use
+                compute_pass.set_pipeline(&self.compute_pipeline);
+                // Set the bind group that we want to use
+                compute_pass.set_bind_group(0, bind_group, &[]);
+
+                compute_pass.dispatch_workgroups(
+                    width.div_ceil(WORKGROUP_SIZE),
+                    height.div_ceil(WORKGROUP_SIZE),
+                    1,
+                );
+            }
+
+            if let Some(gpu_timer) = gpu_timer {
+                gpu_timer.resolve(&mut encoder);
+            }
+        } else if compute == Compute::Resolve {
+            let mut compute_pass = encoder.begin_compute_pass(&Default::default());
+            compute_pass.set_pipeline(&self.resolve_pipeline);
+            compute_pass.set_bind_group(0, bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                self.compute_texture_size.width.div_ceil(WORKGROUP_SIZE),
+                self.compute_texture_size.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        if compute != Compute::Nothing && self.denoise && present {
+            self.denoiser.encode(
+                &self.device,
+                &mut encoder,
+                &self.compute_texture,
+                &self.albedo_texture,
+                &self.normal_texture,
+            );
+        }
+
+        if let (Some(target), Some(surface_texture)) = (&self.target, &surface_texture) {
+            let surface_view = surface_texture
+                .texture
+                .create_view(&wgpu::TextureViewDescriptor {
+                    // Without add_srgb_suffix() the image we will be working with
+                    // might not be "gamma correct", unless the shader already took care of it.
+                    format: Some(if self.srgb_output {
+                        target.format.add_srgb_suffix()
+                    } else {
+                        target.format
+                    }),
+                    ..Default::default()
+                });
+
+            let mut renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: None,
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &surface_view,
+                    depth_slice: None,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            renderpass.set_pipeline(if self.srgb_output {
+                &target.srgb_blit_pipeline
+            } else {
+                &target.linear_blit_pipeline
+            });
+            renderpass.set_bind_group(0, self.blit_bind_group.as_ref(), &[]);
+            renderpass.draw(0..3, 0..1);
 
-        // End the renderpass.
-        drop(renderpass);
+            // End the renderpass.
+            drop(renderpass);
+        }
 
         // Submit the command in the queue to execute
         self.queue.submit([encoder.finish()]);
+        let timed = compute == Compute::Trace && self.tile_size.is_none();
+        if let (true, Some(gpu_timer)) = (timed, &mut self.gpu_timer) {
+            gpu_timer.after_submit();
+        }
 
-        self.window.pre_present_notify();
-        surface_texture.present();
+        if let (Some(target), Some(surface_texture)) = (&self.target, surface_texture) {
+            target.window.pre_present_notify();
+            surface_texture.present();
+        }
     }
 }
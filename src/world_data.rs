@@ -1,11 +1,29 @@
-use crate::math::*;
+use crate::bvh::{self, BvhNode};
 use crate::material::Material;
+use crate::math::*;
+use crate::scene::{
+    AmbientOcclusionDescription, BoxDescription, CameraDescription, CylinderDescription,
+    DiskDescription, InstancesDescription, LightDescription, MaterialDescription, MeshDescription,
+    PlaneDescription, QuadDescription, SceneDescription, SceneError, SkyDescription,
+    SphereDescription, SpotlightDescription, TransformedSphereDescription,
+};
+use crate::scene_builder::SceneBuilder;
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+// Everything that fits in the uniform buffer. The geometry lives in storage buffers.
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-pub struct WorldData {
+pub struct WorldUniform {
     window_width: u32,
     window_height: u32,
+    // Samples are stratified, a perfect square gives the best results.
     sample_per_pixels: u32,
     max_depth: u32,
     frame: u32,
@@ -20,38 +38,495 @@ pub struct WorldData {
     pix_delta_x: Vec4,
     pix_delta_y: Vec4,
     pixel_up_left: Vec4,
+    // Lens basis scaled by the aperture radius, the primary rays start on this disk.
+    defocus_disk_u: Vec4,
+    defocus_disk_v: Vec4,
+    // Colors of the background gradient, picked from the ray direction when nothing is hit.
+    sky_top: Vec4,
+    sky_bottom: Vec4,
+    // Flat background of the SolidColor mode.
+    solid_color: Vec4,
+    // Diameter of the lens, 0 means a perfect pinhole camera.
+    aperture: f32,
+    // Distance from lookfrom to the plane that stays sharp.
+    focus_dist: f32,
+    triangle_count: u32,
+    // Number of bounces before paths start being randomly terminated.
+    rr_min_bounces: u32,
+    tone_map: u32,
+    box_count: u32,
+    // 0 until build_bvh is called, the shader then tests every sphere.
+    bvh_node_count: u32,
+    // 0 shoots every sample through the center of the pixel.
+    antialiasing: u32,
+    // What the rays that hit nothing see, see BackgroundMode.
+    background_mode: u32,
+    light_count: u32,
+    emissive_sphere_count: u32,
+    camera_type: u32,
+    // Height of the viewport in world units for the orthographic camera.
+    ortho_scale: f32,
+    // Fraction of the frame interval during which the shutter is open.
+    shutter: f32,
+    quad_count: u32,
+    // Radiance mapped to pure white by Reinhard, anything brighter clips.
+    white_point: f32,
+    // Mixed into every random sequence, the same seed and frame give the same image.
+    seed: u32,
+    cylinder_count: u32,
+    debug_mode: u32,
+    // Exponent of the encoding applied by the shader, 0 leaves it to the sRGB surface view.
+    gamma: f32,
+    // 0 scatters Lambertian hits toward the normal plus a random unit vector instead.
+    cosine_sampling: u32,
+    plane_count: u32,
+    // Luminance above which a sample is scaled down, 0 keeps every sample as is.
+    firefly_clamp: f32,
+    pixel_filter: u32,
+    transformed_sphere_count: u32,
+    instance_group_count: u32,
+    // Stops applied to the radiance before tone mapping, 0 leaves it unchanged.
+    exposure: f32,
+    // 1 takes the first random numbers of each sample from the blue noise tile.
+    blue_noise: u32,
+    // 1 stops tracing the pixels that have converged and doubles the samples of the noisiest.
+    adaptive_pixel_sampling: u32,
+    // Rays shot from the first hit and their length, for the AmbientOcclusion debug view.
+    ao_rays: u32,
+    ao_radius: f32,
+    // Bounces off anything but a dielectric, those only count against max_depth.
+    max_diffuse_depth: u32,
+    // Transfer function of the output, see OutputColorspace.
+    output_colorspace: u32,
+    _padding: [u32; 3],
+}
+
+// Applied to the accumulated radiance before it is written to the output texture.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMap {
+    // Values above 1.0 are clipped.
+    #[default]
+    None,
+    // Extended with the white point, see WorldData::set_white_point.
+    Reinhard,
+    Aces,
+}
+
+// Weight of a sample depending on where it lands around the pixel center.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PixelFilter {
+    // Every sample inside the pixel counts the same.
+    #[default]
+    Box,
+    // Linear falloff, samples reach the centers of the neighbouring pixels.
+    Tent,
+    // Standard deviation of half a pixel, samples reach 1.5 pixels from the center.
+    Gaussian,
+}
+
+// Encoding of the displayed and saved frames, applied after tone mapping.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputColorspace {
+    // Left to the sRGB view of the surface, and to read_pixels for screenshots.
+    #[default]
+    Srgb,
+    // BT.709 camera curve, linear near black then a 0.45 power, applied by the shader.
+    Rec709,
+    // The radiance as is, for compositing. Looks too dark on a regular display.
+    Linear,
+}
+
+// Seen by the rays that leave the scene, it also lights it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum BackgroundMode {
+    // From sky_bottom below the horizon to sky_top at the zenith, see WorldData::set_sky.
+    #[default]
+    Gradient,
+    // The same color in every direction, see WorldData::set_solid_color.
+    SolidColor,
+    // Only available once an environment map is loaded.
+    Environment,
+}
+
+impl BackgroundMode {
+    pub fn next(self) -> Self {
+        match self {
+            BackgroundMode::Gradient => BackgroundMode::SolidColor,
+            BackgroundMode::SolidColor => BackgroundMode::Environment,
+            BackgroundMode::Environment => BackgroundMode::Gradient,
+        }
+    }
+}
+
+// Replaces the path traced color with a single property of the first surface hit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DebugMode {
+    Off,
+    // Normal facing the camera, mapped from [-1, 1] to [0, 1].
+    Normals,
+    // Linear in the distance, white at the camera, half intensity at the focus distance.
+    Depth,
+    // Surface color without any lighting.
+    AlbedoOnly,
+    // Bounding boxes and spheres tested to find the first sphere, from blue for none to red.
+    BvhCost,
+    // Fraction of the rays leaving the first hit that escape within the radius, without materials.
+    // See WorldData::set_ambient_occlusion.
+    AmbientOcclusion,
+}
+
+impl DebugMode {
+    pub fn next(self) -> Self {
+        match self {
+            DebugMode::Off => DebugMode::Normals,
+            DebugMode::Normals => DebugMode::Depth,
+            DebugMode::Depth => DebugMode::AlbedoOnly,
+            DebugMode::AlbedoOnly => DebugMode::BvhCost,
+            DebugMode::BvhCost => DebugMode::AmbientOcclusion,
+            DebugMode::AmbientOcclusion => DebugMode::Off,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CameraType {
+    Perspective,
+    // Every primary ray goes along the view direction, starting from the viewport.
+    Orthographic,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Triangle {
+    // Only the first three components of each vertex are used.
+    v0: Vec4,
+    v1: Vec4,
+    v2: Vec4,
+    material: Material,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct AxisAlignedBox {
+    // Opposite corners, only the first three components are used.
+    min: Vec4,
+    max: Vec4,
+    material: Material,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Quad {
+    // Parallelogram spanning corner + a * u + b * v for a and b in [0, 1].
+    corner: Vec4,
+    u: Vec4,
+    v: Vec4,
+    material: Material,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Cylinder {
+    // Center of the bottom cap.
+    base: Vec4,
+    // Unit vector from the bottom cap to the top one.
+    axis: Vec4,
+    radius: f32,
+    height: f32,
+    _padding: [f32; 2],
+    material: Material,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Plane {
+    point: Vec4,
+    // Unit vector, the plane is visible from both sides.
+    normal: Vec4,
+    // Disk of this radius around point, 0 for an infinite plane.
+    radius: f32,
+    _padding: [f32; 3],
+    material: Material,
+}
+
+// Unit sphere scaled by radius in object space. The shader moves the rays into that space, so
+// the transform can stretch it into an ellipsoid.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TransformedSphere {
+    // Columns of the inverse of the transform.
+    world_to_object: [Vec4; 4],
+    radius: f32,
+    _padding: [f32; 3],
+    material: Material,
+}
+
+// Spheres added together by add_instances, they only store their center and radius.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceGroup {
+    // Range in the instances.
+    first: u32,
+    count: u32,
+    // Root of the hierarchy over the group in instance_bvh_nodes.
+    root: u32,
+    _padding: u32,
+    material: Material,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PointLight {
+    position: [f32; 3],
+    intensity: f32,
+    color: Vec4,
+    // Unit axis of the cone of a spotlight, zero for a point light.
+    direction: [f32; 3],
+    // Cosines of the angles from the axis where the falloff starts and where it reaches 0, -1 for
+    // a point light.
+    cos_inner: f32,
+    cos_outer: f32,
+    _padding: [f32; 3],
+}
+
+// Viewpoint written by WorldData::save_camera.
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedCamera {
+    lookfrom: Vec3,
+    lookat: Vec3,
+    vup: Vec3,
+    vfov: f32,
+}
+
+// Equirectangular HDR image surrounding the scene, in linear RGBA.
+#[derive(Debug)]
+pub struct Environment {
+    width: u32,
+    height: u32,
+    pixels: Vec<f32>,
+    // Kept for save_scene.
+    path: PathBuf,
+}
+
+impl Environment {
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[f32] {
+        &self.pixels
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorldData {
+    uniform: WorldUniform,
     // A sphere is encoded as a vec4: first three components are center, last is radius.
-    spheres: [Vec4; 128],
-    materials: [Material; 128],
+    spheres: Vec<Vec4>,
+    materials: Vec<Material>,
+    // Displacement of each sphere while the shutter is open, zero for static ones.
+    sphere_velocities: Vec<Vec4>,
+    triangles: Vec<Triangle>,
+    boxes: Vec<AxisAlignedBox>,
+    quads: Vec<Quad>,
+    cylinders: Vec<Cylinder>,
+    // Infinite planes and disks.
+    planes: Vec<Plane>,
+    transformed_spheres: Vec<TransformedSphere>,
+    // Transform of each transformed sphere, only the inverse goes to the GPU.
+    sphere_transforms: Vec<Transform>,
+    // Center and radius of each instanced sphere, in the order of their groups.
+    instances: Vec<Vec4>,
+    instance_groups: Vec<InstanceGroup>,
+    // One hierarchy per group, built along with it.
+    instance_bvh_nodes: Vec<BvhNode>,
+    lights: Vec<PointLight>,
+    // Inner and outer cone angles of each light in degrees, only their cosines go to the GPU.
+    // Point lights have 180 for both.
+    light_cones: Vec<[f32; 2]>,
+    // Indices in spheres of the ones with an emissive material.
+    emissive_spheres: Vec<u32>,
+    // Hierarchy over the spheres, empty when out of date.
+    bvh_nodes: Vec<BvhNode>,
+    // Only used on the CPU to build the camera frame.
+    vup: Vec4,
+    environment: Option<Arc<Environment>>,
+    // RGBA8 sRGB images of TEXTURE_SIZE squared pixels, in the order they were loaded.
+    textures: Vec<Arc<[u8]>>,
+    // Files of the textures and of the meshes loaded by load_obj with their triangles, so that
+    // save_scene can point to them again.
+    texture_paths: Vec<PathBuf>,
+    meshes: Vec<(PathBuf, Range<usize>)>,
+    // Samples per pixel traced since the last change, the frames may not all have the same count.
+    accumulated_samples: u64,
+    // Smooth the displayed frame with the albedo and normal as guides, see Denoiser.
+    denoise: bool,
 }
 
+// Russian roulette ends nearly every path long before, the limit only bounds the worst case.
+pub const MAX_DEPTH_LIMIT: u32 = 256;
+
+// Each ray of the AmbientOcclusion view traverses the whole scene, more would not be a preview.
+const MAX_AO_RAYS: u32 = 64;
+
+// Side of the square every texture is resized to, the GPU keeps them in layers of one array.
+pub const TEXTURE_SIZE: u32 = 512;
+
 impl WorldData {
+    // The window size starts at 0, it is set by update_size once known.
     pub fn new(
-        window_width: u32,
-        window_height: u32,
         lookfrom: Point4,
         lookat: Point4,
+        vup: Vec4,
         vfov: f32,
         sample_per_pixels: u32,
         max_depth: u32,
     ) -> Self {
-        let focal_length = norm(sub(lookfrom, lookat));
-        let theta = deg_to_rad(vfov);
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * focal_length;
-        let viewport_width = viewport_height * (window_width as f32 / window_height as f32);
+        let uniform = WorldUniform {
+            window_height: 0,
+            window_width: 0,
+            lookfrom,
+            lookat,
+            vfov,
+            sample_per_pixels,
+            max_depth: max_depth.clamp(1, MAX_DEPTH_LIMIT),
+            frame: 0,
+            frames_since_change: 0,
+            camera_frame_u: [0.0; 4],
+            camera_frame_v: [0.0; 4],
+            camera_frame_w: [0.0; 4],
+            pix_delta_x: [0.0; 4],
+            pix_delta_y: [0.0; 4],
+            pixel_up_left: [0.0; 4],
+            defocus_disk_u: [0.0; 4],
+            defocus_disk_v: [0.0; 4],
+            sky_top: [0.5, 0.7, 1.0, 1.0],
+            sky_bottom: [1.0, 1.0, 1.0, 1.0],
+            solid_color: [0.0, 0.0, 0.0, 1.0],
+            aperture: 0.0,
+            focus_dist: norm(sub(lookfrom, lookat)),
+            triangle_count: 0,
+            rr_min_bounces: 3,
+            tone_map: ToneMap::None as u32,
+            box_count: 0,
+            bvh_node_count: 0,
+            antialiasing: 1,
+            background_mode: BackgroundMode::Gradient as u32,
+            light_count: 0,
+            emissive_sphere_count: 0,
+            camera_type: CameraType::Perspective as u32,
+            ortho_scale: 1.0,
+            shutter: 1.0,
+            quad_count: 0,
+            // Large enough to leave plain Reinhard.
+            white_point: f32::MAX,
+            seed: 0,
+            cylinder_count: 0,
+            debug_mode: DebugMode::Off as u32,
+            gamma: 0.0,
+            cosine_sampling: 1,
+            plane_count: 0,
+            firefly_clamp: 0.0,
+            pixel_filter: PixelFilter::Box as u32,
+            transformed_sphere_count: 0,
+            instance_group_count: 0,
+            exposure: 0.0,
+            blue_noise: 0,
+            adaptive_pixel_sampling: 0,
+            ao_rays: 4,
+            ao_radius: 1.0,
+            max_diffuse_depth: MAX_DEPTH_LIMIT,
+            output_colorspace: OutputColorspace::Srgb as u32,
+            _padding: [0; 3],
+            sphere_count: 0,
+        };
+
+        let mut world_data = Self {
+            uniform,
+            spheres: Vec::new(),
+            materials: Vec::new(),
+            sphere_velocities: Vec::new(),
+            triangles: Vec::new(),
+            boxes: Vec::new(),
+            quads: Vec::new(),
+            cylinders: Vec::new(),
+            planes: Vec::new(),
+            transformed_spheres: Vec::new(),
+            sphere_transforms: Vec::new(),
+            instances: Vec::new(),
+            instance_groups: Vec::new(),
+            instance_bvh_nodes: Vec::new(),
+            lights: Vec::new(),
+            light_cones: Vec::new(),
+            emissive_spheres: Vec::new(),
+            bvh_nodes: Vec::new(),
+            vup,
+            environment: None,
+            textures: Vec::new(),
+            texture_paths: Vec::new(),
+            meshes: Vec::new(),
+            accumulated_samples: 0,
+            denoise: false,
+        };
+        world_data.recompute_camera();
+        world_data
+    }
+
+    // Derive the camera frame and the viewport from lookfrom, lookat, vfov and the window size.
+    pub fn recompute_camera(&mut self) {
+        let mut vup = self.vup;
+        let uniform = &mut self.uniform;
+        // The view direction would divide by 0 and fill the whole frame with NaNs. Moving lookat
+        // also keeps rotate_camera and orbit_camera working.
+        if norm(sub(uniform.lookfrom, uniform.lookat)) < 1e-6 {
+            log::warn!(
+                "lookfrom and lookat are both at {:?}, looking toward -z instead",
+                xyz(uniform.lookfrom)
+            );
+            uniform.lookat = sub(uniform.lookfrom, [0.0, 0.0, 1.0, 0.0]);
+        }
+        let (lookfrom, lookat) = (uniform.lookfrom, uniform.lookat);
 
-        let vup = [0.0, 1.0, 0.0, 0.0];
+        // The viewport sits on the focus plane so that it stays sharp with a wide aperture. At a
+        // focus distance of 0 it would shrink to a point.
+        let focal_length = if uniform.focus_dist > 0.0 {
+            uniform.focus_dist
+        } else {
+            1.0
+        };
+        let theta = deg_to_rad(uniform.vfov);
+        let h = (theta / 2.0).tan();
+        let viewport_height = if uniform.camera_type == CameraType::Orthographic as u32 {
+            uniform.ortho_scale
+        } else {
+            2.0 * h * focal_length
+        };
+        // The size is 0 until update_size is called, a 1x1 window keeps the frame finite meanwhile.
+        let width = uniform.window_width.max(1) as f32;
+        let height = uniform.window_height.max(1) as f32;
+        let viewport_width = viewport_height * (width / height);
 
         let w = normalize(sub(lookfrom, lookat));
+        // When looking along vup the cross product vanishes, any other up vector gives a valid frame.
+        if norm(cross(vup, w)) < 1e-4 * norm(vup) {
+            vup = if w[2].abs() < 0.9 {
+                [0.0, 0.0, 1.0, 0.0]
+            } else {
+                [1.0, 0.0, 0.0, 0.0]
+            };
+        }
         let u = normalize(cross(vup, w));
         let v = cross(w, u);
 
         let viewport_x = scale(viewport_width, u);
         let viewport_y = scale(-viewport_height, v);
 
-        let pix_delta_x = scale(1.0 / (window_width as f32), viewport_x);
-        let pix_delta_y = scale(1.0 / (window_height as f32), viewport_y);
+        let pix_delta_x = scale(1.0 / width, viewport_x);
+        let pix_delta_y = scale(1.0 / height, viewport_y);
 
         let viewport_up_left = sub(
             sub(
@@ -62,55 +537,1707 @@ impl WorldData {
         );
         let pixel_up_left = add(viewport_up_left, scale(0.5, add(pix_delta_x, pix_delta_y)));
 
-        Self {
-            window_height,
-            window_width,
-            lookfrom,
-            lookat,
-            vfov,
-            sample_per_pixels,
-            max_depth,
-            frame: 0,
-            frames_since_change: 0,
-            camera_frame_u: u,
-            camera_frame_v: v,
-            camera_frame_w: w,
-            pix_delta_x,
-            pix_delta_y,
-            pixel_up_left,
-            spheres: [[0.0; 4]; 128],
-            materials: [Material::lambertian([0.0, 0.0, 0.0, 1.0]); 128],
-            sphere_count: 0,
+        uniform.camera_frame_u = u;
+        uniform.camera_frame_v = v;
+        uniform.camera_frame_w = w;
+        uniform.pix_delta_x = pix_delta_x;
+        uniform.pix_delta_y = pix_delta_y;
+        uniform.pixel_up_left = pixel_up_left;
+
+        let defocus_radius = 0.5 * uniform.aperture;
+        uniform.defocus_disk_u = scale(defocus_radius, u);
+        uniform.defocus_disk_v = scale(defocus_radius, v);
+    }
+
+    pub fn window_size(&self) -> (u32, u32) {
+        (self.uniform.window_width, self.uniform.window_height)
+    }
+
+    // Origin and direction of the primary ray through (x, y) in pixels, with lens a point of the
+    // unit disk. Same as get_ray in compute.wgsl.
+    pub(crate) fn camera_ray(&self, x: f32, y: f32, lens: [f32; 2]) -> (Point4, Vec4) {
+        let uniform = &self.uniform;
+        let pix = add(
+            uniform.pixel_up_left,
+            add(scale(x, uniform.pix_delta_x), scale(y, uniform.pix_delta_y)),
+        );
+        let mut origin = uniform.lookfrom;
+        if uniform.camera_type == CameraType::Orthographic as u32 {
+            origin = add(pix, scale(uniform.focus_dist, uniform.camera_frame_w));
+        }
+        let lens_offset = add(
+            scale(lens[0], uniform.defocus_disk_u),
+            scale(lens[1], uniform.defocus_disk_v),
+        );
+        origin = add(origin, lens_offset);
+        (origin, sub(pix, origin))
+    }
+
+    // Background seen along dir, the environment map is left out.
+    pub(crate) fn sky_color(&self, dir: Vec4) -> Vec4 {
+        if self.background_mode() == BackgroundMode::SolidColor {
+            return self.uniform.solid_color;
         }
+        let a = 0.5 * (normalize(dir)[1] + 1.0);
+        add(
+            scale(1.0 - a, self.uniform.sky_bottom),
+            scale(a, self.uniform.sky_top),
+        )
+    }
+
+    pub fn set_defocus(&mut self, aperture: f32, focus_dist: f32) {
+        self.uniform.aperture = aperture;
+        self.uniform.focus_dist = focus_dist;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Keep the aperture, only move the plane that stays sharp.
+    pub fn set_focus_dist(&mut self, focus_dist: f32) {
+        self.set_defocus(self.uniform.aperture, focus_dist);
+    }
+
+    pub fn camera_type(&self) -> CameraType {
+        if self.uniform.camera_type == CameraType::Orthographic as u32 {
+            CameraType::Orthographic
+        } else {
+            CameraType::Perspective
+        }
+    }
+
+    // Parallel projection, scale is the height of the view in world units.
+    pub fn set_orthographic(&mut self, scale: f32) {
+        self.uniform.camera_type = CameraType::Orthographic as u32;
+        self.uniform.ortho_scale = scale;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    pub fn set_perspective(&mut self) {
+        self.uniform.camera_type = CameraType::Perspective as u32;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Height of the perspective view at the focus distance, the orthographic view of the same size
+    // keeps the focus plane framed the same way.
+    pub fn perspective_height(&self) -> f32 {
+        2.0 * (deg_to_rad(self.uniform.vfov) / 2.0).tan() * self.uniform.focus_dist
+    }
+
+    // Clamped to a range where the projection stays usable.
+    pub fn set_vfov(&mut self, vfov: f32) {
+        self.uniform.vfov = vfov.clamp(10.0, 120.0);
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    pub fn vup(&self) -> Vec4 {
+        self.vup
+    }
+
+    // Rolls the camera around the view direction. A vup along that direction falls back to another
+    // axis like in new, a zero, NaN or infinite one is ignored.
+    pub fn set_vup(&mut self, vup: Vec4) {
+        let vup = direction(xyz(vup));
+        let length = norm(vup);
+        if !length.is_finite() || length <= 0.0 {
+            return;
+        }
+        self.vup = vup;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    pub fn vfov(&self) -> f32 {
+        self.uniform.vfov
+    }
+
+    pub fn set_rr_min_bounces(&mut self, n: u32) {
+        self.uniform.rr_min_bounces = n;
+        self.mark_changed();
+    }
+
+    pub fn set_tone_map(&mut self, mode: ToneMap) {
+        // Tone mapping happens after accumulation, the samples are still valid.
+        self.uniform.tone_map = mode as u32;
+    }
+
+    pub fn set_pixel_filter(&mut self, filter: PixelFilter) {
+        self.uniform.pixel_filter = filter as u32;
+        self.mark_changed();
+    }
+
+    pub fn debug_mode(&self) -> DebugMode {
+        match self.uniform.debug_mode {
+            1 => DebugMode::Normals,
+            2 => DebugMode::Depth,
+            3 => DebugMode::AlbedoOnly,
+            4 => DebugMode::BvhCost,
+            5 => DebugMode::AmbientOcclusion,
+            _ => DebugMode::Off,
+        }
+    }
+
+    pub fn set_debug_mode(&mut self, mode: DebugMode) {
+        self.uniform.debug_mode = mode as u32;
+        self.mark_changed();
+    }
+
+    pub fn ambient_occlusion(&self) -> (u32, f32) {
+        (self.uniform.ao_rays, self.uniform.ao_radius)
+    }
+
+    // Rays per sample for the AmbientOcclusion view, clamped to [1, 64], and the distance beyond
+    // which a hit no longer occludes. Small rooms need a short radius to not look all dark.
+    // A radius that is not positive and finite is ignored.
+    pub fn set_ambient_occlusion(&mut self, rays: u32, radius: f32) {
+        self.uniform.ao_rays = rays.clamp(1, MAX_AO_RAYS);
+        if radius.is_finite() && radius > 0.0 {
+            self.uniform.ao_radius = radius;
+        }
+        self.mark_changed();
+    }
+
+    pub fn gamma(&self) -> Option<f32> {
+        (self.uniform.gamma > 0.0).then_some(self.uniform.gamma)
+    }
+
+    // Encode the output with a plain power curve instead of sRGB, None goes back to sRGB.
+    // Takes precedence over the output colorspace.
+    pub fn set_gamma(&mut self, gamma: Option<f32>) {
+        self.uniform.gamma = gamma.map_or(0.0, |gamma| gamma.max(0.1));
+    }
+
+    pub fn output_colorspace(&self) -> OutputColorspace {
+        match self.uniform.output_colorspace {
+            1 => OutputColorspace::Rec709,
+            2 => OutputColorspace::Linear,
+            _ => OutputColorspace::Srgb,
+        }
+    }
+
+    // Like the tone mapping, the encoding happens after accumulation, the samples stay valid.
+    pub fn set_output_colorspace(&mut self, colorspace: OutputColorspace) {
+        self.uniform.output_colorspace = colorspace as u32;
+    }
+
+    // False once the shader writes the final encoding itself, the surface then takes the colors
+    // as they are instead of encoding them to sRGB.
+    pub(crate) fn srgb_output(&self) -> bool {
+        self.gamma().is_none() && self.output_colorspace() == OutputColorspace::Srgb
+    }
+
+    pub fn firefly_clamp(&self) -> Option<f32> {
+        (self.uniform.firefly_clamp > 0.0).then_some(self.uniform.firefly_clamp)
+    }
+
+    // Trades a little bias for the lone very bright pixels of rare light paths. 0 or infinity
+    // keeps the render unbiased.
+    pub fn set_firefly_clamp(&mut self, luminance: f32) {
+        self.uniform.firefly_clamp = if luminance.is_finite() {
+            luminance.max(0.0)
+        } else {
+            0.0
+        };
+        self.mark_changed();
+    }
+
+    pub fn blue_noise(&self) -> bool {
+        self.uniform.blue_noise != 0
+    }
+
+    // Spreads the errors of neighbouring pixels evenly instead of in clumps, the image looks cleaner
+    // at low sample counts. Only the first bounces of each path use it.
+    pub fn set_blue_noise(&mut self, enabled: bool) {
+        self.uniform.blue_noise = enabled as u32;
+        self.mark_changed();
+    }
+
+    pub fn adaptive_pixel_sampling(&self) -> bool {
+        self.uniform.adaptive_pixel_sampling != 0
+    }
+
+    // Spends the samples where the image is still noisy. Every pixel keeps the variance of its
+    // frames in the accumulation, so this can change without restarting it.
+    pub fn set_adaptive_pixel_sampling(&mut self, enabled: bool) {
+        self.uniform.adaptive_pixel_sampling = enabled as u32;
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.uniform.exposure
+    }
+
+    // Multiplies the strength of every emissive material and point light. Unlike the exposure, this
+    // changes how the lights compare to the sky. Negative, NaN and infinite factors are ignored.
+    pub fn scale_light_intensity(&mut self, factor: f32) {
+        if !factor.is_finite() || factor < 0.0 {
+            return;
+        }
+        let materials = self
+            .materials
+            .iter_mut()
+            .chain(self.triangles.iter_mut().map(|t| &mut t.material))
+            .chain(self.boxes.iter_mut().map(|b| &mut b.material))
+            .chain(self.quads.iter_mut().map(|q| &mut q.material))
+            .chain(self.cylinders.iter_mut().map(|c| &mut c.material))
+            .chain(self.planes.iter_mut().map(|p| &mut p.material))
+            .chain(self.transformed_spheres.iter_mut().map(|s| &mut s.material))
+            .chain(self.instance_groups.iter_mut().map(|g| &mut g.material));
+        for material in materials {
+            material.scale_emission(factor);
+        }
+        for light in &mut self.lights {
+            light.intensity *= factor;
+        }
+        self.mark_changed();
+    }
+
+    // Scales the radiance by 2^stops, like the exposure of a camera. Applied after accumulation, the
+    // samples stay valid.
+    pub fn set_exposure(&mut self, stops: f32) {
+        if stops.is_finite() {
+            self.uniform.exposure = stops;
+        }
+    }
+
+    pub fn set_white_point(&mut self, white_point: f32) {
+        self.uniform.white_point = white_point.max(1e-3);
+    }
+
+    pub fn set_sky(&mut self, top: Vec4, bottom: Vec4) {
+        self.uniform.sky_top = top;
+        self.uniform.sky_bottom = bottom;
+        self.mark_changed();
+    }
+
+    // Background of the SolidColor mode, black by default.
+    pub fn set_solid_color(&mut self, color: Vec4) {
+        self.uniform.solid_color = color;
+        self.mark_changed();
+    }
+
+    pub fn solid_color(&self) -> Vec4 {
+        self.uniform.solid_color
+    }
+
+    // Light the scene with the environment map from now on.
+    pub fn set_environment(&mut self, environment: Environment) {
+        self.environment = Some(Arc::new(environment));
+        self.set_background_mode(BackgroundMode::Environment);
+    }
+
+    pub fn environment(&self) -> Option<&Environment> {
+        self.environment.as_deref()
+    }
+
+    // Returns the index to give Material::textured. Like the environment, the textures are only
+    // uploaded when the GPU state is created, so load them along with the scene.
+    pub fn load_texture(&mut self, path: &Path) -> Result<u32, SceneError> {
+        let image = image::open(path)?.resize_exact(
+            TEXTURE_SIZE,
+            TEXTURE_SIZE,
+            image::imageops::FilterType::Triangle,
+        );
+        self.textures.push(image.into_rgba8().into_raw().into());
+        self.texture_paths.push(path.to_path_buf());
+        Ok(self.textures.len() as u32 - 1)
+    }
+
+    pub fn textures(&self) -> &[Arc<[u8]>] {
+        &self.textures
+    }
+
+    pub fn background_mode(&self) -> BackgroundMode {
+        match self.uniform.background_mode {
+            1 => BackgroundMode::SolidColor,
+            2 => BackgroundMode::Environment,
+            _ => BackgroundMode::Gradient,
+        }
+    }
+
+    // Without an environment map, the Environment mode falls back to the gradient.
+    pub fn set_background_mode(&mut self, mode: BackgroundMode) {
+        let mode = match mode {
+            BackgroundMode::Environment if self.environment.is_none() => BackgroundMode::Gradient,
+            mode => mode,
+        };
+        self.uniform.background_mode = mode as u32;
+        self.mark_changed();
+    }
+
+    // Moving spheres are blurred along the part of their path covered while the shutter is open.
+    pub fn set_shutter(&mut self, shutter: f32) {
+        self.uniform.shutter = shutter.clamp(0.0, 1.0);
+        self.mark_changed();
+    }
+
+    pub fn set_seed(&mut self, seed: u32) {
+        self.uniform.seed = seed;
+        self.mark_changed();
+    }
+
+    pub fn sample_per_pixels(&self) -> u32 {
+        self.uniform.sample_per_pixels
+    }
+
+    pub fn set_samples(&mut self, n: u32) {
+        self.uniform.sample_per_pixels = n.max(1);
+        self.mark_changed();
+    }
+
+    pub fn max_depth(&self) -> u32 {
+        self.uniform.max_depth
+    }
+
+    // Bounces per path, clamped to [1, MAX_DEPTH_LIMIT].
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.uniform.max_depth = max_depth.clamp(1, MAX_DEPTH_LIMIT);
+        self.mark_changed();
+    }
+
+    pub fn max_diffuse_depth(&self) -> u32 {
+        self.uniform.max_diffuse_depth
+    }
+
+    // Limit on the bounces off the other materials within max_depth, clamped to [1, MAX_DEPTH_LIMIT].
+    // Light going through nested glass needs a deep path, a lower diffuse limit keeps the cost of
+    // the other paths down. Defaults to MAX_DEPTH_LIMIT, max_depth alone then ends the paths.
+    pub fn set_max_diffuse_depth(&mut self, max_diffuse_depth: u32) {
+        self.uniform.max_diffuse_depth = max_diffuse_depth.clamp(1, MAX_DEPTH_LIMIT);
+        self.mark_changed();
+    }
+
+    // Each frame averages its own samples, so the count can change without restarting the accumulation.
+    pub fn adjust_samples(&mut self, n: u32) {
+        self.uniform.sample_per_pixels = n.max(1);
+    }
+
+    pub fn cosine_sampling(&self) -> bool {
+        self.uniform.cosine_sampling != 0
+    }
+
+    // Both ways follow the same distribution, the unit vector one is kept for comparison.
+    pub fn set_cosine_sampling(&mut self, enabled: bool) {
+        self.uniform.cosine_sampling = enabled as u32;
+        self.mark_changed();
+    }
+
+    pub fn denoise(&self) -> bool {
+        self.denoise
     }
-    
+
+    // Only filters what is displayed, the accumulation goes on from the noisy samples.
+    pub fn set_denoise(&mut self, enabled: bool) {
+        self.denoise = enabled;
+    }
+
+    pub fn antialiasing(&self) -> bool {
+        self.uniform.antialiasing != 0
+    }
+
+    pub fn set_antialiasing(&mut self, enabled: bool) {
+        self.uniform.antialiasing = enabled as u32;
+        self.mark_changed();
+    }
+
+    pub fn uniform(&self) -> &WorldUniform {
+        &self.uniform
+    }
+
+    pub fn spheres(&self) -> &[Vec4] {
+        &self.spheres
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    pub fn sphere_count(&self) -> usize {
+        self.spheres.len()
+    }
+
+    // Center and radius packed like in spheres, and the material. build_bvh and remove_sphere
+    // change the order.
+    pub fn sphere(&self, index: usize) -> Option<(Vec4, Material)> {
+        Some((*self.spheres.get(index)?, self.materials[index]))
+    }
+
+    pub fn iter_spheres(&self) -> impl Iterator<Item = (Vec4, Material)> + '_ {
+        self.spheres
+            .iter()
+            .copied()
+            .zip(self.materials.iter().copied())
+    }
+
+    pub fn sphere_velocities(&self) -> &[Vec4] {
+        &self.sphere_velocities
+    }
+
+    pub fn triangles(&self) -> &[Triangle] {
+        &self.triangles
+    }
+
+    pub fn boxes(&self) -> &[AxisAlignedBox] {
+        &self.boxes
+    }
+
+    pub fn quads(&self) -> &[Quad] {
+        &self.quads
+    }
+
+    pub fn cylinders(&self) -> &[Cylinder] {
+        &self.cylinders
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        &self.planes
+    }
+
+    pub fn transformed_spheres(&self) -> &[TransformedSphere] {
+        &self.transformed_spheres
+    }
+
+    pub fn instances(&self) -> &[Vec4] {
+        &self.instances
+    }
+
+    pub fn instance_groups(&self) -> &[InstanceGroup] {
+        &self.instance_groups
+    }
+
+    pub fn instance_bvh_nodes(&self) -> &[BvhNode] {
+        &self.instance_bvh_nodes
+    }
+
+    pub fn lights(&self) -> &[PointLight] {
+        &self.lights
+    }
+
+    pub fn emissive_spheres(&self) -> &[u32] {
+        &self.emissive_spheres
+    }
+
+    pub fn bvh_nodes(&self) -> &[BvhNode] {
+        &self.bvh_nodes
+    }
+
     pub fn next_frame(&mut self) {
-        self.frame += 1;
-        self.frames_since_change += 1;
+        self.uniform.frame += 1;
+        self.uniform.frames_since_change += 1;
+        self.accumulated_samples += self.uniform.sample_per_pixels as u64;
+    }
+
+    pub fn accumulated_samples(&self) -> u64 {
+        self.accumulated_samples
+    }
+
+    pub fn frames_since_change(&self) -> u32 {
+        self.uniform.frames_since_change
     }
-    
+
+    // Anything that changes the rendered image must call this so the accumulated samples restart.
+    pub fn mark_changed(&mut self) {
+        self.uniform.frames_since_change = 0;
+        self.accumulated_samples = 0;
+    }
+
     pub fn update_size(&mut self, window_width: u32, window_height: u32) {
-        let mut new_world = Self::new(
-            window_width,
-            window_height,
-            self.lookfrom,
-            self.lookat,
-            self.vfov,
-            self.sample_per_pixels,
-            self.max_depth,
+        self.uniform.window_width = window_width;
+        self.uniform.window_height = window_height;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Translate both lookfrom and lookat along the camera frame.
+    pub fn move_camera(&mut self, right: f32, up: f32, forward: f32) {
+        let uniform = &self.uniform;
+        let offset = add(
+            add(
+                scale(right, uniform.camera_frame_u),
+                scale(up, uniform.camera_frame_v),
+            ),
+            // The camera looks toward -w.
+            scale(-forward, uniform.camera_frame_w),
+        );
+        self.uniform.lookfrom = add(self.uniform.lookfrom, offset);
+        self.uniform.lookat = add(self.uniform.lookat, offset);
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Turn the view direction around lookfrom, angles are in radians.
+    pub fn rotate_camera(&mut self, yaw: f32, pitch: f32) {
+        let dir = sub(self.uniform.lookat, self.uniform.lookfrom);
+        let distance = norm(dir);
+        let dir = normalize(dir);
+
+        // Stay clear of the poles, where the frame built from vup degenerates.
+        let max_pitch = deg_to_rad(89.0);
+        let theta = (dir[1].asin() + pitch).clamp(-max_pitch, max_pitch);
+        let phi = dir[2].atan2(dir[0]) + yaw;
+
+        let new_dir = [
+            theta.cos() * phi.cos(),
+            theta.sin(),
+            theta.cos() * phi.sin(),
+            0.0,
+        ];
+        self.uniform.lookat = add(self.uniform.lookfrom, scale(distance, new_dir));
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Move lookfrom around lookat about the vertical axis, angle in radians. The height above lookat
+    // is kept in proportion when radius changes the distance between the two, None keeps it.
+    pub fn orbit_camera(&mut self, angle: f32, radius: Option<f32>) {
+        let offset = sub(self.uniform.lookfrom, self.uniform.lookat);
+        let distance = norm(offset);
+        let radius = radius.unwrap_or(distance);
+        let (sin, cos) = angle.sin_cos();
+        let rotated = [
+            offset[0] * cos - offset[2] * sin,
+            offset[1],
+            offset[0] * sin + offset[2] * cos,
+            0.0,
+        ];
+        self.uniform.lookfrom = add(self.uniform.lookat, scale(radius / distance, rotated));
+        // A camera focused on lookat stays focused on it.
+        self.uniform.focus_dist *= radius / distance;
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Write the viewpoint to a JSON file, load_camera brings it back.
+    pub fn save_camera(&self, path: &Path) -> anyhow::Result<()> {
+        let camera = SavedCamera {
+            lookfrom: xyz(self.uniform.lookfrom),
+            lookat: xyz(self.uniform.lookat),
+            vup: xyz(self.vup),
+            vfov: self.uniform.vfov,
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&camera)?)?;
+        Ok(())
+    }
+
+    pub fn load_camera(&mut self, path: &Path) -> anyhow::Result<()> {
+        let camera: SavedCamera = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        self.uniform.lookfrom = point(camera.lookfrom);
+        self.uniform.lookat = point(camera.lookat);
+        self.vup = direction(camera.vup);
+        // Also recomputes the camera and restarts the accumulation.
+        self.set_vfov(camera.vfov);
+        Ok(())
+    }
+
+    // Write the scene in the RON format load_scene reads, loading it back gives the same world.
+    // Meshes, textures and the environment map point to the files they were loaded from, with
+    // absolute paths so that the scene can be saved anywhere. Only the triangles added one by one
+    // are left out, with a warning.
+    pub fn save_scene(&self, path: &Path) -> anyhow::Result<()> {
+        let uniform = &self.uniform;
+        let material = MaterialDescription::from_material;
+        let absolute =
+            |file: &Path| std::fs::canonicalize(file).or_else(|_| std::path::absolute(file));
+        let mesh_triangles: usize = self.meshes.iter().map(|(_, range)| range.len()).sum();
+        if mesh_triangles < self.triangles.len() {
+            let count = self.triangles.len() - mesh_triangles;
+            log::warn!(
+                "Saving {} without the {count} triangles not loaded from a mesh",
+                path.display()
+            );
+        }
+        let meshes = self
+            .meshes
+            .iter()
+            .map(|(file, range)| {
+                Ok(MeshDescription {
+                    path: absolute(file)?,
+                    material: material(&self.triangles[range.start].material),
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        let textures = self
+            .texture_paths
+            .iter()
+            .map(|file| absolute(file))
+            .collect::<std::io::Result<_>>()?;
+        let environment = self
+            .environment
+            .as_ref()
+            .map(|environment| absolute(&environment.path))
+            .transpose()?;
+
+        let camera = CameraDescription {
+            lookfrom: xyz(uniform.lookfrom),
+            lookat: xyz(uniform.lookat),
+            vup: Some(xyz(self.vup)),
+            vfov: uniform.vfov,
+            sample_per_pixels: uniform.sample_per_pixels,
+            max_depth: uniform.max_depth,
+            aperture: uniform.aperture,
+            focus_dist: Some(uniform.focus_dist),
+            shutter: Some(uniform.shutter),
+            orthographic: (self.camera_type() == CameraType::Orthographic)
+                .then_some(uniform.ortho_scale),
+            rr_min_bounces: Some(uniform.rr_min_bounces),
+            max_diffuse_depth: (uniform.max_diffuse_depth < MAX_DEPTH_LIMIT)
+                .then_some(uniform.max_diffuse_depth),
+            seed: uniform.seed,
+            antialiasing: Some(self.antialiasing()),
+            cosine_sampling: Some(self.cosine_sampling()),
+            firefly_clamp: self.firefly_clamp(),
+            blue_noise: self.blue_noise(),
+            adaptive_pixel_sampling: self.adaptive_pixel_sampling(),
+        };
+        let spheres = self
+            .spheres
+            .iter()
+            .zip(&self.materials)
+            .zip(&self.sphere_velocities)
+            .map(|((sphere, sphere_material), velocity)| SphereDescription {
+                center: xyz(*sphere),
+                end_center: (*velocity != [0.0; 4]).then(|| xyz(add(*sphere, *velocity))),
+                radius: sphere[3],
+                material: material(sphere_material),
+            })
+            .collect();
+        let boxes = self
+            .boxes
+            .iter()
+            .map(|aabb| BoxDescription {
+                min: xyz(aabb.min),
+                max: xyz(aabb.max),
+                material: material(&aabb.material),
+            })
+            .collect();
+        // Area lights are emissive quads once added.
+        let quads = self
+            .quads
+            .iter()
+            .map(|quad| QuadDescription {
+                corner: xyz(quad.corner),
+                u: xyz(quad.u),
+                v: xyz(quad.v),
+                material: material(&quad.material),
+            })
+            .collect();
+        let cylinders = self
+            .cylinders
+            .iter()
+            .map(|cylinder| CylinderDescription {
+                base: xyz(cylinder.base),
+                axis: xyz(cylinder.axis),
+                radius: cylinder.radius,
+                height: cylinder.height,
+                material: material(&cylinder.material),
+            })
+            .collect();
+        let (planes, disks): (Vec<&Plane>, Vec<&Plane>) =
+            self.planes.iter().partition(|plane| plane.radius == 0.0);
+        let planes = planes
+            .into_iter()
+            .map(|plane| PlaneDescription {
+                point: xyz(plane.point),
+                normal: xyz(plane.normal),
+                material: material(&plane.material),
+            })
+            .collect();
+        let disks = disks
+            .into_iter()
+            .map(|disk| DiskDescription {
+                center: xyz(disk.point),
+                normal: xyz(disk.normal),
+                radius: disk.radius,
+                material: material(&disk.material),
+            })
+            .collect();
+        let transformed_spheres = self
+            .transformed_spheres
+            .iter()
+            .zip(&self.sphere_transforms)
+            .map(|(sphere, transform)| TransformedSphereDescription {
+                translation: transform.translation,
+                rotation: Some(transform.rotation),
+                scale: Some(transform.scale),
+                radius: sphere.radius,
+                material: material(&sphere.material),
+            })
+            .collect();
+        // A group may mix radii, the description has a single one.
+        let mut instances = Vec::new();
+        for group in &self.instance_groups {
+            let first = group.first as usize;
+            let spheres = &self.instances[first..first + group.count as usize];
+            for run in spheres.chunk_by(|a, b| a[3] == b[3]) {
+                instances.push(InstancesDescription {
+                    centers: run.iter().map(|sphere| xyz(*sphere)).collect(),
+                    radius: run[0][3],
+                    material: material(&group.material),
+                });
+            }
+        }
+        let (spotlights, lights): (Vec<_>, Vec<_>) = self
+            .lights
+            .iter()
+            .zip(&self.light_cones)
+            .partition(|(light, _)| light.direction != [0.0; 3]);
+        let lights = lights
+            .into_iter()
+            .map(|(light, _)| LightDescription {
+                position: light.position,
+                color: xyz(light.color),
+                intensity: light.intensity,
+            })
+            .collect();
+        let spotlights = spotlights
+            .into_iter()
+            .map(|(light, &[inner, outer])| SpotlightDescription {
+                position: light.position,
+                direction: light.direction,
+                inner,
+                outer,
+                color: xyz(light.color),
+                intensity: light.intensity,
+            })
+            .collect();
+
+        let scene = SceneDescription {
+            camera,
+            sky: Some(SkyDescription {
+                top: xyz(uniform.sky_top),
+                bottom: xyz(uniform.sky_bottom),
+            }),
+            background_color: (self.background_mode() == BackgroundMode::SolidColor)
+                .then(|| xyz(uniform.solid_color)),
+            environment,
+            tone_map: match uniform.tone_map {
+                1 => ToneMap::Reinhard,
+                2 => ToneMap::Aces,
+                _ => ToneMap::None,
+            },
+            pixel_filter: match uniform.pixel_filter {
+                1 => PixelFilter::Tent,
+                2 => PixelFilter::Gaussian,
+                _ => PixelFilter::Box,
+            },
+            exposure: uniform.exposure,
+            gamma: self.gamma(),
+            output_colorspace: self.output_colorspace(),
+            textures,
+            // f32::MAX is the default, it means no clipping.
+            white_point: (uniform.white_point != f32::MAX).then_some(uniform.white_point),
+            ambient_occlusion: Some(AmbientOcclusionDescription {
+                rays: uniform.ao_rays,
+                radius: uniform.ao_radius,
+            }),
+            spheres,
+            boxes,
+            quads,
+            area_lights: Vec::new(),
+            cylinders,
+            planes,
+            disks,
+            transformed_spheres,
+            instances,
+            meshes,
+            lights,
+            spotlights,
+        };
+        let config = ron::ser::PrettyConfig::default();
+        std::fs::write(path, ron::ser::to_string_pretty(&scene, config)?)?;
+        Ok(())
+    }
+
+    // Remember a sphere is encoded as a Vec4.
+    // There is no fixed capacity: the spheres live in a storage buffer that grows with the scene.
+    // Adding a sphere drops the BVH, call build_bvh again once the scene is complete.
+    // Like every add_* function, fails on a non-positive size or a NaN or infinite value.
+    pub fn add_sphere(&mut self, sphere: Vec4, material: Material) -> Result<(), SceneError> {
+        self.add_moving_sphere_packed(sphere, [0.0; 4], material)
+    }
+
+    // The sphere goes from center0 to center1 while the shutter is open.
+    pub fn add_moving_sphere(
+        &mut self,
+        center0: Point4,
+        center1: Point4,
+        radius: f32,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let sphere = [center0[0], center0[1], center0[2], radius];
+        self.add_moving_sphere_packed(sphere, direction(xyz(sub(center1, center0))), material)
+    }
+
+    fn add_moving_sphere_packed(
+        &mut self,
+        sphere: Vec4,
+        velocity: Vec4,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = self.spheres.len();
+        let radius = sphere[3];
+        if !radius.is_finite() || radius <= 0.0 {
+            return Err(SceneError::InvalidRadius {
+                sphere: index,
+                radius,
+            });
+        }
+        if !all_finite(sphere.iter().chain(&velocity)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "sphere",
+                index,
+            });
+        }
+        if material.is_emissive() {
+            self.emissive_spheres.push(self.spheres.len() as u32);
+            self.uniform.emissive_sphere_count += 1;
+        }
+        self.spheres.push(sphere);
+        self.materials.push(material);
+        self.sphere_velocities.push(velocity);
+        self.uniform.sphere_count += 1;
+        self.bvh_nodes.clear();
+        self.uniform.bvh_node_count = 0;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Swaps the last sphere into the hole, so only the index of that one changes.
+    // Like add_sphere, this drops the BVH. Fails when there is no sphere at index.
+    pub fn remove_sphere(&mut self, index: usize) -> Result<(), SceneError> {
+        let count = self.spheres.len();
+        if index >= count {
+            return Err(SceneError::NoSuchSphere { index, count });
+        }
+        self.spheres.swap_remove(index);
+        self.materials.swap_remove(index);
+        self.sphere_velocities.swap_remove(index);
+        self.uniform.sphere_count -= 1;
+        self.find_emissive_spheres();
+        self.bvh_nodes.clear();
+        self.uniform.bvh_node_count = 0;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Remove every primitive and light, the camera and the sky are kept.
+    pub fn clear_scene(&mut self) {
+        self.spheres.clear();
+        self.materials.clear();
+        self.sphere_velocities.clear();
+        self.triangles.clear();
+        self.meshes.clear();
+        self.boxes.clear();
+        self.quads.clear();
+        self.cylinders.clear();
+        self.planes.clear();
+        self.transformed_spheres.clear();
+        self.sphere_transforms.clear();
+        self.instances.clear();
+        self.instance_groups.clear();
+        self.instance_bvh_nodes.clear();
+        self.lights.clear();
+        self.light_cones.clear();
+        self.emissive_spheres.clear();
+        self.bvh_nodes.clear();
+        self.uniform.sphere_count = 0;
+        self.uniform.triangle_count = 0;
+        self.uniform.box_count = 0;
+        self.uniform.quad_count = 0;
+        self.uniform.cylinder_count = 0;
+        self.uniform.plane_count = 0;
+        self.uniform.transformed_sphere_count = 0;
+        self.uniform.instance_group_count = 0;
+        self.uniform.light_count = 0;
+        self.uniform.emissive_sphere_count = 0;
+        self.uniform.bvh_node_count = 0;
+        self.mark_changed();
+    }
+
+    fn find_emissive_spheres(&mut self) {
+        self.emissive_spheres = (0..self.materials.len() as u32)
+            .filter(|&i| self.materials[i as usize].is_emissive())
+            .collect();
+        self.uniform.emissive_sphere_count = self.emissive_spheres.len() as u32;
+    }
+
+    // Closest sphere hit by the ray going through the center of the view, if any.
+    pub fn sphere_at_view_center(&self) -> Option<usize> {
+        let origin = self.uniform.lookfrom;
+        let dir = normalize(sub(self.uniform.lookat, origin));
+        let mut closest = None;
+        let mut closest_t = f32::INFINITY;
+        for (i, sphere) in self.spheres.iter().enumerate() {
+            let v = sub(*sphere, origin);
+            let h = dot(dir, v);
+            let discriminant = h * h - dot(v, v) + sphere[3] * sphere[3];
+            if discriminant < 0.0 {
+                continue;
+            }
+            // Nearest intersection in front of the camera.
+            let sqrt_disc = discriminant.sqrt();
+            let t = if h - sqrt_disc > 0.0 {
+                h - sqrt_disc
+            } else {
+                h + sqrt_disc
+            };
+            if t > 0.0 && t < closest_t {
+                closest_t = t;
+                closest = Some(i);
+            }
+        }
+        closest
+    }
+
+    // Axis aligned box around every bounded primitive, None when there are none.
+    // Infinite planes are left out, cylinders and disks are bounded by spheres around them and
+    // transformed spheres by their transformed bounding cube.
+    fn scene_bounds(&self) -> Option<(Point4, Point4)> {
+        let mut bounds: Option<(Point4, Point4)> = None;
+        let mut extend = |center: Vec4, radius: f32| {
+            let (mut min, mut max) = bounds.unwrap_or((
+                [f32::MAX, f32::MAX, f32::MAX, 1.0],
+                [f32::MIN, f32::MIN, f32::MIN, 1.0],
+            ));
+            for axis in 0..3 {
+                min[axis] = min[axis].min(center[axis] - radius);
+                max[axis] = max[axis].max(center[axis] + radius);
+            }
+            bounds = Some((min, max));
+        };
+
+        for (sphere, velocity) in self.spheres.iter().zip(&self.sphere_velocities) {
+            extend(*sphere, sphere[3]);
+            extend(add(*sphere, *velocity), sphere[3]);
+        }
+        for triangle in &self.triangles {
+            for vertex in [triangle.v0, triangle.v1, triangle.v2] {
+                extend(vertex, 0.0);
+            }
+        }
+        for aabb in &self.boxes {
+            extend(aabb.min, 0.0);
+            extend(aabb.max, 0.0);
+        }
+        for quad in &self.quads {
+            extend(quad.corner, 0.0);
+            extend(add(quad.corner, quad.u), 0.0);
+            extend(add(quad.corner, quad.v), 0.0);
+            extend(add(add(quad.corner, quad.u), quad.v), 0.0);
+        }
+        for cylinder in &self.cylinders {
+            extend(cylinder.base, cylinder.radius);
+            let top = add(cylinder.base, scale(cylinder.height, cylinder.axis));
+            extend(top, cylinder.radius);
+        }
+        for disk in self.planes.iter().filter(|plane| plane.radius > 0.0) {
+            extend(disk.point, disk.radius);
+        }
+        for instance in &self.instances {
+            extend(*instance, instance[3]);
+        }
+        for (sphere, transform) in self.transformed_spheres.iter().zip(&self.sphere_transforms) {
+            for corner in 0..8 {
+                let [x, y, z] = [1, 2, 4].map(|bit| if corner & bit == 0 { -1.0 } else { 1.0 });
+                let local = point([x, y, z].map(|c| c * sphere.radius));
+                extend(transform.apply_point(local), 0.0);
+            }
+        }
+        bounds
+    }
+
+    // Look at the center of the scene from far enough along the current view direction that its
+    // bounding sphere fits in the view. Does nothing when the scene has no bounded primitive.
+    pub fn frame_scene(&mut self) {
+        let Some((min, max)) = self.scene_bounds() else {
+            return;
+        };
+        let center = scale(0.5, add(min, max));
+        let radius = (0.5 * norm(sub(max, min))).max(1e-3);
+
+        let uniform = &mut self.uniform;
+        let aspect = uniform.window_width.max(1) as f32 / uniform.window_height.max(1) as f32;
+        let half_vfov = deg_to_rad(uniform.vfov) / 2.0;
+        // The view is narrower horizontally in a portrait window.
+        let half_fov = half_vfov.min((half_vfov.tan() * aspect).atan());
+        let distance = radius / half_fov.sin();
+
+        let back = normalize(sub(uniform.lookfrom, uniform.lookat));
+        uniform.lookat = center;
+        uniform.lookfrom = add(center, scale(distance, back));
+        uniform.focus_dist = distance;
+        uniform.ortho_scale = 2.0 * radius / aspect.min(1.0);
+        self.recompute_camera();
+        self.mark_changed();
+    }
+
+    // Build the hierarchy over the spheres. This reorders the spheres and their materials.
+    pub fn build_bvh(&mut self) {
+        let (nodes, order) = bvh::build(&self.spheres, &self.sphere_velocities);
+        self.spheres = order.iter().map(|&i| self.spheres[i]).collect();
+        self.materials = order.iter().map(|&i| self.materials[i]).collect();
+        self.sphere_velocities = order.iter().map(|&i| self.sphere_velocities[i]).collect();
+        self.find_emissive_spheres();
+        self.uniform.bvh_node_count = nodes.len() as u32;
+        self.bvh_nodes = nodes;
+        self.mark_changed();
+    }
+
+    pub fn add_triangle(
+        &mut self,
+        v0: Vec4,
+        v1: Vec4,
+        v2: Vec4,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        if !all_finite(v0.iter().chain(&v1).chain(&v2)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "triangle",
+                index: self.triangles.len(),
+            });
+        }
+        self.triangles.push(Triangle {
+            v0,
+            v1,
+            v2,
+            material,
+        });
+        self.uniform.triangle_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Same checks as add_triangle, for meshes packed ahead of time by load_obj.
+    fn add_triangles(&mut self, triangles: Vec<Triangle>) -> Result<(), SceneError> {
+        let invalid = triangles.iter().position(|t| {
+            !all_finite(t.v0.iter().chain(&t.v1).chain(&t.v2)) || !t.material.is_finite()
+        });
+        if let Some(i) = invalid {
+            return Err(SceneError::NonFinite {
+                primitive: "triangle",
+                index: self.triangles.len() + i,
+            });
+        }
+        self.uniform.triangle_count += triangles.len() as u32;
+        self.triangles.extend(triangles);
+        self.mark_changed();
+        Ok(())
+    }
+
+    // min must be strictly below max on every axis.
+    pub fn add_box(&mut self, min: Vec4, max: Vec4, material: Material) -> Result<(), SceneError> {
+        let index = self.boxes.len();
+        if !all_finite(min.iter().chain(&max)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "box",
+                index,
+            });
+        }
+        if (0..3).any(|axis| min[axis] >= max[axis]) {
+            return Err(SceneError::InvalidBox { index });
+        }
+        self.boxes.push(AxisAlignedBox { min, max, material });
+        self.uniform.box_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Both sides are visible, like triangles. An emissive quad makes a rectangular area light.
+    // The two edges must not be parallel.
+    pub fn add_quad(
+        &mut self,
+        corner: Point4,
+        u: Vec4,
+        v: Vec4,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = self.quads.len();
+        if !all_finite(corner.iter().chain(&u).chain(&v)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "quad",
+                index,
+            });
+        }
+        let area = norm(cross(u, v));
+        if !area.is_finite() || area <= 0.0 {
+            return Err(SceneError::InvalidQuad { index });
+        }
+        self.quads.push(Quad {
+            corner,
+            u,
+            v,
+            material,
+        });
+        self.uniform.quad_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Emissive quad, sampled directly from the Lambertian surfaces for soft shadows with little noise.
+    pub fn add_area_light(
+        &mut self,
+        corner: Point4,
+        u: Vec4,
+        v: Vec4,
+        color: Vec4,
+        intensity: f32,
+    ) -> Result<(), SceneError> {
+        self.add_quad(corner, u, v, Material::emissive(color, intensity))
+    }
+
+    // Closed cylinder standing on base, extending height along axis.
+    pub fn add_cylinder(
+        &mut self,
+        base: Point4,
+        axis: Vec4,
+        radius: f32,
+        height: f32,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = self.cylinders.len();
+        if !all_finite(base.iter().chain(&axis)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "cylinder",
+                index,
+            });
+        }
+        let valid = [norm(axis), radius, height]
+            .iter()
+            .all(|value| value.is_finite() && *value > 0.0);
+        if !valid {
+            return Err(SceneError::InvalidCylinder { index });
+        }
+        self.cylinders.push(Cylinder {
+            base,
+            axis: normalize(axis),
+            radius,
+            height,
+            _padding: [0.0; 2],
+            material,
+        });
+        self.uniform.cylinder_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Flat ground without the curvature of a huge sphere.
+    pub fn add_plane(
+        &mut self,
+        point: Point4,
+        normal: Vec4,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        // Planes and disks share the buffer, the disks are the ones with a radius.
+        let index = self
+            .planes
+            .iter()
+            .filter(|plane| plane.radius == 0.0)
+            .count();
+        if !all_finite(point.iter().chain(&normal)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "plane",
+                index,
+            });
+        }
+        let length = norm(normal);
+        if !length.is_finite() || length <= 0.0 {
+            return Err(SceneError::InvalidPlane { index });
+        }
+        self.push_plane(point, normal, 0.0, material);
+        Ok(())
+    }
+
+    pub fn add_disk(
+        &mut self,
+        center: Point4,
+        normal: Vec4,
+        radius: f32,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = self
+            .planes
+            .iter()
+            .filter(|plane| plane.radius > 0.0)
+            .count();
+        if !all_finite(center.iter().chain(&normal)) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "disk",
+                index,
+            });
+        }
+        let valid = [norm(normal), radius]
+            .iter()
+            .all(|value| value.is_finite() && *value > 0.0);
+        if !valid {
+            return Err(SceneError::InvalidDisk { index });
+        }
+        self.push_plane(center, normal, radius, material);
+        Ok(())
+    }
+
+    fn push_plane(&mut self, point: Point4, normal: Vec4, radius: f32, material: Material) {
+        self.planes.push(Plane {
+            point,
+            normal: normalize(normal),
+            radius,
+            _padding: [0.0; 3],
+            material,
+        });
+        self.uniform.plane_count += 1;
+        self.mark_changed();
+    }
+
+    // The scale factors must not be 0, tiny ones are raised so that the transform stays invertible.
+    pub fn add_transformed_sphere(
+        &mut self,
+        transform: Transform,
+        radius: f32,
+        material: Material,
+    ) -> Result<(), SceneError> {
+        let index = self.transformed_spheres.len();
+        let Transform {
+            translation,
+            rotation,
+            scale,
+        } = transform;
+        let values = translation.iter().chain(&rotation).chain(&scale);
+        if !all_finite(values) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "transformed sphere",
+                index,
+            });
+        }
+        if !radius.is_finite() || radius <= 0.0 || scale.contains(&0.0) {
+            return Err(SceneError::InvalidTransformedSphere { index });
+        }
+        let transform = Transform {
+            scale: scale.map(|s| if s.abs() < 1e-6 { 1e-6 } else { s }),
+            ..transform
+        };
+        self.transformed_spheres.push(TransformedSphere {
+            world_to_object: transform.inverse_columns(),
+            radius,
+            _padding: [0.0; 3],
+            material,
+        });
+        self.sphere_transforms.push(transform);
+        self.uniform.transformed_sphere_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Many spheres sharing one material, each encoded like in spheres: center, then radius in w.
+    // They take 16 bytes each instead of a full sphere with its own material. Each group gets its
+    // own hierarchy, which reorders its spheres. Emissive ones do not light the scene through light
+    // sampling.
+    pub fn add_instances(
+        &mut self,
+        spheres: &[Vec4],
+        material: Material,
+    ) -> Result<(), SceneError> {
+        if spheres.is_empty() {
+            return Ok(());
+        }
+        let index = self.instance_groups.len();
+        if !all_finite(spheres.iter().flatten()) || !material.is_finite() {
+            return Err(SceneError::NonFinite {
+                primitive: "instances",
+                index,
+            });
+        }
+        if spheres.iter().any(|sphere| sphere[3] <= 0.0) {
+            return Err(SceneError::InvalidInstances { index });
+        }
+        let first = self.instances.len() as u32;
+        let root = self.instance_bvh_nodes.len() as u32;
+        let (mut nodes, order) = bvh::build(spheres, &vec![[0.0; 4]; spheres.len()]);
+        bvh::offset(&mut nodes, root, first);
+        self.instance_groups.push(InstanceGroup {
+            first,
+            count: spheres.len() as u32,
+            root,
+            _padding: 0,
+            material,
+        });
+        self.instances.extend(order.iter().map(|&i| spheres[i]));
+        self.instance_bvh_nodes.extend(nodes);
+        self.uniform.instance_group_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Lights Lambertian and Pbr surfaces directly through shadow rays, the power falls off with the
+    // squared distance.
+    pub fn add_point_light(
+        &mut self,
+        position: Point4,
+        color: Vec4,
+        intensity: f32,
+    ) -> Result<(), SceneError> {
+        if !all_finite(position.iter().chain(&color).chain([&intensity])) {
+            return Err(SceneError::NonFinite {
+                primitive: "light",
+                index: self.lights.len(),
+            });
+        }
+        self.lights.push(PointLight {
+            position: xyz(position),
+            intensity,
+            color,
+            direction: [0.0; 3],
+            cos_inner: -1.0,
+            cos_outer: -1.0,
+            _padding: [0.0; 3],
+        });
+        self.light_cones.push([180.0; 2]);
+        self.uniform.light_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+
+    // Point light restricted to a cone around direction. The angles are in degrees from the axis:
+    // full intensity within inner, smoothly falling to nothing at outer. outer is clamped to
+    // [0, 180] and inner to [0, outer].
+    pub fn add_spotlight(
+        &mut self,
+        position: Point4,
+        direction: Vec4,
+        inner: f32,
+        outer: f32,
+        color: Vec4,
+        intensity: f32,
+    ) -> Result<(), SceneError> {
+        // Point lights and spotlights share the buffer, the spotlights are the ones with a direction.
+        let index = self
+            .lights
+            .iter()
+            .filter(|light| light.direction != [0.0; 3])
+            .count();
+        let values = position.iter().chain(&direction).chain(&color);
+        if !all_finite(values.chain([&inner, &outer, &intensity])) {
+            return Err(SceneError::NonFinite {
+                primitive: "spotlight",
+                index,
+            });
+        }
+        let length = norm(direction);
+        if !length.is_finite() || length <= 0.0 {
+            return Err(SceneError::InvalidSpotlight { index });
+        }
+        let outer = outer.clamp(0.0, 180.0);
+        let inner = inner.clamp(0.0, outer);
+        self.lights.push(PointLight {
+            position: xyz(position),
+            intensity,
+            color,
+            direction: xyz(normalize(direction)),
+            cos_inner: deg_to_rad(inner).cos(),
+            cos_outer: deg_to_rad(outer).cos(),
+            _padding: [0.0; 3],
+        });
+        self.light_cones.push([inner, outer]);
+        self.uniform.light_count += 1;
+        self.mark_changed();
+        Ok(())
+    }
+}
+
+fn all_finite<'a>(values: impl IntoIterator<Item = &'a f32>) -> bool {
+    values.into_iter().all(|value| value.is_finite())
+}
+
+// The window size is not known yet, it is set by the first resize event.
+pub fn load_scene(path: &Path) -> Result<WorldData, SceneError> {
+    let source = std::fs::read_to_string(path)?;
+    let scene: SceneDescription = ron::from_str(&source)?;
+
+    // Paths are relative to the scene file.
+    let scene_dir = path.parent().unwrap_or(Path::new("."));
+
+    let camera = &scene.camera;
+    let mut builder = SceneBuilder::new()
+        .camera(camera.lookfrom, camera.lookat, camera.vfov)
+        .samples(camera.sample_per_pixels)
+        .max_depth(camera.max_depth)
+        .aperture(camera.aperture);
+    if let Some(vup) = camera.vup {
+        builder = builder.vup(vup);
+    }
+    if let Some(focus_dist) = camera.focus_dist {
+        builder = builder.focus_dist(focus_dist);
+    }
+    if let Some(scale) = camera.orthographic {
+        builder = builder.orthographic(scale);
+    }
+
+    if let Some(shutter) = camera.shutter {
+        builder = builder.shutter(shutter);
+    }
+    for path in &scene.textures {
+        builder = builder.texture(scene_dir.join(path));
+    }
+
+    for sphere in &scene.spheres {
+        let end_center = sphere.end_center.unwrap_or(sphere.center);
+        builder = builder.moving_sphere(
+            sphere.center,
+            end_center,
+            sphere.radius,
+            sphere.material.to_material(),
+        );
+    }
+    for aabb in &scene.boxes {
+        builder = builder.aabb(aabb.min, aabb.max, aabb.material.to_material());
+    }
+    for quad in &scene.quads {
+        builder = builder.quad(quad.corner, quad.u, quad.v, quad.material.to_material());
+    }
+    for light in &scene.area_lights {
+        builder = builder.area_light(light.corner, light.u, light.v, light.color, light.intensity);
+    }
+    for cylinder in &scene.cylinders {
+        builder = builder.cylinder(
+            cylinder.base,
+            cylinder.axis,
+            cylinder.radius,
+            cylinder.height,
+            cylinder.material.to_material(),
+        );
+    }
+    for plane in &scene.planes {
+        builder = builder.plane(plane.point, plane.normal, plane.material.to_material());
+    }
+    for disk in &scene.disks {
+        builder = builder.disk(
+            disk.center,
+            disk.normal,
+            disk.radius,
+            disk.material.to_material(),
+        );
+    }
+    for sphere in &scene.transformed_spheres {
+        let transform = Transform {
+            translation: sphere.translation,
+            rotation: sphere.rotation.unwrap_or([0.0; 3]),
+            scale: sphere.scale.unwrap_or([1.0; 3]),
+        };
+        builder =
+            builder.transformed_sphere(transform, sphere.radius, sphere.material.to_material());
+    }
+    for instances in &scene.instances {
+        let material = instances.material.to_material();
+        builder = builder.instances(&instances.centers, instances.radius, material);
+    }
+    for light in &scene.lights {
+        builder = builder.point_light(light.position, light.color, light.intensity);
+    }
+    for light in &scene.spotlights {
+        builder = builder.spotlight(
+            light.position,
+            light.direction,
+            light.inner,
+            light.outer,
+            light.color,
+            light.intensity,
         );
-        new_world.sphere_count = self.sphere_count;
-        new_world.spheres = self.spheres;
-        new_world.materials = self.materials;
-        new_world.frame = self.frame;
-        *self = new_world;
     }
+    for mesh in &scene.meshes {
+        builder = builder.mesh(scene_dir.join(&mesh.path), mesh.material.to_material());
+    }
+
+    let mut world_data = builder.build()?;
+    if let Some(n) = camera.rr_min_bounces {
+        world_data.set_rr_min_bounces(n);
+    }
+    if let Some(n) = camera.max_diffuse_depth {
+        world_data.set_max_diffuse_depth(n);
+    }
+    world_data.set_seed(camera.seed);
+    if let Some(enabled) = camera.antialiasing {
+        world_data.set_antialiasing(enabled);
+    }
+    if let Some(enabled) = camera.cosine_sampling {
+        world_data.set_cosine_sampling(enabled);
+    }
+    if let Some(luminance) = camera.firefly_clamp {
+        world_data.set_firefly_clamp(luminance);
+    }
+    world_data.set_blue_noise(camera.blue_noise);
+    world_data.set_adaptive_pixel_sampling(camera.adaptive_pixel_sampling);
 
-    // Remember a sphere is encoded as a Vec4
-    pub fn add_sphere(&mut self, sphere: Vec4, material: Material) {
-        assert!(self.sphere_count < 127);
-        self.spheres[self.sphere_count as usize] = sphere;
-        self.materials[self.sphere_count as usize] = material;
-        self.sphere_count += 1;
+    world_data.set_tone_map(scene.tone_map);
+    world_data.set_pixel_filter(scene.pixel_filter);
+    world_data.set_exposure(scene.exposure);
+    world_data.set_gamma(scene.gamma);
+    world_data.set_output_colorspace(scene.output_colorspace);
+    if let Some(white_point) = scene.white_point {
+        world_data.set_white_point(white_point);
     }
+    if let Some(ao) = &scene.ambient_occlusion {
+        world_data.set_ambient_occlusion(ao.rays, ao.radius);
+    }
+
+    if let Some(sky) = &scene.sky {
+        let [r, g, b] = sky.top;
+        let top = [r, g, b, 1.0];
+        let [r, g, b] = sky.bottom;
+        let bottom = [r, g, b, 1.0];
+        world_data.set_sky(top, bottom);
+    }
+    if let Some([r, g, b]) = scene.background_color {
+        world_data.set_solid_color([r, g, b, 1.0]);
+        world_data.set_background_mode(BackgroundMode::SolidColor);
+    }
+
+    if let Some(environment) = &scene.environment {
+        world_data.set_environment(load_environment(&scene_dir.join(environment))?);
+    }
+
+    Ok(world_data)
+}
+
+// SplitMix64, enough to scatter spheres reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [min, max).
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        let unit = (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32;
+        min + (max - min) * unit
+    }
+
+    fn color(&mut self, min: f32, max: f32) -> Point4 {
+        [
+            self.range(min, max),
+            self.range(min, max),
+            self.range(min, max),
+            1.0,
+        ]
+    }
+}
+
+// The cover of Ray Tracing in One Weekend: a field of small random spheres around three big ones.
+pub fn random_scene(seed: u64) -> WorldData {
+    let mut rng = Rng(seed);
+    let mut builder = SceneBuilder::new()
+        .camera([13.0, 2.0, 3.0], [0.0, 0.0, 0.0], 20.0)
+        .aperture(0.1)
+        .focus_dist(10.0)
+        .sphere(
+            [0.0, -1000.0, 0.0],
+            1000.0,
+            Material::lambertian([0.5, 0.5, 0.5, 1.0]),
+        );
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = [
+                a as f32 + 0.9 * rng.range(0.0, 1.0),
+                0.2,
+                b as f32 + 0.9 * rng.range(0.0, 1.0),
+            ];
+            // Leave room around the big metal sphere.
+            let offset = [center[0] - 4.0, 0.0, center[2], 0.0];
+            if norm(offset) <= 0.9 {
+                continue;
+            }
+
+            let choice = rng.range(0.0, 1.0);
+            let material = if choice < 0.8 {
+                let [r, g, b, _] = rng.color(0.0, 1.0);
+                let [r2, g2, b2, _] = rng.color(0.0, 1.0);
+                Material::lambertian([r * r2, g * g2, b * b2, 1.0])
+            } else if choice < 0.95 {
+                Material::metallic(rng.color(0.5, 1.0), rng.range(0.0, 0.5))
+            } else {
+                Material::dielectric(1.5)
+            };
+            builder = builder.sphere(center, 0.2, material);
+        }
+    }
+
+    builder
+        .sphere([0.0, 1.0, 0.0], 1.0, Material::dielectric(1.5))
+        .sphere(
+            [-4.0, 1.0, 0.0],
+            1.0,
+            Material::lambertian([0.4, 0.2, 0.1, 1.0]),
+        )
+        .sphere(
+            [4.0, 1.0, 0.0],
+            1.0,
+            Material::metallic([0.7, 0.6, 0.5, 1.0], 0.0),
+        )
+        .build()
+        .expect("the generated spheres all have a positive radius")
+}
+
+// Any format the image crate decodes works, in practice a Radiance .hdr file.
+pub fn load_environment(path: &Path) -> Result<Environment, SceneError> {
+    let image = image::open(path)?.into_rgba32f();
+    Ok(Environment {
+        width: image.width(),
+        height: image.height(),
+        pixels: image.into_raw(),
+        path: path.to_path_buf(),
+    })
+}
+
+// Add every triangle of an OBJ file with the given material, returns how many were added.
+pub fn load_obj(
+    world_data: &mut WorldData,
+    path: &Path,
+    material: Material,
+) -> Result<usize, SceneError> {
+    let start = Instant::now();
+    let (models, _) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+    log::info!("Parsed {} in {:.2?}", path.display(), start.elapsed());
+
+    let first = world_data.triangles.len();
+    let mut count = 0;
+    for (i, model) in models.iter().enumerate() {
+        let triangles = pack_triangles(&model.mesh, material);
+        count += triangles.len();
+        world_data.add_triangles(triangles)?;
+        log::info!(
+            "Packed model {}/{} of {}, {count} triangles after {:.2?}",
+            i + 1,
+            models.len(),
+            path.display(),
+            start.elapsed()
+        );
+    }
+    if count > 0 {
+        let triangles = first..first + count;
+        world_data.meshes.push((path.to_path_buf(), triangles));
+    }
+
+    Ok(count)
+}
+
+// The faces are independent, so a mesh of millions of them is split between every core.
+fn pack_triangles(mesh: &tobj::Mesh, material: Material) -> Vec<Triangle> {
+    let vertex = |i: u32| {
+        let i = 3 * i as usize;
+        let p = &mesh.positions;
+        point([p[i], p[i + 1], p[i + 2]])
+    };
+    mesh.indices
+        .par_chunks_exact(3)
+        .map(|face| Triangle {
+            v0: vertex(face[0]),
+            v1: vertex(face[1]),
+            v2: vertex(face[2]),
+            material,
+        })
+        .collect()
 }
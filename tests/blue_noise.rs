@@ -0,0 +1,110 @@
+// At one sample per pixel, blue noise pushes the error to high frequencies: once blurred, a blue
+// noise frame must be closer to the converged image than a white noise one. Needs a GPU, run it
+// with `cargo test -- --ignored`.
+
+use gpu_raytracing::material::Material;
+use gpu_raytracing::scene_builder::SceneBuilder;
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::{BackgroundMode, WorldData};
+
+const SIZE: u32 = 64;
+// Side of the squares the error is averaged over, which keeps its low frequencies.
+const BLOCK: u32 = 4;
+const TRIALS: u32 = 4;
+const REFERENCE_SAMPLES: u32 = 64;
+const REFERENCE_FRAMES: u32 = 16;
+
+// A diffuse floor next to a black wall, which hides part of the sky from every point of the floor.
+fn scene() -> WorldData {
+    let mut world_data = SceneBuilder::new()
+        .camera([0.0, 1.0, 0.0], [0.0, 0.0, -1.0], 60.0)
+        .samples(1)
+        .max_depth(2)
+        .sphere(
+            [0.0, -100.0, -1.0],
+            100.0,
+            Material::lambertian([0.8, 0.8, 0.8, 1.0]),
+        )
+        .sphere(
+            [100.5, 0.0, -1.0],
+            100.0,
+            Material::lambertian([0.0, 0.0, 0.0, 1.0]),
+        )
+        .build()
+        .unwrap();
+    world_data.set_solid_color([1.0, 1.0, 1.0, 1.0]);
+    world_data.set_background_mode(BackgroundMode::SolidColor);
+    world_data.update_size(SIZE, SIZE);
+    world_data
+}
+
+fn state(world_data: &WorldData) -> WgpuState {
+    let new_state = WgpuState::new_headless(SIZE, SIZE, world_data, &AdapterChoice::Default);
+    pollster::block_on(new_state).unwrap_or_else(|err| panic!("no usable GPU for the test: {err}"))
+}
+
+// Linear luminance of every pixel, averaged over the frames since the last change.
+fn luminance(state: &WgpuState) -> Vec<f32> {
+    let color = state.read_gbuffer().unwrap().color;
+    color
+        .chunks_exact(16)
+        .map(|pixel| {
+            let channel = |i: usize| f32::from_le_bytes(pixel[i..i + 4].try_into().unwrap());
+            0.2126 * channel(0) + 0.7152 * channel(4) + 0.0722 * channel(8)
+        })
+        .collect()
+}
+
+// Energy of the error once averaged over BLOCK x BLOCK squares, summed over single frames.
+fn low_frequency_error(
+    world_data: &mut WorldData,
+    state: &mut WgpuState,
+    reference: &[f32],
+) -> f64 {
+    let mut energy = 0.0;
+    for _ in 0..TRIALS {
+        world_data.mark_changed();
+        world_data.next_frame();
+        state.rewrite_world_data(world_data);
+        state.render();
+        let frame = luminance(state);
+        for by in (0..SIZE).step_by(BLOCK as usize) {
+            for bx in (0..SIZE).step_by(BLOCK as usize) {
+                let mut error = 0.0;
+                for y in by..by + BLOCK {
+                    for x in bx..bx + BLOCK {
+                        let i = (y * SIZE + x) as usize;
+                        error += (frame[i] - reference[i]) as f64;
+                    }
+                }
+                error /= (BLOCK * BLOCK) as f64;
+                energy += error * error;
+            }
+        }
+    }
+    energy
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn blue_noise_beats_white_noise_at_one_sample() {
+    let mut world_data = scene();
+    world_data.set_samples(REFERENCE_SAMPLES);
+    let mut state = state(&world_data);
+    for _ in 0..REFERENCE_FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    let reference = luminance(&state);
+
+    world_data.set_samples(1);
+    world_data.set_blue_noise(false);
+    let white = low_frequency_error(&mut world_data, &mut state, &reference);
+    world_data.set_blue_noise(true);
+    let blue = low_frequency_error(&mut world_data, &mut state, &reference);
+    assert!(
+        blue < 0.7 * white,
+        "blue noise leaves {blue:.4} of low frequency error, white noise {white:.4}"
+    );
+}
@@ -0,0 +1,135 @@
+// Renders the same spheres on the GPU and with the CPU reference renderer, the two images must
+// agree up to the noise. Catches shader changes that bias the result, which a golden image
+// regenerated after the change would not. The comparison needs a GPU, run it with
+// `cargo test -- --ignored`. The checks of the CPU renderer alone always run.
+
+use gpu_raytracing::cpu_render;
+use gpu_raytracing::material::Material;
+use gpu_raytracing::scene_builder::SceneBuilder;
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::BackgroundMode;
+
+const WIDTH: u32 = 40;
+const HEIGHT: u32 = 30;
+const SAMPLES_PER_FRAME: u32 = 16;
+const FRAMES: u32 = 16;
+// Mean absolute difference per channel, out of 255. Both images still carry some noise.
+const MAX_MEAN_ERROR: f64 = 2.0;
+
+#[test]
+#[ignore = "needs a GPU"]
+fn gpu_matches_cpu_reference() {
+    let mut world_data = SceneBuilder::new()
+        .camera([0.0, 0.3, 1.0], [0.0, 0.0, -1.0], 70.0)
+        .samples(SAMPLES_PER_FRAME)
+        .max_depth(8)
+        .sphere(
+            [0.0, -100.5, -1.0],
+            100.0,
+            Material::lambertian([0.5, 0.6, 0.5, 1.0]),
+        )
+        .sphere(
+            [0.0, 0.0, -1.2],
+            0.5,
+            Material::lambertian([0.8, 0.3, 0.2, 1.0]),
+        )
+        .sphere([-1.0, 0.0, -1.0], 0.5, Material::dielectric(1.5))
+        .sphere(
+            [1.0, 0.0, -1.0],
+            0.5,
+            Material::metallic([0.8, 0.7, 0.5, 1.0], 0.1),
+        )
+        .sphere(
+            [0.0, 1.0, -1.5],
+            0.3,
+            Material::emissive([1.0, 0.9, 0.7, 1.0], 3.0),
+        )
+        .build()
+        .unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU for the comparison: {err}"));
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    let gpu = state.read_pixels().unwrap();
+    let cpu = cpu_render::render(&world_data, SAMPLES_PER_FRAME * FRAMES, 1);
+
+    let error = gpu
+        .iter()
+        .zip(&cpu)
+        .map(|(&a, &b)| a.abs_diff(b) as f64)
+        .sum::<f64>()
+        / gpu.len() as f64;
+    assert!(
+        error <= MAX_MEAN_ERROR,
+        "the GPU is off by {error:.2} on average"
+    );
+}
+
+// A Lambertian sphere under a uniform background: every bounce leaves the convex sphere and sees
+// the background, so the sphere is exactly albedo times background, without any noise.
+#[test]
+fn cpu_diffuse_sphere_under_uniform_light() {
+    let mut world_data = SceneBuilder::new()
+        .camera([0.0, 0.0, 1.0], [0.0, 0.0, -1.0], 60.0)
+        .samples(1)
+        .max_depth(4)
+        .sphere(
+            [0.0, 0.0, -1.0],
+            0.5,
+            Material::lambertian([0.5, 0.5, 0.5, 1.0]),
+        )
+        .build()
+        .unwrap();
+    world_data.set_solid_color([0.8, 0.8, 0.8, 1.0]);
+    world_data.set_background_mode(BackgroundMode::SolidColor);
+    world_data.set_antialiasing(false);
+    world_data.update_size(WIDTH, HEIGHT);
+
+    let pixels = cpu_render::render(&world_data, 4, 1);
+    let pixel = |x: u32, y: u32| {
+        let i = 4 * (y * WIDTH + x) as usize;
+        &pixels[i..i + 4]
+    };
+    // sRGB encodings of 0.4 and 0.8.
+    let (sphere, background) = (170, 231);
+    for channel in &pixel(WIDTH / 2, HEIGHT / 2)[..3] {
+        assert!(channel.abs_diff(sphere) <= 1, "the sphere is {channel}");
+    }
+    for channel in &pixel(0, 0)[..3] {
+        assert!(
+            channel.abs_diff(background) <= 1,
+            "the background is {channel}"
+        );
+    }
+}
+
+#[test]
+fn cpu_render_is_deterministic() {
+    let mut world_data = SceneBuilder::new()
+        .camera([0.0, 0.3, 1.0], [0.0, 0.0, -1.0], 70.0)
+        .max_depth(8)
+        .sphere(
+            [0.0, -100.5, -1.0],
+            100.0,
+            Material::lambertian([0.5, 0.6, 0.5, 1.0]),
+        )
+        .sphere([-0.5, 0.0, -1.0], 0.5, Material::dielectric(1.5))
+        .sphere(
+            [0.5, 0.0, -1.0],
+            0.5,
+            Material::metallic([0.8, 0.7, 0.5, 1.0], 0.3),
+        )
+        .build()
+        .unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+
+    let first = cpu_render::render(&world_data, 2, 7);
+    assert_eq!(first, cpu_render::render(&world_data, 2, 7));
+    assert_ne!(first, cpu_render::render(&world_data, 2, 8));
+}
@@ -0,0 +1,93 @@
+// Renders the bundled scenes headless and compares them with the images in tests/golden.
+// They need a GPU, so they are ignored by default, run them with `cargo test -- --ignored`.
+// Run with UPDATE_GOLDEN=1 to overwrite the golden images after an intended change.
+
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::load_scene;
+
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const FRAMES: u32 = 4;
+// Mean absolute difference per channel, out of 255. Leaves room for float differences between GPUs.
+const MAX_MEAN_ERROR: f64 = 2.0;
+
+fn render(scene: &Path) -> Vec<u8> {
+    let mut world_data = load_scene(scene).unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU to render {}: {err}", scene.display()));
+
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    state.read_pixels().unwrap()
+}
+
+fn check_golden(name: &str) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let scene = root.join("scenes").join(format!("{name}.ron"));
+    let golden = root.join("tests/golden").join(format!("{name}.png"));
+
+    let pixels = render(&scene);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        image::save_buffer(
+            &golden,
+            &pixels,
+            WIDTH,
+            HEIGHT,
+            image::ExtendedColorType::Rgba8,
+        )
+        .unwrap();
+        return;
+    }
+
+    let expected = image::open(&golden).unwrap().into_rgba8();
+    assert_eq!(expected.dimensions(), (WIDTH, HEIGHT));
+    let error = pixels
+        .iter()
+        .zip(expected.as_raw())
+        .map(|(&a, &b)| a.abs_diff(b) as f64)
+        .sum::<f64>()
+        / pixels.len() as f64;
+    assert!(
+        error <= MAX_MEAN_ERROR,
+        "{name} is off by {error:.2} on average from {}",
+        golden.display()
+    );
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn default_scene() {
+    check_golden("default");
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn boxes() {
+    check_golden("boxes");
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn lights() {
+    check_golden("lights");
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn cornell() {
+    check_golden("cornell");
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn cylinders() {
+    check_golden("cylinders");
+}
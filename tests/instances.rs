@@ -0,0 +1,78 @@
+// Instanced spheres go through their own hierarchy, they must render like the same spheres added
+// one by one. Needs a GPU, run it with `cargo test -- --ignored`.
+
+use gpu_raytracing::material::Material;
+use gpu_raytracing::scene_builder::SceneBuilder;
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const SAMPLES_PER_FRAME: u32 = 16;
+const FRAMES: u32 = 4;
+// Mean absolute difference per channel, out of 255. Both scenes trace the same paths, this only
+// leaves room for rounding.
+const MAX_MEAN_ERROR: f64 = 0.5;
+
+fn render(instanced: bool) -> Vec<u8> {
+    // A grid large enough for a hierarchy several levels deep, in two groups.
+    let centers: Vec<[f32; 3]> = (0..100)
+        .map(|i| {
+            let (x, z) = ((i % 10) as f32, (i / 10) as f32);
+            [0.3 * x - 1.35, -0.3, -0.3 * z - 1.0]
+        })
+        .collect();
+    let (left, right) = centers.split_at(50);
+    let red = Material::lambertian([0.8, 0.3, 0.2, 1.0]);
+    let blue = Material::metallic([0.3, 0.4, 0.8, 1.0], 0.2);
+
+    let mut builder = SceneBuilder::new()
+        .camera([0.0, 0.8, 1.0], [0.0, -0.3, -2.0], 70.0)
+        .samples(SAMPLES_PER_FRAME)
+        .max_depth(4)
+        .sphere(
+            [0.0, -100.5, -1.0],
+            100.0,
+            Material::lambertian([0.5, 0.6, 0.5, 1.0]),
+        );
+    if instanced {
+        builder = builder
+            .instances(left, 0.12, red)
+            .instances(right, 0.12, blue);
+    } else {
+        for &center in left {
+            builder = builder.sphere(center, 0.12, red);
+        }
+        for &center in right {
+            builder = builder.sphere(center, 0.12, blue);
+        }
+    }
+    let mut world_data = builder.build().unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU for the comparison: {err}"));
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    state.read_pixels().unwrap()
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn instances_match_spheres() {
+    let instanced = render(true);
+    let spheres = render(false);
+    let error = instanced
+        .iter()
+        .zip(&spheres)
+        .map(|(&a, &b)| a.abs_diff(b) as f64)
+        .sum::<f64>()
+        / instanced.len() as f64;
+    assert!(
+        error <= MAX_MEAN_ERROR,
+        "the instances are off by {error:.2} on average"
+    );
+}
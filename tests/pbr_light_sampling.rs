@@ -0,0 +1,72 @@
+// Pbr surfaces sample the emissive spheres directly. Instanced spheres are never sampled, so the
+// same light added as an instance is only found by the scattered rays: both must converge to the
+// same image, or the light sampling is biased. Needs a GPU, run it with `cargo test -- --ignored`.
+
+use gpu_raytracing::material::Material;
+use gpu_raytracing::scene_builder::SceneBuilder;
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::BackgroundMode;
+
+const WIDTH: u32 = 48;
+const HEIGHT: u32 = 36;
+const SAMPLES_PER_FRAME: u32 = 64;
+const FRAMES: u32 = 8;
+// Difference of the mean luminance over the image, relative to that mean.
+const MAX_RELATIVE_ERROR: f64 = 0.03;
+
+fn mean_luminance(sampled: bool) -> f64 {
+    let light = Material::emissive([1.0, 0.9, 0.8, 1.0], 4.0);
+    let mut builder = SceneBuilder::new()
+        .camera([0.0, 1.0, 1.5], [0.0, 0.0, -0.5], 60.0)
+        .samples(SAMPLES_PER_FRAME)
+        .max_depth(4)
+        .sphere(
+            [0.0, -100.0, -1.0],
+            100.0,
+            Material::pbr([0.7, 0.5, 0.3, 1.0], 0.2, 0.4),
+        )
+        .sphere(
+            [-0.5, 0.3, -0.8],
+            0.3,
+            Material::pbr([0.3, 0.5, 0.8, 1.0], 0.0, 0.2),
+        );
+    builder = if sampled {
+        builder.sphere([0.4, 0.8, -0.5], 0.25, light)
+    } else {
+        builder.instances(&[[0.4, 0.8, -0.5]], 0.25, light)
+    };
+    let mut world_data = builder.build().unwrap();
+    world_data.set_solid_color([0.0, 0.0, 0.0, 1.0]);
+    world_data.set_background_mode(BackgroundMode::SolidColor);
+    world_data.update_size(WIDTH, HEIGHT);
+
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU for the comparison: {err}"));
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    let color = state.read_gbuffer().unwrap().color;
+    let sum: f64 = color
+        .chunks_exact(16)
+        .map(|pixel| {
+            let channel = |i: usize| f32::from_le_bytes(pixel[i..i + 4].try_into().unwrap());
+            (0.2126 * channel(0) + 0.7152 * channel(4) + 0.0722 * channel(8)) as f64
+        })
+        .sum();
+    sum / (WIDTH * HEIGHT) as f64
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn pbr_light_sampling_is_unbiased() {
+    let sampled = mean_luminance(true);
+    let unsampled = mean_luminance(false);
+    let error = (sampled - unsampled).abs() / unsampled;
+    assert!(
+        error <= MAX_RELATIVE_ERROR,
+        "sampling the light gives {sampled:.4}, finding it by chance {unsampled:.4}"
+    );
+}
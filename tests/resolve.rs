@@ -0,0 +1,56 @@
+// Changing the exposure while paused must show up without tracing a new frame, and must not lose
+// the accumulated samples. Needs a GPU, run it with `cargo test -- --ignored`.
+
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::load_scene;
+
+use std::path::PathBuf;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+const FRAMES: u32 = 3;
+
+fn brightness(pixels: &[u8]) -> u64 {
+    pixels
+        .chunks_exact(4)
+        .map(|p| p[..3].iter().map(|&c| c as u64).sum::<u64>())
+        .sum()
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn resolve_applies_the_exposure() {
+    let scene = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes/default.ron");
+    let mut world_data = load_scene(&scene).unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU for the comparison: {err}"));
+
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    let traced = state.read_pixels().unwrap();
+
+    let exposure = world_data.exposure();
+    world_data.set_exposure(exposure + 1.0);
+    state.rewrite_world_data(&world_data);
+    state.resolve();
+    let brighter = state.read_pixels().unwrap();
+    assert!(
+        brightness(&brighter) > brightness(&traced),
+        "the exposure was not applied"
+    );
+
+    // Same accumulation, so the same image once the exposure is back.
+    world_data.set_exposure(exposure);
+    state.rewrite_world_data(&world_data);
+    state.resolve();
+    assert!(
+        state.read_pixels().unwrap() == traced,
+        "the resolve changed the accumulation"
+    );
+    assert_eq!(world_data.frames_since_change(), FRAMES);
+}
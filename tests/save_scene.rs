@@ -0,0 +1,232 @@
+// Saves the bundled scenes and loads them back, the world must come out the same.
+
+use gpu_raytracing::material::Material;
+use gpu_raytracing::scene::SceneDescription;
+use gpu_raytracing::scene_builder::SceneBuilder;
+use gpu_raytracing::world_data::{
+    load_scene, BackgroundMode, OutputColorspace, PixelFilter, ToneMap, WorldData,
+};
+
+use std::path::PathBuf;
+
+// The BVH reorders the spheres, so they are compared as a sorted list of sphere and material bytes.
+fn sorted_spheres(world_data: &WorldData) -> Vec<Vec<u8>> {
+    let mut spheres: Vec<Vec<u8>> = world_data
+        .spheres()
+        .iter()
+        .zip(world_data.materials())
+        .zip(world_data.sphere_velocities())
+        .map(|((sphere, material), velocity)| {
+            let mut bytes = bytemuck::bytes_of(sphere).to_vec();
+            bytes.extend_from_slice(bytemuck::bytes_of(material));
+            bytes.extend_from_slice(bytemuck::bytes_of(velocity));
+            bytes
+        })
+        .collect();
+    spheres.sort();
+    spheres
+}
+
+fn check_round_trip(name: &str) {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let original = load_scene(&root.join("scenes").join(format!("{name}.ron"))).unwrap();
+    check_saved_world(name, &original);
+}
+
+fn check_saved_world(name: &str, original: &WorldData) {
+    let path =
+        std::env::temp_dir().join(format!("gpu-raytracing-{}-{name}.ron", std::process::id()));
+    original.save_scene(&path).unwrap();
+    let loaded = load_scene(&path);
+    std::fs::remove_file(&path).unwrap();
+    let loaded = loaded.unwrap();
+
+    assert_eq!(
+        bytemuck::bytes_of(original.uniform()),
+        bytemuck::bytes_of(loaded.uniform()),
+        "{name}: uniform"
+    );
+    assert_eq!(
+        sorted_spheres(original),
+        sorted_spheres(&loaded),
+        "{name}: spheres"
+    );
+    assert_eq!(
+        original
+            .environment()
+            .map(|environment| environment.pixels()),
+        loaded.environment().map(|environment| environment.pixels()),
+        "{name}: environment"
+    );
+    assert_eq!(original.textures(), loaded.textures(), "{name}: textures");
+    let lists = [
+        (
+            "triangles",
+            bytemuck::cast_slice(original.triangles()),
+            bytemuck::cast_slice(loaded.triangles()),
+        ),
+        (
+            "boxes",
+            bytemuck::cast_slice(original.boxes()),
+            bytemuck::cast_slice(loaded.boxes()),
+        ),
+        (
+            "quads",
+            bytemuck::cast_slice(original.quads()),
+            bytemuck::cast_slice(loaded.quads()),
+        ),
+        (
+            "cylinders",
+            bytemuck::cast_slice(original.cylinders()),
+            bytemuck::cast_slice(loaded.cylinders()),
+        ),
+        (
+            "planes",
+            bytemuck::cast_slice(original.planes()),
+            bytemuck::cast_slice(loaded.planes()),
+        ),
+        (
+            "transformed spheres",
+            bytemuck::cast_slice(original.transformed_spheres()),
+            bytemuck::cast_slice(loaded.transformed_spheres()),
+        ),
+        (
+            "instances",
+            bytemuck::cast_slice(original.instances()),
+            bytemuck::cast_slice(loaded.instances()),
+        ),
+        (
+            "instance groups",
+            bytemuck::cast_slice(original.instance_groups()),
+            bytemuck::cast_slice(loaded.instance_groups()),
+        ),
+        (
+            "lights",
+            bytemuck::cast_slice(original.lights()),
+            bytemuck::cast_slice(loaded.lights()),
+        ),
+    ];
+    for (kind, before, after) in lists {
+        let (before, after): (&[f32], &[f32]) = (before, after);
+        assert_eq!(before.len(), after.len(), "{name}: {kind}");
+        // Axes and normals are normalized again when loaded, which can move the last bit. The
+        // tolerance is relative, so integers read as tiny floats still have to match exactly.
+        for (a, b) in before.iter().zip(after) {
+            assert!(
+                (a - b).abs() <= 1e-6 * a.abs().max(b.abs()),
+                "{name}: {kind} differ, {a} became {b}"
+            );
+        }
+    }
+}
+
+#[test]
+fn default_round_trip() {
+    check_round_trip("default");
+}
+
+#[test]
+fn boxes_round_trip() {
+    check_round_trip("boxes");
+}
+
+#[test]
+fn cornell_round_trip() {
+    check_round_trip("cornell");
+}
+
+#[test]
+fn cylinders_round_trip() {
+    check_round_trip("cylinders");
+}
+
+#[test]
+fn lights_round_trip() {
+    check_round_trip("lights");
+}
+
+#[test]
+fn mesh_round_trip() {
+    check_round_trip("mesh");
+}
+
+#[test]
+fn environment_round_trip() {
+    check_round_trip("environment");
+}
+
+#[test]
+fn texture_round_trip() {
+    let texture =
+        std::env::temp_dir().join(format!("gpu-raytracing-{}-texture.png", std::process::id()));
+    let pixels: Vec<u8> = (0..4 * 4 * 4).map(|i| (i * 13) as u8).collect();
+    image::save_buffer(&texture, &pixels, 4, 4, image::ExtendedColorType::Rgba8).unwrap();
+    // The saved scene points to the file, it has to stay until the scene is loaded again.
+    let world_data = SceneBuilder::new()
+        .texture(&texture)
+        .sphere([0.0, 0.0, -1.0], 0.5, Material::textured(0))
+        .build()
+        .unwrap();
+    check_saved_world("texture", &world_data);
+    std::fs::remove_file(&texture).unwrap();
+}
+
+// None of the bundled scenes changes the render settings, every one of them is moved off its
+// default here.
+#[test]
+fn settings_round_trip() {
+    let mut world_data = SceneBuilder::new()
+        .camera([0.5, 1.0, 2.0], [0.0, 0.2, -1.0], 55.0)
+        .samples(9)
+        .max_depth(12)
+        .aperture(0.1)
+        .focus_dist(2.5)
+        .shutter(0.5)
+        .sphere(
+            [0.0, 0.0, -1.0],
+            0.5,
+            Material::lambertian([0.8, 0.3, 0.2, 1.0]),
+        )
+        .point_light([1.0, 2.0, 0.0], [1.0, 1.0, 1.0], 4.0)
+        // Angles whose cosines do not give them back exactly.
+        .spotlight(
+            [0.0, 3.0, 0.0],
+            [0.0, -1.0, 0.1],
+            23.7,
+            41.3,
+            [1.0, 0.9, 0.8],
+            10.0,
+        )
+        .build()
+        .unwrap();
+    world_data.set_seed(42);
+    world_data.set_antialiasing(false);
+    world_data.set_cosine_sampling(false);
+    world_data.set_firefly_clamp(20.0);
+    world_data.set_blue_noise(true);
+    world_data.set_adaptive_pixel_sampling(true);
+    world_data.set_rr_min_bounces(5);
+    world_data.set_max_diffuse_depth(4);
+    world_data.set_gamma(Some(1.8));
+    world_data.set_output_colorspace(OutputColorspace::Rec709);
+    world_data.set_tone_map(ToneMap::Reinhard);
+    world_data.set_white_point(6.0);
+    world_data.set_pixel_filter(PixelFilter::Gaussian);
+    world_data.set_exposure(-0.5);
+    world_data.set_ambient_occlusion(8, 0.3);
+    world_data.set_sky([0.1, 0.2, 0.3, 1.0], [0.4, 0.5, 0.6, 1.0]);
+    world_data.set_solid_color([0.2, 0.1, 0.05, 1.0]);
+    world_data.set_background_mode(BackgroundMode::SolidColor);
+
+    check_saved_world("settings", &world_data);
+
+    // The lights only keep the cosines, the angles must be saved as given.
+    let path =
+        std::env::temp_dir().join(format!("gpu-raytracing-{}-cones.ron", std::process::id()));
+    world_data.save_scene(&path).unwrap();
+    let saved = std::fs::read_to_string(&path);
+    std::fs::remove_file(&path).unwrap();
+    let scene: SceneDescription = ron::from_str(&saved.unwrap()).unwrap();
+    assert_eq!(scene.spotlights[0].inner, 23.7);
+    assert_eq!(scene.spotlights[0].outer, 41.3);
+}
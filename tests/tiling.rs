@@ -0,0 +1,38 @@
+// A frame traced in tiles must come out exactly like the same frame traced in one dispatch.
+// Needs a GPU, run it with `cargo test -- --ignored`.
+
+use gpu_raytracing::state::{AdapterChoice, WgpuState};
+use gpu_raytracing::world_data::load_scene;
+
+use std::path::PathBuf;
+
+// Not a multiple of the tile size, so the last row and column of tiles are partial.
+const WIDTH: u32 = 70;
+const HEIGHT: u32 = 50;
+const FRAMES: u32 = 3;
+
+fn render(tile_size: Option<u32>) -> Vec<u8> {
+    let scene = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("scenes/default.ron");
+    let mut world_data = load_scene(&scene).unwrap();
+    world_data.update_size(WIDTH, HEIGHT);
+    let new_state = WgpuState::new_headless(WIDTH, HEIGHT, &world_data, &AdapterChoice::Default);
+    let mut state = pollster::block_on(new_state)
+        .unwrap_or_else(|err| panic!("no usable GPU for the comparison: {err}"));
+    state.set_tile_size(tile_size);
+
+    for _ in 0..FRAMES {
+        world_data.next_frame();
+        state.rewrite_world_data(&world_data);
+        state.render();
+    }
+    state.read_pixels().unwrap()
+}
+
+#[test]
+#[ignore = "needs a GPU"]
+fn tiled_matches_untiled() {
+    assert!(
+        render(None) == render(Some(16)),
+        "the tiles change the image"
+    );
+}